@@ -0,0 +1,196 @@
+use std::env;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ioi::corpus;
+use ioi::generator::Rng;
+use ioi::grid::{Color, Grid, Rule};
+use ioi::{BackendKind, SolveOptions, Solver};
+
+// Solves puzzles whose cost depends on how region sizes are encoded, with every backend: area
+// puzzles, which need region sizes, and a large viewpoint puzzle, which should not pay for them.
+// The puzzles are built from fixed seeds so runs compare. Set IOI_CORPUS to a decoded.json corpus
+// to add its large area puzzles as well.
+//
+// Mean time per solve when sizes were made bounded and built only for the rules that need them.
+// The z3 runs linked a system libz3 instead of the static build, and no corpus was at hand, so
+// only the generated puzzles were measured:
+//
+//                      z3 before   z3 after   sat after
+//   area_6x6             9.09 s     0.77 s     0.22 s
+//   area_7x7            24.05 s     1.55 s     0.43 s
+//   islands_9x9         16.06 s     2.55 s     0.14 s
+//   viewpoints_17x17    20.03 s    18.39 s     0.27 s
+//
+// The 17x17 viewpoint puzzle has no area clues, so it only shows that it no longer pays for sizes
+// nobody checks; tests/backends.rs checks the two backends still agree.
+
+// Corpus puzzles with at least this many squares count as large.
+const LARGE: usize = 144;
+
+fn neighbors(size: usize, (i, j): (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+    [
+        (i.wrapping_sub(1), j),
+        (i + 1, j),
+        (i, j.wrapping_sub(1)),
+        (i, j + 1),
+    ]
+    .into_iter()
+    .filter(move |&(i, j)| i < size && j < size)
+}
+
+fn random_colors(size: usize, rng: &mut Rng) -> Vec<Vec<Color>> {
+    (0..size)
+        .map(|_| {
+            (0..size)
+                .map(|_| match rng.below(2) {
+                    0 => Color::Light,
+                    _ => Color::Dark,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// A random coloring with the size of every region written on one of its squares.
+fn area_puzzle(size: usize, seed: u64) -> Grid {
+    let colors = random_colors(size, &mut Rng::new(seed));
+    let mut grid = Grid::new(size, size);
+    grid.add_rule(Rule::RegionAreaEqualsNumber);
+    let mut seen = vec![vec![false; size]; size];
+    for i in 0..size {
+        for j in 0..size {
+            if seen[i][j] {
+                continue;
+            }
+            seen[i][j] = true;
+            let mut region = vec![(i, j)];
+            let mut next = 0;
+            while next < region.len() {
+                let square = region[next];
+                next += 1;
+                for (x, y) in neighbors(size, square) {
+                    if !seen[x][y] && colors[x][y] == colors[i][j] {
+                        seen[x][y] = true;
+                        region.push((x, y));
+                    }
+                }
+            }
+            grid.set_area_number(i, j, region.len());
+        }
+    }
+    grid
+}
+
+// Light islands of up to five squares in a dark sea, each numbered on one square.
+fn islands_puzzle(size: usize, seed: u64) -> Grid {
+    let mut rng = Rng::new(seed);
+    let mut grid = Grid::new(size, size);
+    grid.add_rule(Rule::RegionAreaEqualsNumber);
+    grid.add_rule(Rule::ExactlyOneNumberPerRegion(Color::Light));
+    let mut light = vec![vec![false; size]; size];
+    // A square can join an island if it touches no other island.
+    let free = |light: &[Vec<bool>], island: &[(usize, usize)], (i, j): (usize, usize)| {
+        !light[i][j]
+            && neighbors(size, (i, j)).all(|(x, y)| !light[x][y] || island.contains(&(x, y)))
+    };
+    for _ in 0..size * size {
+        let start = (rng.below(size), rng.below(size));
+        if !free(&light, &[], start) {
+            continue;
+        }
+        let target = 1 + rng.below(5);
+        let mut island = vec![start];
+        light[start.0][start.1] = true;
+        for _ in 0..4 * target {
+            if island.len() == target {
+                break;
+            }
+            let from = island[rng.below(island.len())];
+            let options = neighbors(size, from).collect::<Vec<_>>();
+            let square = options[rng.below(options.len())];
+            if free(&light, &island, square) {
+                light[square.0][square.1] = true;
+                island.push(square);
+            }
+        }
+        grid.set_area_number(start.0, start.1, island.len());
+    }
+    grid
+}
+
+// A random coloring with the visible count on every fourth square.
+fn viewpoint_puzzle(size: usize, seed: u64) -> Grid {
+    let colors = random_colors(size, &mut Rng::new(seed));
+    let mut grid = Grid::new(size, size);
+    grid.add_rule(Rule::VisibleCellCount);
+    for i in 0..size {
+        for j in 0..size {
+            if (i * 7 + j * 3) % 4 != 0 {
+                continue;
+            }
+            let mut count = 1;
+            for (di, dj) in [(usize::MAX, 0), (1, 0), (0, usize::MAX), (0, 1)] {
+                let (mut x, mut y) = (i.wrapping_add(di), j.wrapping_add(dj));
+                while x < size && y < size && colors[x][y] == colors[i][j] {
+                    count += 1;
+                    (x, y) = (x.wrapping_add(di), y.wrapping_add(dj));
+                }
+            }
+            grid.visible_count(i, j, count);
+        }
+    }
+    grid
+}
+
+fn corpus_puzzles() -> Vec<(String, Grid)> {
+    let Ok(path) = env::var("IOI_CORPUS") else {
+        return Vec::new();
+    };
+    let corpus = corpus::load_corpus(&path).expect("failed to load IOI_CORPUS");
+    corpus
+        .entries
+        .into_iter()
+        .filter(|entry| {
+            entry.grid.rows() * entry.grid.cols() >= LARGE
+                && entry.grid.rules().iter().any(|rule| {
+                    matches!(
+                        rule,
+                        Rule::RegionAreaEqualsNumber | Rule::RegionFixedSize(_, _)
+                    )
+                })
+        })
+        .map(|entry| (entry.pid, entry.grid))
+        .collect()
+}
+
+fn region_size(c: &mut Criterion) {
+    let mut puzzles = vec![
+        ("area_6x6".to_string(), area_puzzle(6, 1)),
+        ("area_7x7".to_string(), area_puzzle(7, 1)),
+        ("islands_9x9".to_string(), islands_puzzle(9, 1)),
+        ("viewpoints_17x17".to_string(), viewpoint_puzzle(17, 1)),
+    ];
+    puzzles.extend(corpus_puzzles());
+
+    let mut group = c.benchmark_group("region_size");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    for &backend in BackendKind::ALL {
+        let solver = Solver::new(SolveOptions {
+            timeout: 600,
+            backend,
+        });
+        for (name, grid) in &puzzles {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{:?}", backend), name),
+                grid,
+                |b, grid| b.iter(|| solver.solve(grid)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, region_size);
+criterion_main!(benches);
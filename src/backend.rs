@@ -0,0 +1,306 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::grid::{Color, Coord, GridPattern, PreparedGrid, SquareIndex};
+use crate::ir::{Constraint, ConstraintSet};
+
+// The solvers a prepared grid can be handed to. A backend encodes the grid once and then answers
+// checks under assumptions, so everything built on top works the same with any of them.
+
+// Something a check can assume: a square's color, or that a prepared rule holds. Rule literals
+// only exist in tracked sessions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Literal {
+    Color(SquareIndex, Color),
+    Rule(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckResult {
+    Sat,
+    Unsat,
+    Unknown,
+}
+
+pub trait Session {
+    // Every check enforces the shape rules, which backends add lazily.
+    fn check(&mut self, assumptions: &[Literal]) -> CheckResult;
+    // The color of each square in the last satisfying assignment, by square index.
+    fn model(&self) -> Vec<Color>;
+    // After an unsat check, assumptions that are unsatisfiable together. Not necessarily minimal.
+    fn core(&self) -> Vec<Literal>;
+    // Rules out exactly this coloring of the squares, e.g. to look for another solution.
+    fn block(&mut self, colors: &[Color]);
+    // Conflicts the solver spent on the last check.
+    fn conflicts(&self) -> u64;
+    // Encodes more constraints, which are only enforced while the returned literal is assumed.
+    // Shape rules are not encoded up front, so they cannot be added this way.
+    fn add(&mut self, constraints: &[Constraint]) -> Literal;
+}
+
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Encodes the grid and hands `f` a session on it, with `timeout` seconds for each check. When
+    // tracked, each prepared rule is only enforced while its `Literal::Rule` is assumed.
+    fn run(
+        &self,
+        grid: &PreparedGrid,
+        timeout: u32,
+        tracked: bool,
+        f: &mut dyn FnMut(&mut dyn Session),
+    );
+}
+
+// `Backend::run` for closures that return a value.
+pub fn with_session<R>(
+    backend: &dyn Backend,
+    grid: &PreparedGrid,
+    timeout: u32,
+    tracked: bool,
+    f: impl FnOnce(&mut dyn Session) -> R,
+) -> R {
+    let mut f = Some(f);
+    let mut result = None;
+    backend.run(grid, timeout, tracked, &mut |session| {
+        result = f.take().map(|f| f(session));
+    });
+    result.unwrap()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackendKind {
+    #[cfg(feature = "z3")]
+    Z3,
+    Sat,
+}
+
+impl BackendKind {
+    pub const ALL: &'static [BackendKind] = &[
+        #[cfg(feature = "z3")]
+        BackendKind::Z3,
+        BackendKind::Sat,
+    ];
+
+    pub fn backend(self) -> &'static dyn Backend {
+        match self {
+            #[cfg(feature = "z3")]
+            BackendKind::Z3 => &crate::constraints::Z3Backend,
+            BackendKind::Sat => &crate::cnf::SatBackend,
+        }
+    }
+}
+
+impl Default for BackendKind {
+    #[cfg(feature = "z3")]
+    fn default() -> Self {
+        BackendKind::Z3
+    }
+
+    #[cfg(not(feature = "z3"))]
+    fn default() -> Self {
+        BackendKind::Sat
+    }
+}
+
+// Each backend, with a timeout the small grids in tests never reach.
+#[cfg(test)]
+pub(crate) fn test_options() -> impl Iterator<Item = crate::solver::SolveOptions> {
+    BackendKind::ALL
+        .iter()
+        .map(|&backend| crate::solver::SolveOptions {
+            timeout: 10,
+            backend,
+        })
+}
+
+// Rules about the shapes of whole regions. These are too expensive to encode up front, so they are
+// checked against each model instead, and any pair of regions breaking them is ruled out before
+// asking the solver again.
+#[derive(Clone, Copy, Debug)]
+enum ShapeRule {
+    Different(Color),
+    Same(Color),
+}
+
+pub(crate) struct ShapeRules {
+    // Each rule with the index of its prepared rule.
+    rules: Vec<(ShapeRule, usize)>,
+    coords: Vec<Coord>,
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl ShapeRules {
+    pub fn new(grid: &PreparedGrid, set: &ConstraintSet) -> ShapeRules {
+        let rules = set
+            .constraints
+            .iter()
+            .filter_map(|(constraint, origin)| match constraint {
+                Constraint::RegionsHaveDifferentShapes(color) => {
+                    Some((ShapeRule::Different(*color), *origin))
+                }
+                Constraint::RegionsHaveSameShape(color) => Some((ShapeRule::Same(*color), *origin)),
+                _ => None,
+            })
+            .collect();
+        ShapeRules {
+            rules,
+            coords: set.coords.clone(),
+            neighbors: neighbors(grid),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_shape_rule(constraint: &Constraint) -> bool {
+        matches!(
+            constraint,
+            Constraint::RegionsHaveDifferentShapes(_) | Constraint::RegionsHaveSameShape(_)
+        )
+    }
+
+    // Splits the squares into regions of the same color.
+    fn regions(&self, colors: &[Color]) -> Vec<(Color, Vec<usize>)> {
+        let mut region_of = vec![None; colors.len()];
+        let mut regions = Vec::new();
+        for start in 0..colors.len() {
+            if region_of[start].is_some() {
+                continue;
+            }
+            region_of[start] = Some(regions.len());
+            let mut region = vec![start];
+            let mut next = 0;
+            while next < region.len() {
+                let current = region[next];
+                next += 1;
+                for &neighbor in &self.neighbors[current] {
+                    if region_of[neighbor].is_none() && colors[neighbor] == colors[start] {
+                        region_of[neighbor] = Some(regions.len());
+                        region.push(neighbor);
+                    }
+                }
+            }
+            regions.push((colors[start], region));
+        }
+        regions
+    }
+
+    fn region_pattern(&self, color: Color, region: &[usize]) -> GridPattern {
+        GridPattern {
+            pattern: region
+                .iter()
+                .map(|&index| (self.coords[index], color))
+                .collect(),
+        }
+    }
+
+    // The colors that make `a` and `b` whole regions of the given color: all of their squares
+    // have the color and all squares bordering them have the opposite color.
+    fn both_regions(&self, color: Color, a: &[usize], b: &[usize]) -> Vec<(usize, Color)> {
+        let mut terms = Vec::new();
+        for region in [a, b] {
+            let mut bordering = Vec::new();
+            for &index in region {
+                terms.push((index, color));
+                for &neighbor in &self.neighbors[index] {
+                    if !region.contains(&neighbor) && !bordering.contains(&neighbor) {
+                        bordering.push(neighbor);
+                    }
+                }
+            }
+            terms.extend(bordering.into_iter().map(|index| (index, color.opposite())));
+        }
+        terms
+    }
+
+    // The coloring's violations of the shape rules, each as the index of its prepared rule and
+    // square colors that must not all hold at once. Only the rules `enforced` accepts by index
+    // are checked, since a tracked shape rule only holds while its literal is assumed.
+    pub fn violations(
+        &self,
+        colors: &[Color],
+        enforced: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, Vec<(usize, Color)>)> {
+        let regions = self.regions(colors);
+        let mut violations = Vec::new();
+        for &(rule, origin) in &self.rules {
+            if !enforced(origin) {
+                continue;
+            }
+            match rule {
+                ShapeRule::Different(color) => {
+                    let mut by_size: HashMap<usize, Vec<&Vec<usize>>> = HashMap::new();
+                    for (region_color, region) in &regions {
+                        if *region_color == color {
+                            by_size.entry(region.len()).or_default().push(region);
+                        }
+                    }
+                    for same_size in by_size.values() {
+                        for (n, a) in same_size.iter().enumerate() {
+                            let a_pattern = self.region_pattern(color, a);
+                            for b in &same_size[n + 1..] {
+                                if a_pattern.is_congruent(&self.region_pattern(color, b)) {
+                                    violations.push((origin, self.both_regions(color, a, b)));
+                                }
+                            }
+                        }
+                    }
+                }
+                ShapeRule::Same(color) => {
+                    let mut of_color = regions
+                        .iter()
+                        .filter(|(region_color, _)| *region_color == color)
+                        .map(|(_, region)| region);
+                    if let Some(first) = of_color.next() {
+                        let first_pattern = self.region_pattern(color, first);
+                        for other in of_color {
+                            if !first_pattern.is_congruent(&self.region_pattern(color, other)) {
+                                violations.push((origin, self.both_regions(color, first, other)));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        violations
+    }
+}
+
+// The existing orthogonal neighbors of each square, by square index.
+pub(crate) fn neighbors(grid: &PreparedGrid) -> Vec<Vec<usize>> {
+    grid.squares
+        .iter()
+        .map(|square| {
+            [square.left, square.right, square.above, square.below]
+                .into_iter()
+                .flatten()
+                .map(|index| index.0)
+                .collect()
+        })
+        .collect()
+}
+
+// Squares within `limit` steps of `start`, by distance.
+pub(crate) fn distances(
+    neighbors: &[Vec<usize>],
+    start: usize,
+    limit: usize,
+) -> Vec<Option<usize>> {
+    let mut distances = vec![None; neighbors.len()];
+    distances[start] = Some(0);
+    let mut queue = VecDeque::from([start]);
+    while let Some(current) = queue.pop_front() {
+        let distance = distances[current].unwrap();
+        if distance == limit {
+            continue;
+        }
+        for &neighbor in &neighbors[current] {
+            if distances[neighbor].is_none() {
+                distances[neighbor] = Some(distance + 1);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    distances
+}
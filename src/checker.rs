@@ -0,0 +1,558 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::grid::{Color, Coord, Direction, Grid, GridPattern, Rule};
+
+// Checks a colored grid against its rules directly, without z3. Regions are found by flood fill
+// over the existing squares, so holes split them just as they do in the encoding.
+
+#[derive(Clone, Debug)]
+pub struct RuleCheck {
+    pub rule: Rule,
+    // The squares breaking the rule; empty if it holds.
+    pub violations: Vec<Coord>,
+}
+
+impl RuleCheck {
+    pub fn holds(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CheckReport {
+    // Squares with no color, which no rule is checked for.
+    pub uncolored: Vec<Coord>,
+    // Merged squares colored differently from the square they are merged with.
+    pub broken_merges: Vec<Coord>,
+    pub rules: Vec<RuleCheck>,
+}
+
+impl CheckReport {
+    pub fn is_valid(&self) -> bool {
+        self.uncolored.is_empty()
+            && self.broken_merges.is_empty()
+            && self.rules.iter().all(RuleCheck::holds)
+    }
+}
+
+pub(crate) struct Regions {
+    region_of: HashMap<Coord, usize>,
+    regions: Vec<(Color, Vec<Coord>)>,
+}
+
+impl Regions {
+    pub(crate) fn new(grid: &Grid) -> Regions {
+        let size = grid_size(grid);
+        let mut region_of = HashMap::new();
+        let mut regions = Vec::new();
+        for (start, square) in grid.squares() {
+            let Some(color) = square.color else {
+                continue;
+            };
+            if region_of.contains_key(&start) {
+                continue;
+            }
+            region_of.insert(start, regions.len());
+            let mut region = vec![start];
+            let mut next = 0;
+            while next < region.len() {
+                let current = region[next];
+                next += 1;
+                for direction in Direction::ALL {
+                    let Some(neighbor) = current.neighbor(direction, size) else {
+                        continue;
+                    };
+                    let same_color = grid
+                        .square(neighbor)
+                        .is_some_and(|square| square.color == Some(color));
+                    if same_color && !region_of.contains_key(&neighbor) {
+                        region_of.insert(neighbor, regions.len());
+                        region.push(neighbor);
+                    }
+                }
+            }
+            regions.push((color, region));
+        }
+        Regions { region_of, regions }
+    }
+
+    fn of_color(&self, color: Color) -> impl Iterator<Item = &Vec<Coord>> {
+        self.regions
+            .iter()
+            .filter(move |(region_color, _)| *region_color == color)
+            .map(|(_, region)| region)
+    }
+
+    pub(crate) fn size_at(&self, coord: Coord) -> usize {
+        self.region_of
+            .get(&coord)
+            .map_or(0, |&region| self.regions[region].1.len())
+    }
+
+    fn same_region(&self, a: Coord, b: Coord) -> bool {
+        match (self.region_of.get(&a), self.region_of.get(&b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+fn grid_size(grid: &Grid) -> Coord {
+    Coord {
+        i: grid.rows() as isize,
+        j: grid.cols() as isize,
+    }
+}
+
+pub(crate) fn color_at(grid: &Grid, coord: Coord) -> Option<Color> {
+    grid.square(coord).and_then(|square| square.color)
+}
+
+// The squares from `coord` in a direction, up to the edge or the first hole.
+pub(crate) fn ray(grid: &Grid, coord: Coord, direction: Direction) -> Vec<Coord> {
+    let size = grid_size(grid);
+    let mut squares = Vec::new();
+    let mut current = coord.neighbor(direction, size);
+    while let Some(coord) = current.filter(|&coord| grid.square(coord).is_some()) {
+        squares.push(coord);
+        current = coord.neighbor(direction, size);
+    }
+    squares
+}
+
+// The squares a dart at `coord` points at. Unlike other rays, darts see past holes.
+pub(crate) fn dart_squares(grid: &Grid, coord: Coord, direction: Direction) -> Vec<Coord> {
+    let size = grid_size(grid);
+    let mut squares = Vec::new();
+    let mut current = coord.neighbor(direction, size);
+    while let Some(coord) = current {
+        if grid.square(coord).is_some() {
+            squares.push(coord);
+        }
+        current = coord.neighbor(direction, size);
+    }
+    squares
+}
+
+fn number_matches(actual: usize, number: usize, off_by_one: bool) -> bool {
+    if off_by_one {
+        actual + 1 == number || actual == number + 1
+    } else {
+        actual == number
+    }
+}
+
+pub fn check_grid(grid: &Grid) -> CheckReport {
+    let regions = Regions::new(grid);
+    let off_by_one = grid
+        .rules()
+        .iter()
+        .any(|rule| matches!(rule, Rule::NumbersAreOffByOne));
+    let size = grid_size(grid);
+
+    let mut uncolored = Vec::new();
+    let mut broken_merges = Vec::new();
+    for (coord, square) in grid.squares() {
+        let Some(color) = square.color else {
+            uncolored.push(coord);
+            continue;
+        };
+        let merged = [
+            (square.merge_with_right, coord.right(size)),
+            (square.merge_with_bottom, coord.below(size)),
+        ];
+        for (merge, other) in merged {
+            if let Some(other) = other.filter(|_| merge) {
+                if color_at(grid, other).is_some_and(|other| other != color) {
+                    broken_merges.push(coord);
+                }
+            }
+        }
+    }
+
+    let rules = grid
+        .rules()
+        .iter()
+        .map(|rule| RuleCheck {
+            rule: rule.clone(),
+            violations: check_rule(grid, &regions, rule, off_by_one),
+        })
+        .collect();
+    CheckReport {
+        uncolored,
+        broken_merges,
+        rules,
+    }
+}
+
+fn check_rule(grid: &Grid, regions: &Regions, rule: &Rule, off_by_one: bool) -> Vec<Coord> {
+    let mut violations = Vec::new();
+    match rule {
+        Rule::BanPattern(pattern) => {
+            for pattern in pattern.all_rotations_and_reflections() {
+                for i in 0..grid.rows() as isize {
+                    for j in 0..grid.cols() as isize {
+                        let offset = pattern.offset(Coord { i, j });
+                        let found = offset
+                            .pattern
+                            .iter()
+                            .all(|&(coord, color)| color_at(grid, coord) == Some(color));
+                        if found {
+                            violations.extend(offset.pattern.iter().map(|&(coord, _)| coord));
+                        }
+                    }
+                }
+            }
+        }
+        Rule::ConnectAll(color) => {
+            let mut of_color = regions.of_color(*color);
+            if of_color.next().is_some() {
+                violations.extend(of_color.flatten());
+            }
+        }
+        Rule::RegionAreaEqualsNumber => {
+            for (coord, square) in grid.squares() {
+                if let Some(number) = square.area_number {
+                    if !number_matches(regions.size_at(coord), number, off_by_one) {
+                        violations.push(coord);
+                    }
+                }
+            }
+        }
+        Rule::RegionFixedSize(color, size) => {
+            for region in regions.of_color(*color) {
+                if region.len() != *size {
+                    violations.extend(region);
+                }
+            }
+        }
+        Rule::ExactlyOneNumberPerRegion(color) => {
+            // As in the encoding, no region of either color may hold two numbers.
+            for (region_color, region) in &regions.regions {
+                let numbers = region
+                    .iter()
+                    .filter(|&&coord| grid.square(coord).unwrap().area_number.is_some())
+                    .count();
+                if numbers > 1 || (numbers == 0 && region_color == color) {
+                    violations.extend(region);
+                }
+            }
+        }
+        Rule::VisibleCellCount => {
+            for (coord, square) in grid.squares() {
+                if let Some(number) = square.visible_count {
+                    let color = square.color;
+                    let visible = 1 + Direction::ALL
+                        .iter()
+                        .map(|&direction| {
+                            ray(grid, coord, direction)
+                                .into_iter()
+                                .take_while(|&other| color_at(grid, other) == color)
+                                .count()
+                        })
+                        .sum::<usize>();
+                    if !number_matches(visible, number, off_by_one) {
+                        violations.push(coord);
+                    }
+                }
+            }
+        }
+        Rule::RegionsHaveDifferentShapes(color) => {
+            let shapes = regions
+                .of_color(*color)
+                .map(|region| (region, shape(*color, region)))
+                .collect::<Vec<_>>();
+            for (n, (a, a_shape)) in shapes.iter().enumerate() {
+                let congruent = shapes
+                    .iter()
+                    .enumerate()
+                    .any(|(m, (_, b_shape))| m != n && a_shape.is_congruent(b_shape));
+                if congruent {
+                    violations.extend(a.iter());
+                }
+            }
+        }
+        Rule::RegionsHaveSameShape(color) => {
+            let mut of_color = regions.of_color(*color);
+            if let Some(first) = of_color.next() {
+                let first_shape = shape(*color, first);
+                for region in of_color {
+                    if !first_shape.is_congruent(&shape(*color, region)) {
+                        violations.extend(region);
+                    }
+                }
+            }
+        }
+        Rule::NumbersAreOffByOne => {}
+        Rule::DartNumbers => {
+            for (coord, square) in grid.squares() {
+                if let (Some((direction, number)), Some(color)) = (square.dart_number, square.color)
+                {
+                    let count = dart_squares(grid, coord, direction)
+                        .into_iter()
+                        .filter(|&other| color_at(grid, other) == Some(color.opposite()))
+                        .count();
+                    if count != number {
+                        violations.push(coord);
+                    }
+                }
+            }
+        }
+        Rule::Galaxy | Rule::Lotus => {
+            for symmetry in grid.symmetries(rule) {
+                let Some(&anchor) = symmetry.anchors.first() else {
+                    continue;
+                };
+                for &other in &symmetry.anchors[1..] {
+                    if !regions.same_region(anchor, other) {
+                        violations.push(other);
+                    }
+                }
+                for (coord, image) in symmetry.images {
+                    let symmetric = image.is_some_and(|image| regions.same_region(anchor, image));
+                    if regions.same_region(anchor, coord) && !symmetric {
+                        violations.push(coord);
+                    }
+                }
+            }
+        }
+        Rule::Letters => {
+            let mut letters = BTreeMap::<usize, Vec<Coord>>::new();
+            for (coord, square) in grid.squares() {
+                if let Some(letter) = square.letter {
+                    letters.entry(letter).or_default().push(coord);
+                }
+            }
+            let groups = letters.values().collect::<Vec<_>>();
+            for (n, group) in groups.iter().enumerate() {
+                let split = group
+                    .iter()
+                    .any(|&coord| !regions.same_region(group[0], coord));
+                let shared = groups
+                    .iter()
+                    .enumerate()
+                    .any(|(m, other)| m != n && regions.same_region(group[0], other[0]));
+                if split || shared {
+                    violations.extend(group.iter());
+                }
+            }
+        }
+        Rule::Myopia => {
+            for (coord, square) in grid.squares() {
+                let Some(mask) = square.myopia else {
+                    continue;
+                };
+                // The distance to the first square of the opposite color in each direction.
+                let distances = Direction::ALL.map(|direction| {
+                    ray(grid, coord, direction)
+                        .into_iter()
+                        .position(|other| {
+                            color_at(grid, other).is_some_and(|color| Some(color) != square.color)
+                        })
+                        .map(|distance| (direction, distance))
+                });
+                let nearest = distances
+                    .iter()
+                    .flatten()
+                    .map(|&(_, distance)| distance)
+                    .min();
+                let pointed = distances
+                    .iter()
+                    .flatten()
+                    .filter(|&&(_, distance)| Some(distance) == nearest)
+                    .fold(0, |pointed, &(direction, _)| pointed | direction.bit());
+                if pointed != mask {
+                    violations.push(coord);
+                }
+            }
+        }
+    }
+    violations.sort();
+    violations.dedup();
+    violations
+}
+
+fn shape(color: Color, region: &[Coord]) -> GridPattern {
+    GridPattern {
+        pattern: region.iter().map(|&coord| (coord, color)).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(i: isize, j: isize) -> Coord {
+        Coord { i, j }
+    }
+
+    // '.' is light, '#' dark, '?' uncolored and ' ' a hole.
+    fn colored(rows: &[&str], rules: Vec<Rule>) -> Grid {
+        let mut grid = Grid::new(rows.len(), rows[0].len());
+        for (i, row) in rows.iter().enumerate() {
+            for (j, c) in row.chars().enumerate() {
+                match c {
+                    '.' => grid.color_light(i, j),
+                    '#' => grid.color_dark(i, j),
+                    '?' => {}
+                    _ => grid.remove_square(i, j),
+                }
+            }
+        }
+        for rule in rules {
+            grid.add_rule(rule);
+        }
+        grid
+    }
+
+    fn violations(grid: &Grid) -> Vec<Coord> {
+        let report = check_grid(grid);
+        assert!(report.uncolored.is_empty());
+        assert!(report.broken_merges.is_empty());
+        let mut violations = report
+            .rules
+            .into_iter()
+            .flat_map(|check| check.violations)
+            .collect::<Vec<_>>();
+        violations.sort();
+        violations
+    }
+
+    #[test]
+    fn area_numbers() {
+        let rows = ["..#", "#.#", "###"];
+        let mut grid = colored(&rows, vec![Rule::RegionAreaEqualsNumber]);
+        grid.set_area_number(0, 0, 3);
+        grid.set_area_number(2, 2, 6);
+        assert_eq!(violations(&grid), []);
+        grid.set_area_number(0, 0, 4);
+        assert_eq!(violations(&grid), [at(0, 0)]);
+
+        grid.add_rule(Rule::NumbersAreOffByOne);
+        grid.set_area_number(2, 2, 7);
+        assert_eq!(violations(&grid), []);
+        grid.set_area_number(0, 0, 3);
+        assert_eq!(violations(&grid), [at(0, 0)]);
+
+        // Holes split regions.
+        let mut grid = colored(&[". ."], vec![Rule::RegionAreaEqualsNumber]);
+        grid.set_area_number(0, 0, 1);
+        assert_eq!(violations(&grid), []);
+        grid.set_area_number(0, 2, 2);
+        assert_eq!(violations(&grid), [at(0, 2)]);
+    }
+
+    #[test]
+    fn darts_count_past_holes() {
+        let mut grid = colored(&["# .#."], vec![Rule::DartNumbers]);
+        grid.dart_number(0, 0, Direction::Right, 2, Color::Dark);
+        assert_eq!(violations(&grid), []);
+        grid.dart_number(0, 0, Direction::Right, 1, Color::Dark);
+        assert_eq!(violations(&grid), [at(0, 0)]);
+    }
+
+    #[test]
+    fn visible_counts_stop_at_holes() {
+        let mut grid = colored(&[".. ..", "#.#.#"], vec![Rule::VisibleCellCount]);
+        grid.visible_count(0, 1, 3);
+        assert_eq!(violations(&grid), []);
+        grid.visible_count(0, 3, 4);
+        assert_eq!(violations(&grid), [at(0, 3)]);
+    }
+
+    #[test]
+    fn different_shapes() {
+        let rule = Rule::RegionsHaveDifferentShapes(Color::Dark);
+        let grid = colored(&["#.#", "#..", "..."], vec![rule.clone()]);
+        assert_eq!(violations(&grid), []);
+        // Two dominoes, one turned.
+        let grid = colored(&["#..", "#..", ".##"], vec![rule]);
+        assert_eq!(violations(&grid), [at(0, 0), at(1, 0), at(2, 1), at(2, 2)]);
+    }
+
+    #[test]
+    fn same_shapes() {
+        let rule = Rule::RegionsHaveSameShape(Color::Dark);
+        let grid = colored(&["#..", "#..", ".##"], vec![rule.clone()]);
+        assert_eq!(violations(&grid), []);
+        let grid = colored(&["#.#", "#..", "..."], vec![rule]);
+        assert_eq!(violations(&grid), [at(0, 2)]);
+    }
+
+    #[test]
+    fn ban_patterns() {
+        let dark = |i, j| (at(i, j), Color::Dark);
+        let rule = Rule::BanPattern(GridPattern {
+            pattern: vec![dark(0, 0), dark(0, 1), dark(1, 0), dark(1, 1)],
+        });
+        let grid = colored(&[".##", ".#.", "..."], vec![rule.clone()]);
+        assert_eq!(violations(&grid), []);
+        let grid = colored(&[".##", ".##", "..."], vec![rule]);
+        assert_eq!(violations(&grid), [at(0, 1), at(0, 2), at(1, 1), at(1, 2)]);
+    }
+
+    #[test]
+    fn connectivity() {
+        let rule = Rule::ConnectAll(Color::Light);
+        let grid = colored(&["...", "##.", "..."], vec![rule.clone()]);
+        assert_eq!(violations(&grid), []);
+        let grid = colored(&["...", "###", ".#."], vec![rule.clone()]);
+        assert_eq!(violations(&grid), [at(2, 0), at(2, 2)]);
+        // A hole disconnects as well.
+        let grid = colored(&[". ."], vec![rule]);
+        assert_eq!(violations(&grid), [at(0, 2)]);
+    }
+
+    #[test]
+    fn fixed_sizes_and_one_number_per_region() {
+        let rule = Rule::RegionFixedSize(Color::Dark, 2);
+        let grid = colored(&["#.#", "#.#", "..."], vec![rule.clone()]);
+        assert_eq!(violations(&grid), []);
+        let grid = colored(&["#.#", "#..", "..."], vec![rule]);
+        assert_eq!(violations(&grid), [at(0, 2)]);
+
+        let mut grid = colored(
+            &["...", "###", "..."],
+            vec![Rule::ExactlyOneNumberPerRegion(Color::Light)],
+        );
+        grid.set_area_number(0, 0, 3);
+        grid.set_area_number(2, 1, 3);
+        assert_eq!(violations(&grid), []);
+        let mut grid = colored(
+            &["...", "###", "..."],
+            vec![Rule::ExactlyOneNumberPerRegion(Color::Light)],
+        );
+        grid.set_area_number(0, 0, 3);
+        grid.set_area_number(0, 2, 3);
+        assert_eq!(
+            violations(&grid),
+            [at(0, 0), at(0, 1), at(0, 2), at(2, 0), at(2, 1), at(2, 2)]
+        );
+    }
+
+    #[test]
+    fn letters_and_myopia() {
+        let mut grid = colored(&["..#", "..."], vec![Rule::Letters]);
+        grid.set_letter(0, 0, 0);
+        grid.set_letter(1, 2, 0);
+        assert_eq!(violations(&grid), []);
+        grid.set_letter(1, 0, 1);
+        assert_eq!(violations(&grid), [at(0, 0), at(1, 0), at(1, 2)]);
+
+        let mut grid = colored(&["..#", "..."], vec![Rule::Myopia]);
+        grid.set_myopia(0, 0, &[Direction::Right]);
+        assert_eq!(violations(&grid), []);
+        grid.set_myopia(0, 0, &[Direction::Down]);
+        assert_eq!(violations(&grid), [at(0, 0)]);
+    }
+
+    #[test]
+    fn uncolored_squares_and_broken_merges() {
+        let mut grid = colored(&[".#", ".?"], vec![]);
+        grid.join_right(0, 0);
+        let report = check_grid(&grid);
+        assert_eq!(report.uncolored, [at(1, 1)]);
+        assert_eq!(report.broken_merges, [at(0, 0)]);
+        assert!(!report.is_valid());
+    }
+}
@@ -0,0 +1,877 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::backend::{distances, neighbors, Backend, CheckResult, Literal, Session, ShapeRules};
+use crate::grid::{Color, Direction, PreparedGrid};
+use crate::ir::{Constraint, ConstraintSet, Size};
+use crate::sat::{Lit, SatSolver};
+
+// Encodes a prepared grid as clauses for the embedded SAT solver. Each square has a color
+// variable, true for light. Regions are only encoded when a rule needs to tell them apart: every
+// square then picks a leader among the squares up to its own index, with neighbors of the same
+// color picking the same one, and an order-encoded rank that some neighbor of the same color
+// undercuts unless the square leads itself. Counts are unary totalizers, bounded by the largest
+// number they are compared with.
+
+pub struct SatBackend;
+
+impl Backend for SatBackend {
+    fn name(&self) -> &'static str {
+        "sat"
+    }
+
+    fn run(
+        &self,
+        grid: &PreparedGrid,
+        timeout: u32,
+        tracked: bool,
+        f: &mut dyn FnMut(&mut dyn Session),
+    ) {
+        let mut set = ConstraintSet::new(grid);
+        set.simplify();
+        let mut encoding = Encoding::new(grid);
+        let rules = match tracked {
+            true => grid.rules.iter().map(|_| encoding.new_lit()).collect(),
+            false => Vec::new(),
+        };
+        for (constraint, origin) in &set.constraints {
+            encoding.guard = rules.get(*origin).copied();
+            encoding.add_constraint(constraint);
+        }
+        encoding.guard = None;
+        f(&mut SatSession {
+            shapes: ShapeRules::new(grid, &set),
+            encoding,
+            rules,
+            tracked,
+            timeout: Duration::from_secs(timeout as u64),
+            assumptions: Vec::new(),
+            colors: Vec::new(),
+            conflicts: 0,
+        });
+    }
+}
+
+struct SatSession<'a> {
+    encoding: Encoding<'a>,
+    shapes: ShapeRules,
+    // One literal per prepared rule when tracked, followed by one per `add`.
+    rules: Vec<Lit>,
+    tracked: bool,
+    timeout: Duration,
+    // The last check's assumptions, to map its unsat core back.
+    assumptions: Vec<(Literal, Lit)>,
+    colors: Vec<Color>,
+    conflicts: u64,
+}
+
+impl Session for SatSession<'_> {
+    fn check(&mut self, assumptions: &[Literal]) -> CheckResult {
+        self.assumptions = assumptions
+            .iter()
+            .map(|&literal| {
+                let lit = match literal {
+                    Literal::Color(index, color) => self.encoding.color(index.0, color),
+                    Literal::Rule(rule) => self.rules[rule],
+                };
+                (literal, lit)
+            })
+            .collect();
+        let lits = self
+            .assumptions
+            .iter()
+            .map(|&(_, lit)| lit)
+            .collect::<Vec<_>>();
+        let solver = &mut self.encoding.solver;
+        solver.set_deadline(Some(Instant::now() + self.timeout));
+        let before = solver.conflicts();
+        let result = loop {
+            let result = self.encoding.solver.solve(&lits);
+            if result != CheckResult::Sat {
+                break result;
+            }
+            self.colors = self
+                .encoding
+                .colors
+                .iter()
+                .map(|lit| {
+                    if self.encoding.solver.model_value(lit.var()) {
+                        Color::Light
+                    } else {
+                        Color::Dark
+                    }
+                })
+                .collect();
+            if self.shapes.is_empty() {
+                break result;
+            }
+            let violations = self.shapes.violations(&self.colors, |origin| {
+                !self.tracked
+                    || self
+                        .assumptions
+                        .iter()
+                        .any(|&(literal, _)| literal == Literal::Rule(origin))
+            });
+            if violations.is_empty() {
+                break result;
+            }
+            for (origin, terms) in violations {
+                let mut clause = terms
+                    .into_iter()
+                    .map(|(index, color)| !self.encoding.color(index, color))
+                    .collect::<Vec<_>>();
+                if self.tracked {
+                    clause.push(!self.rules[origin]);
+                }
+                self.encoding.solver.add_clause(&clause);
+            }
+        };
+        self.conflicts = self.encoding.solver.conflicts() - before;
+        result
+    }
+
+    fn model(&self) -> Vec<Color> {
+        self.colors.clone()
+    }
+
+    fn core(&self) -> Vec<Literal> {
+        self.encoding
+            .solver
+            .core()
+            .iter()
+            .filter_map(|lit| {
+                self.assumptions
+                    .iter()
+                    .find(|(_, other)| other == lit)
+                    .map(|&(literal, _)| literal)
+            })
+            .collect()
+    }
+
+    fn block(&mut self, colors: &[Color]) {
+        let clause = colors
+            .iter()
+            .enumerate()
+            .map(|(index, color)| self.encoding.color(index, color.opposite()))
+            .collect::<Vec<_>>();
+        self.encoding.solver.add_clause(&clause);
+    }
+
+    fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    fn add(&mut self, constraints: &[Constraint]) -> Literal {
+        debug_assert!(!constraints.iter().any(ShapeRules::is_shape_rule));
+        let literal = self.encoding.new_lit();
+        self.encoding.guard = Some(literal);
+        for constraint in constraints {
+            self.encoding.add_constraint(constraint);
+        }
+        self.encoding.guard = None;
+        self.rules.push(literal);
+        Literal::Rule(self.rules.len() - 1)
+    }
+}
+
+// The leader and rank variables of every square.
+struct Regions {
+    // For each square, a literal per square that may lead its region: those up to its own index
+    // in the same part of the grid.
+    leaders: Vec<Vec<Option<Lit>>>,
+}
+
+impl Regions {
+    fn leader(&self, index: usize, leader: usize) -> Option<Lit> {
+        self.leaders[index].get(leader).copied().flatten()
+    }
+}
+
+struct Encoding<'a> {
+    grid: &'a PreparedGrid,
+    solver: SatSolver,
+    colors: Vec<Lit>,
+    neighbors: Vec<Vec<usize>>,
+    always: Lit,
+    // The literal the rule being encoded is enforced under, when tracked.
+    guard: Option<Lit>,
+    // Whether the squares of an edge have the same color, by the lower index first.
+    same: HashMap<(usize, usize), Lit>,
+    // For each square and direction, whether at least 1, 2, ... squares that way are visible.
+    visible: HashMap<(usize, Direction), Vec<Lit>>,
+    regions: Option<Regions>,
+}
+
+impl<'a> Encoding<'a> {
+    fn new(grid: &'a PreparedGrid) -> Encoding<'a> {
+        let mut solver = SatSolver::new();
+        let always = Lit::new(solver.new_var(), true);
+        solver.add_clause(&[always]);
+        let colors = grid
+            .squares
+            .iter()
+            .map(|_| Lit::new(solver.new_var(), true))
+            .collect();
+        Encoding {
+            grid,
+            solver,
+            colors,
+            neighbors: neighbors(grid),
+            always,
+            guard: None,
+            same: HashMap::new(),
+            visible: HashMap::new(),
+            regions: None,
+        }
+    }
+
+    fn new_lit(&mut self) -> Lit {
+        Lit::new(self.solver.new_var(), true)
+    }
+
+    fn constant(&self, value: bool) -> Lit {
+        if value {
+            self.always
+        } else {
+            !self.always
+        }
+    }
+
+    fn color(&self, index: usize, color: Color) -> Lit {
+        match color {
+            Color::Light => self.colors[index],
+            Color::Dark => !self.colors[index],
+        }
+    }
+
+    // A clause that only defines auxiliary variables, so it holds whichever rules are enforced.
+    fn define(&mut self, clause: &[Lit]) {
+        self.solver.add_clause(clause);
+    }
+
+    // A clause of the rule being encoded.
+    fn require(&mut self, clause: &[Lit]) {
+        match self.guard {
+            Some(guard) => {
+                let mut clause = clause.to_vec();
+                clause.push(!guard);
+                self.solver.add_clause(&clause);
+            }
+            None => self.solver.add_clause(clause),
+        }
+    }
+
+    fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let and = self.new_lit();
+        self.define(&[!and, a]);
+        self.define(&[!and, b]);
+        self.define(&[and, !a, !b]);
+        and
+    }
+
+    fn or(&mut self, terms: &[Lit]) -> Lit {
+        let or = self.new_lit();
+        let mut clause = vec![!or];
+        clause.extend(terms);
+        self.define(&clause);
+        for &term in terms {
+            self.define(&[or, !term]);
+        }
+        or
+    }
+
+    fn same(&mut self, a: usize, b: usize) -> Lit {
+        let key = (a.min(b), a.max(b));
+        if let Some(&same) = self.same.get(&key) {
+            return same;
+        }
+        let same = self.new_lit();
+        let (a, b) = (self.colors[a], self.colors[b]);
+        self.define(&[!same, !a, b]);
+        self.define(&[!same, a, !b]);
+        self.define(&[same, a, b]);
+        self.define(&[same, !a, !b]);
+        self.same.insert(key, same);
+        same
+    }
+
+    // Unary outputs of a totalizer: the k-th is true exactly when at least k + 1 of the inputs
+    // are, up to `bound` outputs.
+    fn count(&mut self, inputs: &[Lit], bound: usize) -> Vec<Lit> {
+        if inputs.len() <= 1 {
+            return inputs.to_vec();
+        }
+        let (left, right) = inputs.split_at(inputs.len() / 2);
+        let left = self.count(left, bound);
+        let right = self.count(right, bound);
+        let len = (left.len() + right.len()).min(bound);
+        let outputs = (0..len).map(|_| self.new_lit()).collect::<Vec<_>>();
+        for a in 0..=left.len() {
+            for b in 0..=right.len() {
+                if a + b > 0 {
+                    let mut clause = vec![outputs[(a + b).min(len) - 1]];
+                    if a > 0 {
+                        clause.push(!left[a - 1]);
+                    }
+                    if b > 0 {
+                        clause.push(!right[b - 1]);
+                    }
+                    self.define(&clause);
+                }
+                if a + b < len {
+                    let mut clause = vec![!outputs[a + b]];
+                    if a < left.len() {
+                        clause.push(left[a]);
+                    }
+                    if b < right.len() {
+                        clause.push(right[b]);
+                    }
+                    self.define(&clause);
+                }
+            }
+        }
+        outputs
+    }
+
+    // True exactly when the count is `n`, given outputs of `count` with a bound above `n`.
+    fn count_is(&mut self, outputs: &[Lit], n: usize) -> Lit {
+        let (yes, no) = (self.constant(true), self.constant(false));
+        let at_least = |n: usize| match n {
+            0 => yes,
+            n => outputs.get(n - 1).copied().unwrap_or(no),
+        };
+        let (low, high) = (at_least(n), at_least(n + 1));
+        self.and(low, !high)
+    }
+
+    fn at_most_one(&mut self, lits: &[Lit]) {
+        if lits.len() <= 4 {
+            for (n, &a) in lits.iter().enumerate() {
+                for &b in &lits[n + 1..] {
+                    self.require(&[!a, !b]);
+                }
+            }
+            return;
+        }
+        // Sequential encoding: `seen[n]` holds once one of the first n + 1 literals does.
+        let seen = (0..lits.len() - 1)
+            .map(|_| self.new_lit())
+            .collect::<Vec<_>>();
+        for n in 0..lits.len() - 1 {
+            self.require(&[!lits[n], seen[n]]);
+            self.require(&[!seen[n], !lits[n + 1]]);
+            if n + 1 < seen.len() {
+                self.require(&[!seen[n], seen[n + 1]]);
+            }
+        }
+    }
+
+    fn regions(&mut self) -> &Regions {
+        if self.regions.is_none() {
+            self.regions = Some(self.encode_regions());
+        }
+        self.regions.as_ref().unwrap()
+    }
+
+    fn encode_regions(&mut self) -> Regions {
+        let n = self.colors.len();
+        // Squares can only share a region within the same part of the grid.
+        let mut parts = vec![usize::MAX; n];
+        let mut part_sizes = Vec::new();
+        for start in 0..n {
+            if parts[start] == usize::MAX {
+                let distances = distances(&self.neighbors, start, n);
+                let part = distances.iter().flatten().count();
+                for (index, distance) in distances.into_iter().enumerate() {
+                    if distance.is_some() {
+                        parts[index] = part_sizes.len();
+                    }
+                }
+                part_sizes.push(part);
+            }
+        }
+
+        let mut leaders = Vec::new();
+        for index in 0..n {
+            let candidates = (0..=index)
+                .map(|leader| (parts[leader] == parts[index]).then(|| self.new_lit()))
+                .collect::<Vec<_>>();
+            let some = candidates.iter().flatten().copied().collect::<Vec<_>>();
+            self.define(&some);
+            // A leader leads only itself.
+            let own = candidates[index].unwrap();
+            for &other in &some[..some.len() - 1] {
+                self.define(&[!own, !other]);
+            }
+            leaders.push(candidates);
+        }
+        let regions = Regions { leaders };
+
+        for a in 0..n {
+            for b in self.neighbors[a].clone() {
+                if b < a {
+                    continue;
+                }
+                let same = self.same(a, b);
+                for leader in 0..=b {
+                    let Some(leader_b) = regions.leader(b, leader) else {
+                        continue;
+                    };
+                    match regions.leader(a, leader) {
+                        Some(leader_a) => {
+                            self.define(&[!same, !leader_a, leader_b]);
+                            self.define(&[!same, !leader_b, leader_a]);
+                            self.define(&[same, !leader_a, !leader_b]);
+                        }
+                        None => self.define(&[!same, !leader_b]),
+                    }
+                }
+            }
+        }
+
+        // Ranks: `ranks[index][k]` holds when the square's rank is more than k. Every square but
+        // the leader has a neighbor in its region with a lower rank.
+        let ranks = (0..n)
+            .map(|index| {
+                (0..part_sizes[parts[index]] - 1)
+                    .map(|_| self.new_lit())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for index in 0..n {
+            let own = regions.leader(index, index).unwrap();
+            let rank = &ranks[index];
+            if rank.is_empty() {
+                continue;
+            }
+            self.define(&[!own, !rank[0]]);
+            self.define(&[own, rank[0]]);
+            for k in 1..rank.len() {
+                self.define(&[!rank[k], rank[k - 1]]);
+            }
+            let mut parents = vec![own];
+            for neighbor in self.neighbors[index].clone() {
+                let parent = self.new_lit();
+                let same = self.same(index, neighbor);
+                self.define(&[!parent, same]);
+                let neighbor_rank = &ranks[neighbor];
+                for k in 0..rank.len() - 1 {
+                    self.define(&[!parent, !neighbor_rank[k], rank[k + 1]]);
+                }
+                self.define(&[!parent, !neighbor_rank[rank.len() - 1]]);
+                parents.push(parent);
+            }
+            self.define(&parents);
+        }
+        regions
+    }
+
+    // Whether the squares `index` is part of reach at least 1, 2, ... squares, up to `bound` + 1.
+    // Only counts squares within `bound` steps, which is enough to tell whether there are more.
+    fn region_size(&mut self, index: usize, bound: usize) -> Vec<Lit> {
+        let distances = distances(&self.neighbors, index, bound);
+        let mut reached = vec![None; self.colors.len()];
+        reached[index] = Some(self.constant(true));
+        for step in 1..=bound {
+            let mut next = reached.clone();
+            for square in 0..self.colors.len() {
+                if distances[square].is_none_or(|distance| distance > step) {
+                    continue;
+                }
+                let mut terms = reached[square].into_iter().collect::<Vec<_>>();
+                for neighbor in self.neighbors[square].clone() {
+                    if let Some(from) = reached[neighbor] {
+                        let same = self.same(square, neighbor);
+                        terms.push(self.and(from, same));
+                    }
+                }
+                next[square] = Some(self.or(&terms));
+            }
+            reached = next;
+        }
+        let inputs = reached.into_iter().flatten().collect::<Vec<_>>();
+        self.count(&inputs, bound + 1)
+    }
+
+    // Whether at least 1, 2, ... squares in the direction have the square's color.
+    fn visible(&mut self, index: usize, direction: Direction) -> Vec<Lit> {
+        if let Some(visible) = self.visible.get(&(index, direction)) {
+            return visible.clone();
+        }
+        let grid = self.grid;
+        let next = |square: usize| {
+            let square = &grid.squares[square];
+            match direction {
+                Direction::Up => square.above,
+                Direction::Down => square.below,
+                Direction::Left => square.left,
+                Direction::Right => square.right,
+            }
+        };
+        let mut visible = Vec::new();
+        let (mut current, mut run) = (index, self.constant(true));
+        while let Some(square) = next(current) {
+            let same = self.same(current, square.0);
+            run = self.and(run, same);
+            visible.push(run);
+            current = square.0;
+        }
+        self.visible.insert((index, direction), visible.clone());
+        visible
+    }
+
+    // Unary literals for a size: the k-th holds when the size is more than k, for every k up to
+    // `bound` that the size can exceed.
+    fn unary(&mut self, size: Size, bound: usize) -> Vec<Lit> {
+        match size {
+            Size::Area(index) => self.region_size(index.0, bound),
+            Size::Visible(index) => {
+                let mut inputs = Vec::new();
+                for direction in Direction::ALL {
+                    inputs.extend(self.visible(index.0, direction));
+                }
+                let mut unary = vec![self.constant(true)];
+                unary.extend(self.count(&inputs, bound));
+                unary
+            }
+            Size::Ray(index, direction) => self.visible(index.0, direction),
+        }
+    }
+
+    // True when the size is at least `n`, given unary literals from `unary`.
+    fn at_least(&self, unary: &[Lit], n: usize) -> Lit {
+        match n {
+            0 => self.constant(true),
+            n => unary.get(n - 1).copied().unwrap_or(self.constant(false)),
+        }
+    }
+
+    fn add_constraint(&mut self, constraint: &Constraint) {
+        match constraint {
+            Constraint::Clause(clause) => {
+                let clause = clause
+                    .iter()
+                    .map(|&(index, color)| self.color(index.0, color))
+                    .collect::<Vec<_>>();
+                self.require(&clause);
+            }
+            Constraint::SameColor(a, b) => {
+                let (a, b) = (self.colors[a.0], self.colors[b.0]);
+                self.require(&[!a, b]);
+                self.require(&[a, !b]);
+            }
+            Constraint::Connected(color) => {
+                let mut leaders = Vec::new();
+                for index in 0..self.colors.len() {
+                    let own = self.regions().leader(index, index).unwrap();
+                    let color = self.color(index, *color);
+                    leaders.push(self.and(own, color));
+                }
+                self.at_most_one(&leaders);
+            }
+            Constraint::Count(cells, count) => {
+                let inputs = cells
+                    .iter()
+                    .map(|&(index, color)| self.color(index.0, color))
+                    .collect::<Vec<_>>();
+                let outputs = self.count(&inputs, count + 1);
+                let exact = self.count_is(&outputs, *count);
+                self.require(&[exact]);
+            }
+            Constraint::SizeIn(size, numbers) => {
+                let unary = self.unary(*size, *numbers.iter().max().unwrap());
+                let options = numbers
+                    .iter()
+                    .map(|&number| self.count_is(&unary, number))
+                    .collect::<Vec<_>>();
+                self.require(&options);
+            }
+            Constraint::SizeBelow(size, limit) => {
+                let unary = self.unary(*size, *limit);
+                self.require(&[!self.at_least(&unary, *limit)]);
+            }
+            Constraint::SizesEqual(a, b) => {
+                let bound = self.colors.len();
+                let (a, b) = (self.unary(*a, bound), self.unary(*b, bound));
+                for k in 1..=a.len().max(b.len()) {
+                    let (at_least_a, at_least_b) = (self.at_least(&a, k), self.at_least(&b, k));
+                    self.require(&[!at_least_a, at_least_b]);
+                    self.require(&[at_least_a, !at_least_b]);
+                }
+            }
+            Constraint::SizeBeyond(near, far, limit) => {
+                let bound = self.colors.len();
+                let (near, far) = (self.unary(*near, bound), self.unary(*far, bound));
+                let further = self.new_lit();
+                for k in 0..=near.len() {
+                    let (at_least_near, beyond) =
+                        (self.at_least(&near, k), self.at_least(&far, k + 1));
+                    self.define(&[!further, !at_least_near, beyond]);
+                }
+                self.require(&[self.at_least(&far, *limit), further]);
+            }
+            Constraint::RegionsOfSize(color, size) => {
+                for leader in 0..self.colors.len() {
+                    let distances = distances(&self.neighbors, leader, *size);
+                    let members = (leader..self.colors.len())
+                        .filter(|&index| distances[index].is_some())
+                        .filter_map(|index| self.regions().leader(index, leader))
+                        .collect::<Vec<_>>();
+                    let outputs = self.count(&members, size + 1);
+                    let exact = self.count_is(&outputs, *size);
+                    let own = self.regions().leader(leader, leader).unwrap();
+                    self.require(&[!self.color(leader, *color), !own, exact]);
+                }
+            }
+            // Left to `ShapeRules`.
+            Constraint::RegionsHaveSameShape(_) | Constraint::RegionsHaveDifferentShapes(_) => {}
+            Constraint::OnePerRegion(color, numbered_squares) => {
+                for leader in 0..self.colors.len() {
+                    let numbered = numbered_squares
+                        .iter()
+                        .filter_map(|index| self.regions().leader(index.0, leader))
+                        .collect::<Vec<_>>();
+                    self.at_most_one(&numbered);
+                    let own = self.regions().leader(leader, leader).unwrap();
+                    let mut clause = vec![!self.color(leader, *color), !own];
+                    clause.extend(numbered);
+                    self.require(&clause);
+                }
+            }
+            Constraint::SameRegion(indexes) => {
+                let first = indexes[0].0;
+                for leader in 0..=first {
+                    if let Some(in_first) = self.regions().leader(first, leader) {
+                        for index in &indexes[1..] {
+                            self.same_leader(in_first, index.0, leader);
+                        }
+                    }
+                }
+            }
+            Constraint::DifferentRegions(indexes) => {
+                for leader in 0..self.colors.len() {
+                    let led = indexes
+                        .iter()
+                        .filter_map(|index| self.regions().leader(index.0, leader))
+                        .collect::<Vec<_>>();
+                    self.at_most_one(&led);
+                }
+            }
+            Constraint::Mirrored(index, images) => {
+                for leader in 0..=index.0 {
+                    let Some(in_first) = self.regions().leader(index.0, leader) else {
+                        continue;
+                    };
+                    for (index, image) in images {
+                        let Some(in_region) = self.regions().leader(index.0, leader) else {
+                            continue;
+                        };
+                        let mut clause = vec![!in_first, !in_region];
+                        clause
+                            .extend(image.and_then(|image| self.regions().leader(image.0, leader)));
+                        self.require(&clause);
+                    }
+                }
+            }
+        }
+    }
+
+    // Requires `index` to be led by `leader` exactly when `in_first` says the first square is.
+    fn same_leader(&mut self, in_first: Lit, index: usize, leader: usize) {
+        match self.regions().leader(index, leader) {
+            Some(other) => {
+                self.require(&[!in_first, other]);
+                self.require(&[in_first, !other]);
+            }
+            None => self.require(&[!in_first]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{Grid, PreparedSquare, SquareIndex};
+
+    // A 2x3 grid, and a 3x3 one with a hole in the middle, so that regions can wrap around.
+    fn grids() -> Vec<PreparedGrid> {
+        let mut ring = Grid::new(3, 3);
+        ring.remove_square(1, 1);
+        vec![Grid::new(2, 3).prepare(), ring.prepare()]
+    }
+
+    fn value(encoding: &Encoding, lit: Lit) -> bool {
+        encoding.solver.model_value(lit.var()) == lit.is_positive()
+    }
+
+    fn colorings(squares: usize) -> impl Iterator<Item = Vec<Color>> {
+        (0..1u32 << squares).map(move |bits| {
+            (0..squares)
+                .map(|index| match bits >> index & 1 {
+                    0 => Color::Light,
+                    _ => Color::Dark,
+                })
+                .collect()
+        })
+    }
+
+    fn assume(encoding: &Encoding, colors: &[Color]) -> Vec<Lit> {
+        colors
+            .iter()
+            .enumerate()
+            .map(|(index, &color)| encoding.color(index, color))
+            .collect()
+    }
+
+    // The squares of the region of each square, found by flood fill.
+    fn regions(neighbors: &[Vec<usize>], colors: &[Color]) -> Vec<Vec<usize>> {
+        (0..colors.len())
+            .map(|start| {
+                let mut region = vec![start];
+                let mut next = 0;
+                while next < region.len() {
+                    for &neighbor in &neighbors[region[next]] {
+                        if colors[neighbor] == colors[start] && !region.contains(&neighbor) {
+                            region.push(neighbor);
+                        }
+                    }
+                    next += 1;
+                }
+                region
+            })
+            .collect()
+    }
+
+    #[test]
+    fn totalizer_counts_up_to_its_bound() {
+        let grid = Grid::new(1, 1).prepare();
+        for bound in [2, 6] {
+            let mut encoding = Encoding::new(&grid);
+            let inputs = (0..6).map(|_| encoding.new_lit()).collect::<Vec<_>>();
+            let outputs = encoding.count(&inputs, bound);
+            assert_eq!(outputs.len(), bound);
+            let exactly = (0..bound)
+                .map(|n| encoding.count_is(&outputs, n))
+                .collect::<Vec<_>>();
+            for bits in 0..1u32 << inputs.len() {
+                let assumptions = inputs
+                    .iter()
+                    .enumerate()
+                    .map(|(n, &input)| if bits >> n & 1 == 1 { input } else { !input })
+                    .collect::<Vec<_>>();
+                assert_eq!(encoding.solver.solve(&assumptions), CheckResult::Sat);
+                let count = bits.count_ones() as usize;
+                for (k, &output) in outputs.iter().enumerate() {
+                    assert_eq!(value(&encoding, output), count > k);
+                }
+                for (n, &exact) in exactly.iter().enumerate() {
+                    assert_eq!(value(&encoding, exact), count == n);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn at_most_one_allows_one() {
+        let grid = Grid::new(1, 1).prepare();
+        for len in [3, 6] {
+            let mut encoding = Encoding::new(&grid);
+            let lits = (0..len).map(|_| encoding.new_lit()).collect::<Vec<_>>();
+            encoding.at_most_one(&lits);
+            for bits in 0..1u32 << len {
+                let assumptions = lits
+                    .iter()
+                    .enumerate()
+                    .map(|(n, &lit)| if bits >> n & 1 == 1 { lit } else { !lit })
+                    .collect::<Vec<_>>();
+                let expected = match bits.count_ones() {
+                    0 | 1 => CheckResult::Sat,
+                    _ => CheckResult::Unsat,
+                };
+                assert_eq!(encoding.solver.solve(&assumptions), expected);
+            }
+        }
+    }
+
+    // Ranks rule out a region led by a square outside it, so every square must be led by the
+    // lowest square of its own region and by no other.
+    #[test]
+    fn ranks_make_the_lowest_square_lead() {
+        for grid in grids() {
+            let mut encoding = Encoding::new(&grid);
+            encoding.regions();
+            let leaders = encoding.regions.as_ref().unwrap().leaders.clone();
+            let n = grid.squares.len();
+            for colors in colorings(n) {
+                let assumptions = assume(&encoding, &colors);
+                assert_eq!(encoding.solver.solve(&assumptions), CheckResult::Sat);
+                let regions = regions(&encoding.neighbors, &colors);
+                for index in 0..n {
+                    let lowest = *regions[index].iter().min().unwrap();
+                    for (leader, &lit) in leaders[index].iter().enumerate() {
+                        let Some(lit) = lit else {
+                            continue;
+                        };
+                        assert_eq!(value(&encoding, lit), leader == lowest);
+                        if leader != lowest {
+                            let mut wrong = assumptions.clone();
+                            wrong.push(lit);
+                            assert_eq!(encoding.solver.solve(&wrong), CheckResult::Unsat);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unary_sizes_count_regions_and_visible_squares() {
+        for grid in grids() {
+            let mut encoding = Encoding::new(&grid);
+            let n = grid.squares.len();
+            let mut areas = Vec::new();
+            let mut visible = Vec::new();
+            for index in 0..n {
+                for bound in 1..=3 {
+                    let unary = encoding.unary(Size::Area(SquareIndex(index)), bound);
+                    areas.push((index, bound, unary));
+                }
+                visible.push(encoding.unary(Size::Visible(SquareIndex(index)), n));
+            }
+            for colors in colorings(n) {
+                let assumptions = assume(&encoding, &colors);
+                assert_eq!(encoding.solver.solve(&assumptions), CheckResult::Sat);
+                let regions = regions(&encoding.neighbors, &colors);
+                for (index, bound, unary) in &areas {
+                    // Sizes are exact up to the bound, and only known to be beyond it past that.
+                    let size = regions[*index].len().min(bound + 1);
+                    for k in 0..=bound + 1 {
+                        assert_eq!(value(&encoding, encoding.at_least(unary, k)), size >= k);
+                    }
+                }
+                for (index, unary) in visible.iter().enumerate() {
+                    let square = &grid.squares[index];
+                    let mut count = 1;
+                    let steps: [fn(&PreparedSquare) -> Option<SquareIndex>; 4] = [
+                        |square| square.left,
+                        |square| square.right,
+                        |square| square.above,
+                        |square| square.below,
+                    ];
+                    for step in steps {
+                        let mut current = step(square);
+                        while let Some(other) =
+                            current.filter(|other| colors[other.0] == colors[index])
+                        {
+                            count += 1;
+                            current = step(&grid.squares[other.0]);
+                        }
+                    }
+                    for k in 0..=n {
+                        assert_eq!(value(&encoding, encoding.at_least(unary, k)), count >= k);
+                    }
+                }
+            }
+        }
+    }
+}
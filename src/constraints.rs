@@ -1,7 +1,12 @@
-use crate::grid::{Color, Coord, PreparedGrid, PreparedRule};
+use std::cell::OnceCell;
+use std::collections::HashMap;
+
+use crate::backend::{distances, neighbors, Backend, CheckResult, Literal, Session, ShapeRules};
+use crate::grid::{Color, Coord, Direction, PreparedGrid, SquareIndex};
+use crate::ir::{Constraint, ConstraintSet, Size};
 use z3::{
     ast::{self, Ast},
-    Solver,
+    Params, SatResult, Solver, StatisticsValue,
 };
 
 pub struct SquareVariables<'ctx> {
@@ -10,7 +15,6 @@ pub struct SquareVariables<'ctx> {
     pub color: ast::Bool<'ctx>,
     region_leader: ast::Int<'ctx>,
     region_rank: ast::Int<'ctx>,
-    region_size: ast::Int<'ctx>,
     is_leader: ast::Bool<'ctx>,
 
     left_visible: ast::Int<'ctx>,
@@ -33,7 +37,6 @@ impl<'ctx> SquareVariables<'ctx> {
         let id = ast::Int::from_u64(ctx, id as u64);
         let region_leader = ast::Int::new_const(ctx, format!("region_leader_{}", id));
         let region_rank = ast::Int::new_const(ctx, format!("region_rank_{}", id));
-        let region_size = ast::Int::new_const(ctx, format!("region_size_{}", id));
         let is_leader = region_leader._eq(&id);
 
         let left_visible = ast::Int::new_const(ctx, format!("left_visible_{}", id));
@@ -56,7 +59,6 @@ impl<'ctx> SquareVariables<'ctx> {
             color,
             region_leader,
             region_rank,
-            region_size,
             is_leader,
 
             left_visible,
@@ -66,6 +68,15 @@ impl<'ctx> SquareVariables<'ctx> {
             visible_total,
         }
     }
+
+    fn visible(&self, direction: Direction) -> &ast::Int<'ctx> {
+        match direction {
+            Direction::Up => &self.top_visible,
+            Direction::Down => &self.bottom_visible,
+            Direction::Left => &self.left_visible,
+            Direction::Right => &self.right_visible,
+        }
+    }
 }
 
 pub struct GridConstraints<'ctx> {
@@ -73,6 +84,18 @@ pub struct GridConstraints<'ctx> {
     pub aux: AuxVariables<'ctx>,
     pub basic_constraints: Vec<ast::Bool<'ctx>>,
     pub rule_constraints: Vec<ast::Bool<'ctx>>,
+
+    shapes: ShapeRules,
+    neighbors: Vec<Vec<usize>>,
+    // Region sizes, made as rules need them and only counted as far as those rules look. See
+    // `leader_size` and `region_size`.
+    leader_sizes: HashMap<(usize, usize), ast::Int<'ctx>>,
+    region_sizes: HashMap<(usize, usize), ast::Int<'ctx>>,
+    // The index of the prepared rule behind each rule constraint.
+    rule_origins: Vec<usize>,
+    // One literal per prepared rule, once the rules are asserted with `assert_tracked`.
+    rule_count: usize,
+    tracking: OnceCell<Vec<ast::Bool<'ctx>>>,
 }
 
 impl Color {
@@ -97,15 +120,26 @@ impl<'ctx> GridConstraints<'ctx> {
             zero: ast::Int::from_u64(ctx, 0),
             one: ast::Int::from_u64(ctx, 1),
         };
+        let mut set = ConstraintSet::new(grid);
+        set.simplify();
         let mut constraints = GridConstraints {
             squares,
             aux,
             basic_constraints: Vec::new(),
             rule_constraints: Vec::new(),
+            shapes: ShapeRules::new(grid, &set),
+            neighbors: neighbors(grid),
+            leader_sizes: HashMap::new(),
+            region_sizes: HashMap::new(),
+            rule_origins: Vec::new(),
+            rule_count: grid.rules.len(),
+            tracking: OnceCell::new(),
         };
         constraints.add_basic_constraints_for_variables(grid, ctx);
-        for rule in &grid.rules {
-            constraints.add_constraints_for_rule(rule, grid, ctx);
+        for (constraint, origin) in &set.constraints {
+            constraints.add_constraint(constraint, ctx);
+            let rules = constraints.rule_constraints.len();
+            constraints.rule_origins.resize(rules, *origin);
         }
         constraints
     }
@@ -171,7 +205,7 @@ impl<'ctx> GridConstraints<'ctx> {
             }
             let neighbors = [square.left, square.right, square.above, square.below]
                 .into_iter()
-                .filter_map(|x| x)
+                .flatten()
                 .collect::<Vec<_>>();
 
             // Either rank is zero, or there's at least one neighbor with same color and rank - 1.
@@ -193,23 +227,6 @@ impl<'ctx> GridConstraints<'ctx> {
             self.basic_constraints
                 .push(ast::Bool::or(ctx, &rank_cases.iter().collect::<Vec<_>>()));
 
-            // Region size is the number of cells that share the same region leader.
-            let mut region_size_components = Vec::new();
-            for other in &grid.squares {
-                let other_vars = &self.squares[other.index.0];
-                region_size_components.push(
-                    square_vars
-                        .region_leader
-                        ._eq(&other_vars.region_leader)
-                        .ite(&self.aux.one, &self.aux.zero),
-                );
-            }
-            self.basic_constraints
-                .push(square_vars.region_size._eq(&ast::Int::add(
-                    ctx,
-                    &region_size_components.iter().collect::<Vec<_>>(),
-                )));
-
             // A square's top_visible is 1 plus its above neighbor's top_visible if they have the same color,
             // otherwise zero.
             if let Some(above) = square.above {
@@ -280,42 +297,103 @@ impl<'ctx> GridConstraints<'ctx> {
         }
     }
 
-    fn add_constraints_for_rule(
-        &mut self,
-        rule: &PreparedRule,
-        grid: &PreparedGrid,
-        ctx: &'ctx z3::Context,
-    ) {
-        match rule {
-            PreparedRule::SquareIsColor(index, color) => {
-                let square = &self.squares[index.0];
-                self.rule_constraints.push(color.to_bool(&square.color));
+    fn cell(&self, &(index, color): &(SquareIndex, Color)) -> ast::Bool<'ctx> {
+        color.to_bool(&self.squares[index.0].color)
+    }
+
+    // The number of squares led by `leader`, counting only squares within `bound` steps of it.
+    // That is exact for regions of up to `bound` squares, and more than `bound` for larger ones.
+    fn leader_size(&mut self, leader: usize, bound: usize) -> ast::Int<'ctx> {
+        if let Some(size) = self.leader_sizes.get(&(leader, bound)) {
+            return size.clone();
+        }
+        let ctx = self.aux.zero.get_ctx();
+        let distances = distances(&self.neighbors, leader, bound);
+        // A leader has the lowest index in its region.
+        let components = (leader..self.squares.len())
+            .filter(|&index| distances[index].is_some())
+            .map(|index| {
+                self.squares[index]
+                    .region_leader
+                    ._eq(&self.squares[leader].id)
+                    .ite(&self.aux.one, &self.aux.zero)
+            })
+            .collect::<Vec<_>>();
+        let size = ast::Int::new_const(ctx, format!("leader_size_{}_{}", leader, bound));
+        self.basic_constraints
+            .push(size._eq(&ast::Int::add(ctx, &components.iter().collect::<Vec<_>>())));
+        self.leader_sizes.insert((leader, bound), size.clone());
+        size
+    }
+
+    // The size of the region of `index`, counting only squares within `bound` steps as for
+    // `leader_size`. The region is grown one step at a time from the square itself.
+    fn region_size(&mut self, index: usize, bound: usize) -> ast::Int<'ctx> {
+        if let Some(size) = self.region_sizes.get(&(index, bound)) {
+            return size.clone();
+        }
+        let ctx = self.aux.zero.get_ctx();
+        let distances = distances(&self.neighbors, index, bound);
+        let mut reached = vec![None; self.squares.len()];
+        reached[index] = Some(ast::Bool::from_bool(ctx, true));
+        for step in 1..=bound {
+            let mut next = reached.clone();
+            for square in 0..self.squares.len() {
+                if distances[square].is_none_or(|distance| distance > step) {
+                    continue;
+                }
+                let mut terms = reached[square].iter().cloned().collect::<Vec<_>>();
+                for &neighbor in &self.neighbors[square] {
+                    if let Some(from) = &reached[neighbor] {
+                        let same = self.squares[square]
+                            .color
+                            ._eq(&self.squares[neighbor].color);
+                        terms.push(ast::Bool::and(ctx, &[from, &same]));
+                    }
+                }
+                next[square] = Some(ast::Bool::or(ctx, &terms.iter().collect::<Vec<_>>()));
             }
-            PreparedRule::SquaresAreSameColor(a, b) => {
+            reached = next;
+        }
+        let components = reached
+            .iter()
+            .flatten()
+            .map(|reached| reached.ite(&self.aux.one, &self.aux.zero))
+            .collect::<Vec<_>>();
+        let size = ast::Int::add(ctx, &components.iter().collect::<Vec<_>>());
+        self.region_sizes.insert((index, bound), size.clone());
+        size
+    }
+
+    // Sizes are exact up to `bound`, and more than `bound` past it.
+    fn size(&mut self, size: &Size, bound: usize) -> ast::Int<'ctx> {
+        match size {
+            Size::Area(index) => self.region_size(index.0, bound),
+            Size::Visible(index) => self.squares[index.0].visible_total.clone(),
+            Size::Ray(index, direction) => self.squares[index.0].visible(*direction).clone(),
+        }
+    }
+
+    fn add_constraint(&mut self, constraint: &Constraint, ctx: &'ctx z3::Context) {
+        match constraint {
+            Constraint::Clause(clause) => {
+                let terms = clause
+                    .iter()
+                    .map(|cell| self.cell(cell))
+                    .collect::<Vec<_>>();
+                self.rule_constraints.push(match terms.len() {
+                    0 => ast::Bool::from_bool(ctx, false),
+                    1 => terms[0].clone(),
+                    _ => ast::Bool::or(ctx, &terms.iter().collect::<Vec<_>>()),
+                });
+            }
+            Constraint::SameColor(a, b) => {
                 let square_a = &self.squares[a.0];
                 let square_b = &self.squares[b.0];
                 self.rule_constraints
                     .push(square_a.color._eq(&square_b.color));
             }
-            PreparedRule::BanPattern(grid_pattern) => {
-                for i in 0..grid.size.i {
-                    'outer: for j in 0..grid.size.j {
-                        let offset = grid_pattern.offset(Coord { i, j });
-                        let mut and_terms = Vec::new();
-                        for (coord, color) in &offset.pattern {
-                            if let Some(index) = grid.square_indexes.get(coord) {
-                                let square = &self.squares[index.0];
-                                and_terms.push(color.to_bool(&square.color));
-                            } else {
-                                continue 'outer;
-                            }
-                        }
-                        let and = ast::Bool::and(ctx, &and_terms.iter().collect::<Vec<_>>());
-                        self.rule_constraints.push(and.not());
-                    }
-                }
-            }
-            PreparedRule::ConnectAll(color) => {
+            Constraint::Connected(color) => {
                 let leader = match color {
                     Color::Dark => &self.aux.dark_leader,
                     Color::Light => &self.aux.light_leader,
@@ -328,17 +406,88 @@ impl<'ctx> GridConstraints<'ctx> {
                     );
                 }
             }
-            PreparedRule::RegionFixedSize(color, size) => {
+            Constraint::Count(cells, count) => {
+                // z3 cannot add up nothing, e.g. for a dart pointing at a hole.
+                let mut components = vec![self.aux.zero.clone()];
+                for cell in cells {
+                    components.push(self.cell(cell).ite(&self.aux.one, &self.aux.zero));
+                }
+                self.rule_constraints.push(
+                    ast::Int::add(ctx, &components.iter().collect::<Vec<_>>())
+                        ._eq(&ast::Int::from_u64(ctx, *count as u64)),
+                );
+            }
+            Constraint::SizeIn(size, numbers) => {
+                let size = self.size(size, *numbers.iter().max().unwrap());
+                let terms = numbers
+                    .iter()
+                    .map(|&number| size._eq(&ast::Int::from_u64(ctx, number as u64)))
+                    .collect::<Vec<_>>();
+                self.rule_constraints.push(match terms.len() {
+                    1 => terms[0].clone(),
+                    _ => ast::Bool::or(ctx, &terms.iter().collect::<Vec<_>>()),
+                });
+            }
+            Constraint::SizeBelow(size, limit) => {
+                let limit_int = ast::Int::from_u64(ctx, *limit as u64);
+                let size = self.size(size, *limit);
+                self.rule_constraints.push(size.lt(&limit_int));
+            }
+            Constraint::SizesEqual(a, b) => {
+                let bound = self.squares.len();
+                let (a, b) = (self.size(a, bound), self.size(b, bound));
+                self.rule_constraints.push(a._eq(&b));
+            }
+            Constraint::SizeBeyond(near, far, limit) => {
+                let limit_int = ast::Int::from_u64(ctx, *limit as u64);
+                let bound = self.squares.len();
+                let (near, far) = (self.size(near, bound), self.size(far, bound));
+                self.rule_constraints
+                    .push(ast::Bool::or(ctx, &[&far.ge(&limit_int), &far.gt(&near)]));
+            }
+            Constraint::RegionsOfSize(color, size) => {
                 let size_int = ast::Int::from_u64(ctx, *size as u64);
-                for square in &self.squares {
+                for leader in 0..self.squares.len() {
+                    let leader_size = self.leader_size(leader, *size);
+                    let square = &self.squares[leader];
                     self.rule_constraints.push(
+                        ast::Bool::and(ctx, &[&color.to_bool(&square.color), &square.is_leader])
+                            .implies(&leader_size._eq(&size_int)),
+                    );
+                }
+                // Every region has the same size, so the color's total must be a multiple of it.
+                // This is implied, but lets the solver prune without building regions.
+                let count = self
+                    .squares
+                    .iter()
+                    .map(|square| {
                         color
                             .to_bool(&square.color)
-                            .implies(&square.region_size._eq(&size_int)),
+                            .ite(&self.aux.one, &self.aux.zero)
+                    })
+                    .collect::<Vec<_>>();
+                self.rule_constraints.push(
+                    ast::Int::add(ctx, &count.iter().collect::<Vec<_>>())
+                        .modulo(&size_int)
+                        ._eq(&self.aux.zero),
+                );
+            }
+            Constraint::RegionsHaveSameShape(color) => {
+                // Congruent regions have the same size, which is cheap to require up front.
+                let shape_size = ast::Int::new_const(ctx, format!("shape_size_{:?}", color));
+                let bound = self.squares.len();
+                for leader in 0..self.squares.len() {
+                    let leader_size = self.leader_size(leader, bound);
+                    let square = &self.squares[leader];
+                    self.rule_constraints.push(
+                        ast::Bool::and(ctx, &[&color.to_bool(&square.color), &square.is_leader])
+                            .implies(&leader_size._eq(&shape_size)),
                     );
                 }
             }
-            PreparedRule::ExactlyOneNumberPerRegion(color, numbered_squares) => {
+            // Left to `ShapeRules`.
+            Constraint::RegionsHaveDifferentShapes(_) => {}
+            Constraint::OnePerRegion(color, numbered_squares) => {
                 self.rule_constraints.push(ast::Int::distinct(
                     ctx,
                     &numbered_squares
@@ -359,53 +508,34 @@ impl<'ctx> GridConstraints<'ctx> {
                     );
                 }
             }
-            PreparedRule::RegionAreaEqualsNumber(index, number) => {
-                let number_int = ast::Int::from_u64(ctx, *number as u64);
-                self.rule_constraints
-                    .push(self.squares[index.0].region_size._eq(&number_int));
-            }
-            PreparedRule::VisibleCellCount(index, number) => {
-                let number_int = ast::Int::from_u64(ctx, *number as u64);
-                self.rule_constraints
-                    .push(self.squares[index.0].visible_total._eq(&number_int));
-            }
-            PreparedRule::RegionAreaEqualsEither(index, a, b) => {
-                let a_int = ast::Int::from_u64(ctx, *a as u64);
-                let b_int = ast::Int::from_u64(ctx, *b as u64);
-                self.rule_constraints.push(ast::Bool::or(
-                    ctx,
-                    &[
-                        &self.squares[index.0].region_size._eq(&a_int),
-                        &self.squares[index.0].region_size._eq(&b_int),
-                    ],
-                ));
+            Constraint::SameRegion(indexes) => {
+                let leader = &self.squares[indexes[0].0].region_leader;
+                for index in &indexes[1..] {
+                    self.rule_constraints
+                        .push(self.squares[index.0].region_leader._eq(leader));
+                }
             }
-            PreparedRule::VisibleCellCountEither(index, a, b) => {
-                let a_int = ast::Int::from_u64(ctx, *a as u64);
-                let b_int = ast::Int::from_u64(ctx, *b as u64);
-                self.rule_constraints.push(ast::Bool::or(
+            Constraint::DifferentRegions(indexes) => {
+                self.rule_constraints.push(ast::Int::distinct(
                     ctx,
-                    &[
-                        &self.squares[index.0].visible_total._eq(&a_int),
-                        &self.squares[index.0].visible_total._eq(&b_int),
-                    ],
+                    &indexes
+                        .iter()
+                        .map(|index| &self.squares[index.0].region_leader)
+                        .collect::<Vec<_>>(),
                 ));
             }
-            PreparedRule::ColorCountInSet(count, color, set) => {
-                let mut components = Vec::new();
-                for index in set {
-                    components.push(
-                        color
-                            .to_bool(&self.squares[index.0].color)
-                            .ite(&self.aux.one, &self.aux.zero),
-                    );
+            Constraint::Mirrored(index, images) => {
+                let leader = &self.squares[index.0].region_leader;
+                for (index, image) in images {
+                    let in_region = self.squares[index.0].region_leader._eq(leader);
+                    match image {
+                        Some(image) => self.rule_constraints.push(
+                            in_region.implies(&self.squares[image.0].region_leader._eq(leader)),
+                        ),
+                        None => self.rule_constraints.push(in_region.not()),
+                    }
                 }
-                self.rule_constraints.push(
-                    ast::Int::add(ctx, &components.iter().collect::<Vec<_>>())
-                        ._eq(&ast::Int::from_u64(ctx, *count as u64)),
-                );
             }
-            PreparedRule::RegionsHaveDifferentShapes(_) => todo!(),
         }
     }
 
@@ -417,6 +547,236 @@ impl<'ctx> GridConstraints<'ctx> {
             solver.assert(constraint);
         }
     }
+
+    // Like `assert`, but each prepared rule is only enforced under its own literal, which is
+    // returned by the prepared rule's index to be passed as an assumption. The unsat core then
+    // tells which rules a conflict needs.
+    pub fn assert_tracked(&self, solver: &Solver<'ctx>) -> &[ast::Bool<'ctx>] {
+        let ctx = self.aux.zero.get_ctx();
+        let literals = self.tracking.get_or_init(|| {
+            (0..self.rule_count)
+                .map(|n| ast::Bool::new_const(ctx, format!("rule_{}", n)))
+                .collect()
+        });
+        for constraint in &self.basic_constraints {
+            solver.assert(constraint);
+        }
+        for (constraint, &origin) in self.rule_constraints.iter().zip(&self.rule_origins) {
+            solver.assert(&literals[origin].implies(constraint));
+        }
+        literals
+    }
+
+    // Use this instead of `solver.check()`, so that the shape rules are enforced.
+    pub fn check(&self, solver: &Solver<'ctx>) -> SatResult {
+        self.check_assumptions(solver, &[])
+    }
+
+    pub fn check_assumptions(
+        &self,
+        solver: &Solver<'ctx>,
+        assumptions: &[ast::Bool<'ctx>],
+    ) -> SatResult {
+        loop {
+            let result = solver.check_assumptions(assumptions);
+            if result != SatResult::Sat || self.shapes.is_empty() {
+                return result;
+            }
+            let colors = self.model_colors(&solver.get_model().unwrap());
+            let violations = self.shapes.violations(&colors, |origin| {
+                self.tracking
+                    .get()
+                    .is_none_or(|literals| assumptions.contains(&literals[origin]))
+            });
+            if violations.is_empty() {
+                return result;
+            }
+            let ctx = self.aux.zero.get_ctx();
+            for (origin, terms) in &violations {
+                let terms = terms
+                    .iter()
+                    .map(|(index, color)| color.to_bool(&self.squares[*index].color))
+                    .collect::<Vec<_>>();
+                let lemma = ast::Bool::and(ctx, &terms.iter().collect::<Vec<_>>()).not();
+                match self.tracking.get() {
+                    Some(literals) => solver.assert(&literals[*origin].implies(&lemma)),
+                    None => solver.assert(&lemma),
+                }
+            }
+        }
+    }
+
+    // The color of each square in the model, by square index.
+    pub fn model_colors(&self, model: &z3::Model<'ctx>) -> Vec<Color> {
+        self.squares
+            .iter()
+            .map(
+                |square| match model.eval(&square.color, true).unwrap().as_bool() {
+                    Some(false) => Color::Dark,
+                    _ => Color::Light,
+                },
+            )
+            .collect()
+    }
+
+    // Rules out exactly this coloring of the squares, e.g. to look for another solution.
+    pub fn block_colors(&self, colors: &[Color]) -> ast::Bool<'ctx> {
+        let ctx = self.aux.zero.get_ctx();
+        let differences = self
+            .squares
+            .iter()
+            .zip(colors)
+            .map(|(square, color)| color.opposite().to_bool(&square.color))
+            .collect::<Vec<_>>();
+        ast::Bool::or(ctx, &differences.iter().collect::<Vec<_>>())
+    }
+}
+
+pub(crate) fn z3_solver(ctx: &z3::Context, timeout: u32) -> Solver<'_> {
+    let solver = Solver::new(ctx);
+    let mut params = Params::new(ctx);
+    params.set_u32("timeout", timeout * 1000);
+    solver.set_params(&params);
+    solver
+}
+
+pub struct Z3Backend;
+
+impl Backend for Z3Backend {
+    fn name(&self) -> &'static str {
+        "z3"
+    }
+
+    fn run(
+        &self,
+        grid: &PreparedGrid,
+        timeout: u32,
+        tracked: bool,
+        f: &mut dyn FnMut(&mut dyn Session),
+    ) {
+        let config = z3::Config::new();
+        let ctx = z3::Context::new(&config);
+        let constraints = GridConstraints::new(grid, &ctx);
+        let solver = z3_solver(&ctx, timeout);
+        let rules = if tracked {
+            constraints.assert_tracked(&solver).to_vec()
+        } else {
+            constraints.assert(&solver);
+            Vec::new()
+        };
+        f(&mut Z3Session {
+            constraints,
+            solver: &solver,
+            rules,
+            assumptions: Vec::new(),
+            colors: Vec::new(),
+            total_conflicts: 0,
+            conflicts: 0,
+        });
+    }
+}
+
+struct Z3Session<'a, 'ctx> {
+    constraints: GridConstraints<'ctx>,
+    solver: &'a Solver<'ctx>,
+    rules: Vec<ast::Bool<'ctx>>,
+    // The last check's assumptions, to map its unsat core back.
+    assumptions: Vec<(Literal, ast::Bool<'ctx>)>,
+    colors: Vec<Color>,
+    total_conflicts: u64,
+    conflicts: u64,
+}
+
+impl Session for Z3Session<'_, '_> {
+    fn check(&mut self, assumptions: &[Literal]) -> CheckResult {
+        self.assumptions = assumptions
+            .iter()
+            .map(|&literal| {
+                let literal_bool = match literal {
+                    Literal::Color(index, color) => {
+                        color.to_bool(&self.constraints.squares[index.0].color)
+                    }
+                    Literal::Rule(rule) => self.rules[rule].clone(),
+                };
+                (literal, literal_bool)
+            })
+            .collect();
+        let bools = self
+            .assumptions
+            .iter()
+            .map(|(_, literal_bool)| literal_bool.clone())
+            .collect::<Vec<_>>();
+        let result = self.constraints.check_assumptions(self.solver, &bools);
+
+        // The statistics may either accumulate or start over with each check.
+        let total = match self.solver.get_statistics().value("conflicts") {
+            Some(StatisticsValue::UInt(conflicts)) => conflicts as u64,
+            Some(StatisticsValue::Double(conflicts)) => conflicts as u64,
+            None => 0,
+        };
+        self.conflicts = total.checked_sub(self.total_conflicts).unwrap_or(total);
+        self.total_conflicts = total;
+
+        match result {
+            SatResult::Sat => {
+                self.colors = self
+                    .constraints
+                    .model_colors(&self.solver.get_model().unwrap());
+                CheckResult::Sat
+            }
+            SatResult::Unsat => CheckResult::Unsat,
+            SatResult::Unknown => CheckResult::Unknown,
+        }
+    }
+
+    fn model(&self) -> Vec<Color> {
+        self.colors.clone()
+    }
+
+    fn core(&self) -> Vec<Literal> {
+        self.solver
+            .get_unsat_core()
+            .iter()
+            .filter_map(|core_bool| {
+                self.assumptions
+                    .iter()
+                    .find(|(_, literal_bool)| literal_bool == core_bool)
+                    .map(|&(literal, _)| literal)
+            })
+            .collect()
+    }
+
+    fn block(&mut self, colors: &[Color]) {
+        self.solver.assert(&self.constraints.block_colors(colors));
+    }
+
+    fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    fn add(&mut self, constraints: &[Constraint]) -> Literal {
+        debug_assert!(!constraints.iter().any(ShapeRules::is_shape_rule));
+        let ctx = self.constraints.aux.zero.get_ctx();
+        let literal = ast::Bool::new_const(ctx, format!("rule_{}", self.rules.len()));
+        let start = self.constraints.rule_constraints.len();
+        let basic = self.constraints.basic_constraints.len();
+        for constraint in constraints {
+            self.constraints.add_constraint(constraint, ctx);
+        }
+        // Region sizes the new constraints count on.
+        for constraint in &self.constraints.basic_constraints[basic..] {
+            self.solver.assert(constraint);
+        }
+        for constraint in &self.constraints.rule_constraints[start..] {
+            self.solver.assert(&literal.implies(constraint));
+        }
+        let rules = self.constraints.rule_constraints.len();
+        self.constraints
+            .rule_origins
+            .resize(rules, self.rules.len());
+        self.rules.push(literal);
+        Literal::Rule(self.rules.len() - 1)
+    }
 }
 
 pub enum PrintKind {
@@ -440,9 +800,17 @@ impl PrintKind {
 
     pub fn print_square<'ctx>(
         &self,
+        constraints: &GridConstraints<'ctx>,
         square: &SquareVariables<'ctx>,
         model: &z3::Model<'ctx>,
     ) -> String {
+        let leader = |square: &SquareVariables<'ctx>| {
+            model
+                .eval(&square.region_leader, false)
+                .unwrap()
+                .as_i64()
+                .unwrap()
+        };
         let mut res = match self {
             PrintKind::Color => {
                 if model.eval(&square.color, false).unwrap().as_bool().unwrap() {
@@ -451,18 +819,14 @@ impl PrintKind {
                     "■".to_string()
                 }
             }
-            PrintKind::RegionSize => model
-                .eval(&square.region_size, false)
-                .unwrap()
-                .as_i64()
-                .unwrap()
-                .to_string(),
-            PrintKind::RegionLeader => model
-                .eval(&square.region_leader, false)
-                .unwrap()
-                .as_i64()
-                .unwrap()
+            // Sizes are only encoded where rules need them, so count the region instead.
+            PrintKind::RegionSize => constraints
+                .squares
+                .iter()
+                .filter(|other| leader(other) == leader(square))
+                .count()
                 .to_string(),
+            PrintKind::RegionLeader => leader(square).to_string(),
             PrintKind::RegionRank => model
                 .eval(&square.region_rank, false)
                 .unwrap()
@@ -483,10 +847,10 @@ impl PrintKind {
     }
 }
 
-pub fn print_solved_grid(
+pub fn print_solved_grid<'ctx>(
     grid: &PreparedGrid,
-    constraints: &GridConstraints<'_>,
-    model: &z3::Model<'_>,
+    constraints: &GridConstraints<'ctx>,
+    model: &z3::Model<'ctx>,
     kind: PrintKind,
 ) -> String {
     let mut res = String::new();
@@ -494,7 +858,7 @@ pub fn print_solved_grid(
         for j in 0..grid.size.j {
             if let Some(index) = grid.square_indexes.get(&Coord { i, j }) {
                 let square = &constraints.squares[index.0];
-                res.push_str(&kind.print_square(square, model));
+                res.push_str(&kind.print_square(constraints, square, model));
             } else {
                 res.push_str(&" ".to_string().repeat(kind.column_width()));
             }
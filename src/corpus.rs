@@ -0,0 +1,410 @@
+use std::{
+    fmt::{self, Display},
+    path::Path,
+};
+
+use serde_json::Value;
+
+use crate::{
+    grid::{Color, Grid},
+    pdata::{self, DecodedGrid, DecodedRule, Solution, Topology, Untranslatable},
+};
+
+// Loader for the decoded.json corpus written by capture/puzzles.py. Each entry is mapped onto the
+// same `DecodedGrid` the binary decoder produces, so both share the translation into a `Grid`.
+
+#[derive(Clone, Debug)]
+pub struct CorpusEntry {
+    pub pid: String,
+    pub difficulty: u64,
+    pub grid: Grid,
+    pub solution: Option<Solution>,
+    // Rules that could not be expressed as `grid::Rule`s; the grid is missing these.
+    pub untranslatable: Vec<Untranslatable>,
+    // The error capture/puzzles.py hit while decoding, in which case the rules may be incomplete.
+    pub decode_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum CorpusError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Malformed { entry: usize, message: String },
+}
+
+impl Display for CorpusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CorpusError::Io(err) => write!(f, "failed to read corpus: {}", err),
+            CorpusError::Json(err) => write!(f, "failed to parse corpus: {}", err),
+            CorpusError::Malformed { entry, message } => {
+                write!(f, "malformed corpus entry {}: {}", entry, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CorpusError {}
+
+// The entries of a corpus, along with the ones that could not be parsed.
+#[derive(Debug)]
+pub struct Corpus {
+    pub entries: Vec<CorpusEntry>,
+    pub errors: Vec<CorpusError>,
+}
+
+// Fails only if the file as a whole cannot be read; malformed entries end up in `Corpus::errors`.
+pub fn load_corpus(path: impl AsRef<Path>) -> Result<Corpus, CorpusError> {
+    let json = std::fs::read_to_string(path).map_err(CorpusError::Io)?;
+    parse_corpus(&json)
+}
+
+pub fn parse_corpus(json: &str) -> Result<Corpus, CorpusError> {
+    let value: Value = serde_json::from_str(json).map_err(CorpusError::Json)?;
+    let values = value.as_array().ok_or_else(|| CorpusError::Malformed {
+        entry: 0,
+        message: "expected a list of puzzles".to_string(),
+    })?;
+    let mut corpus = Corpus {
+        entries: Vec::new(),
+        errors: Vec::new(),
+    };
+    for (entry, value) in values.iter().enumerate() {
+        match parse_entry(value) {
+            Ok(parsed) => corpus.entries.push(parsed),
+            Err(message) => corpus
+                .errors
+                .push(CorpusError::Malformed { entry, message }),
+        }
+    }
+    Ok(corpus)
+}
+
+pub fn parse_entry(value: &Value) -> Result<CorpusEntry, String> {
+    let pid = match &value["pid"] {
+        Value::String(pid) => pid.clone(),
+        Value::Null => return Err("missing pid".to_string()),
+        pid => pid.to_string(),
+    };
+    let in_entry = |message: String| format!("puzzle {}: {}", pid, message);
+    let difficulty = value["difficulty"].as_u64().unwrap_or(0);
+    let rows = as_usize(&value["rows"]).map_err(in_entry)?;
+    let cols = as_usize(&value["cols"]).map_err(in_entry)?;
+    if !(1..=pdata::MAX_SIZE).contains(&rows) || !(1..=pdata::MAX_SIZE).contains(&cols) {
+        return Err(in_entry(format!("invalid grid size {}x{}", rows, cols)));
+    }
+
+    let topology = as_list(&value["topology"])
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| parse_topology(item, rows, cols))
+                .collect()
+        })
+        .map_err(in_entry)?;
+    let rules = as_list(&value["rules"])
+        .and_then(|items| {
+            items
+                .iter()
+                .map(|item| parse_rule(item, rows, cols))
+                .collect()
+        })
+        .map_err(in_entry)?;
+    let solution = match &value["solution"] {
+        Value::Null => None,
+        solution => Some(parse_solution(solution, rows, cols).map_err(in_entry)?),
+    };
+    // The error is stringified in Python, so a successful decode shows up as "None".
+    let decode_error = match value["error"].as_str() {
+        None | Some("None") => None,
+        Some(error) => Some(error.to_string()),
+    };
+
+    let decoded = DecodedGrid {
+        rows,
+        cols,
+        topology,
+        rules,
+        solution,
+    };
+    if let Some(edge) = decoded.merge_into_hole() {
+        return Err(in_entry(format!("merge edge {} joins a hole", edge)));
+    }
+    let (grid, untranslatable) = decoded.to_grid();
+    Ok(CorpusEntry {
+        pid,
+        difficulty,
+        grid,
+        solution: decoded.solution,
+        untranslatable,
+        decode_error,
+    })
+}
+
+fn as_usize(value: &Value) -> Result<usize, String> {
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| format!("expected a number, got {}", value))
+}
+
+fn as_list(value: &Value) -> Result<&Vec<Value>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("expected a list, got {}", value))
+}
+
+fn as_cell(value: &Value, rows: usize, cols: usize) -> Result<usize, String> {
+    let cell = as_usize(value)?;
+    if cell >= rows * cols {
+        return Err(format!("cell {} is out of range", cell));
+    }
+    Ok(cell)
+}
+
+fn as_half_cell(value: &Value, rows: usize, cols: usize) -> Result<usize, String> {
+    let location = as_usize(value)?;
+    if location >= (2 * rows + 1) * (2 * cols + 1) {
+        return Err(format!("location {} is out of range", location));
+    }
+    Ok(location)
+}
+
+fn as_direction(value: &Value, limit: usize) -> Result<usize, String> {
+    let direction = as_usize(value)?;
+    if direction > limit {
+        return Err(format!("invalid direction {}", direction));
+    }
+    Ok(direction)
+}
+
+// Reads a list of fixed-size tuples, e.g. `[[cell, number], ...]`.
+fn as_tuples<const N: usize>(value: &Value) -> Result<Vec<[&Value; N]>, String> {
+    as_list(value)?
+        .iter()
+        .map(|item| {
+            let items = as_list(item)?;
+            if items.len() != N {
+                return Err(format!("expected {} values, got {}", N, item));
+            }
+            Ok(std::array::from_fn(|i| &items[i]))
+        })
+        .collect()
+}
+
+fn parse_topology(value: &Value, rows: usize, cols: usize) -> Result<Topology, String> {
+    let items = as_list(value)?;
+    let arg = items.get(1).unwrap_or(&Value::Null);
+    match items.first().and_then(Value::as_str) {
+        Some("merge") => Ok(Topology::Merge(
+            as_list(arg)?
+                .iter()
+                .map(|edge| {
+                    let edge = as_usize(edge)?;
+                    if pdata::merge_edge(rows, cols, edge).is_none() {
+                        return Err(format!("invalid merge edge {}", edge));
+                    }
+                    Ok(edge)
+                })
+                .collect::<Result<_, _>>()?,
+        )),
+        Some("hole") => Ok(Topology::Hole(
+            as_list(arg)?
+                .iter()
+                .map(|cell| as_cell(cell, rows, cols))
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Err(format!("unsupported topology {}", value)),
+    }
+}
+
+fn parse_rule(value: &Value, rows: usize, cols: usize) -> Result<DecodedRule, String> {
+    let items = as_list(value)?;
+    let name = items
+        .first()
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("expected a rule name, got {}", value))?;
+    let arg = items.get(1).unwrap_or(&Value::Null);
+    let cells = || -> Result<Vec<usize>, String> {
+        as_list(arg)?
+            .iter()
+            .map(|cell| as_cell(cell, rows, cols))
+            .collect()
+    };
+    let numbered_cells = || -> Result<Vec<(usize, usize)>, String> {
+        as_tuples::<2>(arg)?
+            .into_iter()
+            .map(|[cell, number]| Ok((as_cell(cell, rows, cols)?, as_usize(number)?)))
+            .collect()
+    };
+    let rule = match name {
+        "light" => DecodedRule::Light(cells()?),
+        "dark" => DecodedRule::Dark(cells()?),
+        "area" => DecodedRule::Area(numbered_cells()?),
+        "viewpoint" => DecodedRule::Viewpoint(numbered_cells()?),
+        "dart" => DecodedRule::Dart(
+            as_tuples::<3>(arg)?
+                .into_iter()
+                .map(|[cell, number, direction]| {
+                    Ok((
+                        as_cell(cell, rows, cols)?,
+                        as_usize(number)?,
+                        as_direction(direction, 3)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        "galaxy" => DecodedRule::Galaxy(
+            as_list(arg)?
+                .iter()
+                .map(|location| as_half_cell(location, rows, cols))
+                .collect::<Result<_, _>>()?,
+        ),
+        "lotus" => DecodedRule::Lotus(
+            as_tuples::<2>(arg)?
+                .into_iter()
+                .map(|[location, direction]| {
+                    Ok((
+                        as_half_cell(location, rows, cols)?,
+                        as_direction(direction, 3)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        "myopia" => DecodedRule::Myopia(
+            as_tuples::<2>(arg)?
+                .into_iter()
+                .map(|[cell, arrows]| Ok((as_cell(cell, rows, cols)?, as_direction(arrows, 15)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        "letters" => DecodedRule::Letters(numbered_cells()?),
+        "ban_patterns" => DecodedRule::BanPatterns(
+            as_tuples::<4>(arg)?
+                .into_iter()
+                .map(|[pattern_rows, pattern_cols, _, data]| {
+                    let pattern_cols = as_usize(pattern_cols)?;
+                    let data = as_list(data)?
+                        .iter()
+                        .map(|cell| as_usize(cell).map(|cell| cell as u8))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if data.len() != as_usize(pattern_rows)? * pattern_cols {
+                        return Err(format!("ban pattern has {} cells", data.len()));
+                    }
+                    Ok(pdata::pattern_from_cells(pattern_cols, &data))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        "connect_all_light" => DecodedRule::ConnectAll(Color::Light),
+        "connect_all_dark" => DecodedRule::ConnectAll(Color::Dark),
+        "one_symbol_per_light" => DecodedRule::OneSymbolPerRegion(Color::Light),
+        "one_symbol_per_dark" => DecodedRule::OneSymbolPerRegion(Color::Dark),
+        "light_shapes_distinct" => DecodedRule::ShapesDistinct(Color::Light),
+        "dark_shapes_distinct" => DecodedRule::ShapesDistinct(Color::Dark),
+        "light_area" => DecodedRule::RegionArea(Color::Light, as_usize(arg)?),
+        "dark_area" => DecodedRule::RegionArea(Color::Dark, as_usize(arg)?),
+        "light_shapes_same" => DecodedRule::ShapesSame(Color::Light),
+        "dark_shapes_same" => DecodedRule::ShapesSame(Color::Dark),
+        "unknown_rule_0x7f" => DecodedRule::Unknown(
+            0x7f,
+            as_list(arg)?
+                .iter()
+                .map(|byte| as_usize(byte).map(|byte| byte as u8))
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(format!("unsupported rule {}", value)),
+    };
+    Ok(rule)
+}
+
+fn parse_solution(value: &Value, rows: usize, cols: usize) -> Result<Solution, String> {
+    let items = as_list(value)?;
+    if items.len() != 3 {
+        return Err(format!("expected (kind, pattern, data), got {}", value));
+    }
+    let data = as_list(&items[2])?
+        .iter()
+        .map(as_usize)
+        .collect::<Result<Vec<_>, _>>()?;
+    if data.len() != rows * cols {
+        return Err(format!(
+            "expected {} solution cells, got {}",
+            rows * cols,
+            data.len()
+        ));
+    }
+    let row = |i: usize| &data[i * cols..(i + 1) * cols];
+    match as_usize(&items[0])? {
+        1 => Ok(Solution::Full(
+            (0..rows)
+                .map(|i| {
+                    row(i)
+                        .iter()
+                        .map(|&cell| match cell {
+                            0 => Color::Light,
+                            _ => Color::Dark,
+                        })
+                        .collect()
+                })
+                .collect(),
+        )),
+        2 => Ok(Solution::Underconstrained(
+            (0..rows)
+                .map(|i| {
+                    row(i)
+                        .iter()
+                        .map(|&cell| pdata::cell_color(cell as u8))
+                        .collect()
+                })
+                .collect(),
+        )),
+        kind => Err(format!("unsupported solution kind {}", kind)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_entries_do_not_hide_the_rest() {
+        let corpus = parse_corpus(
+            r#"[
+                {"pid": "a", "difficulty": 1, "rows": 2, "cols": 2, "topology": [],
+                 "rules": [["dark", [0]], ["connect_all_light"]], "solution": null},
+                {"pid": "b", "rows": 2, "cols": 2, "topology": [], "rules": [["dark", [9]]]},
+                {"pid": "c", "rows": 2, "cols": 2, "topology": [["merge", [6]], ["hole", [1]]],
+                 "rules": []},
+                {"pid": "d", "difficulty": 3, "rows": 1, "cols": 3, "topology": [],
+                 "rules": [["area", [[0, 3]]]], "solution": [1, ["LLL"], [0, 0, 0]]}
+            ]"#,
+        )
+        .unwrap();
+        let pids = corpus
+            .entries
+            .iter()
+            .map(|entry| entry.pid.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(pids, ["a", "d"]);
+        let errors = corpus
+            .errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "malformed corpus entry 1: puzzle b: cell 9 is out of range",
+                "malformed corpus entry 2: puzzle c: merge edge 6 joins a hole",
+            ]
+        );
+    }
+
+    #[test]
+    fn unreadable_corpus_is_an_error() {
+        assert!(matches!(parse_corpus("{"), Err(CorpusError::Json(_))));
+        assert!(matches!(
+            parse_corpus("{}"),
+            Err(CorpusError::Malformed { entry: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::backend::{with_session, CheckResult, Literal};
+use crate::grid::{Color, Coord, Grid, RuleRef};
+use crate::solver::SolveOptions;
+
+// Estimates how hard a puzzle is for a person by replaying the deduction a solver does: in each
+// round, every square that is forced by the rules and the squares known so far gets filled in.
+// Puzzles that take many rounds, need several known squares at once to force a square, or make
+// the solver search a lot score higher.
+
+#[derive(Clone, Debug)]
+pub struct DeductionStep {
+    pub forced: Vec<(Coord, Color)>,
+    // How many of the forced squares depend on more than one known square at once. Based on
+    // the solver's unsat cores, which are not minimal, so this overestimates a little.
+    pub multi_cell: usize,
+    // Solver conflicts spent on this round.
+    pub conflicts: u64,
+}
+
+// How a trace ended. Only a trace that solved the puzzle says how hard it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEnd {
+    Solved,
+    // The rules contradict each other or the givens.
+    Unsolvable,
+    // No more squares could be forced, and the model showed each remaining square can take
+    // either color, so the puzzle has more than one solution.
+    Stuck,
+    // Some check timed out, so squares may have been missed.
+    TimedOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct DifficultyTrace {
+    pub steps: Vec<DeductionStep>,
+    // Squares left uncolored when no more could be forced.
+    pub remaining: usize,
+    pub end: TraceEnd,
+}
+
+impl DifficultyTrace {
+    pub fn is_solved(&self) -> bool {
+        self.end == TraceEnd::Solved
+    }
+
+    pub fn forced(&self) -> usize {
+        self.steps.iter().map(|step| step.forced.len()).sum()
+    }
+
+    // A heuristic score: the number of rounds, plus up to 3 for the share of squares needing
+    // multi-cell reasoning, plus the log of the conflicts per forced square. It only measures
+    // difficulty if the trace is solved.
+    pub fn score(&self) -> f64 {
+        let forced = self.forced().max(1) as f64;
+        let multi_cell = self.steps.iter().map(|step| step.multi_cell).sum::<usize>() as f64;
+        let conflicts = self.steps.iter().map(|step| step.conflicts).sum::<u64>() as f64;
+        self.steps.len() as f64 + 3.0 * multi_cell / forced + (1.0 + conflicts / forced).log2()
+    }
+}
+
+pub fn trace_difficulty(grid: &Grid, options: SolveOptions) -> DifficultyTrace {
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    with_session(backend, &prepared, options.timeout, true, |session| {
+        let literals = (0..prepared.rules.len())
+            .map(Literal::Rule)
+            .collect::<Vec<_>>();
+        // The squares deduced so far, which are assumed rather than asserted.
+        let mut known = vec![None; prepared.squares.len()];
+        for (coord, square) in grid.squares() {
+            known[prepared.square_indexes[&coord].0] = square.color;
+        }
+        let mut steps = Vec::new();
+        let mut incomplete = false;
+        let mut unsolvable = false;
+        loop {
+            let mut assumptions = literals.clone();
+            let mut deduced = Vec::new();
+            for (coord, square) in grid.squares() {
+                let index = prepared.square_indexes[&coord];
+                if let (Some(color), None) = (known[index.0], square.color) {
+                    let literal = Literal::Color(index, color);
+                    deduced.push(literal);
+                    assumptions.push(literal);
+                }
+            }
+            let colors = match session.check(&assumptions) {
+                CheckResult::Sat => session.model(),
+                CheckResult::Unknown => {
+                    incomplete = true;
+                    break;
+                }
+                CheckResult::Unsat => {
+                    unsolvable = true;
+                    break;
+                }
+            };
+            let mut step = DeductionStep {
+                forced: Vec::new(),
+                multi_cell: 0,
+                conflicts: session.conflicts(),
+            };
+            let mut free = vec![false; colors.len()];
+            for (coord, _) in grid.squares() {
+                let index = prepared.square_indexes[&coord];
+                if known[index.0].is_some() || free[index.0] {
+                    continue;
+                }
+                let mut check = assumptions.clone();
+                check.push(Literal::Color(index, colors[index.0].opposite()));
+                let result = session.check(&check);
+                step.conflicts += session.conflicts();
+                match result {
+                    CheckResult::Sat => {
+                        for (n, color) in session.model().into_iter().enumerate() {
+                            free[n] |= color != colors[n];
+                        }
+                    }
+                    CheckResult::Unknown => incomplete = true,
+                    CheckResult::Unsat => {
+                        let known_squares = session
+                            .core()
+                            .iter()
+                            .filter(|literal| match literal {
+                                Literal::Color(..) => deduced.contains(literal),
+                                Literal::Rule(rule) => {
+                                    matches!(prepared.sources[*rule], RuleRef::Given(_))
+                                }
+                            })
+                            .count();
+                        if known_squares > 1 {
+                            step.multi_cell += 1;
+                        }
+                        step.forced.push((coord, colors[index.0]));
+                    }
+                }
+            }
+            if step.forced.is_empty() {
+                break;
+            }
+            for &(coord, color) in &step.forced {
+                known[prepared.square_indexes[&coord].0] = Some(color);
+            }
+            steps.push(step);
+        }
+        let remaining = known.iter().filter(|color| color.is_none()).count();
+        let end = if unsolvable {
+            TraceEnd::Unsolvable
+        } else if incomplete {
+            TraceEnd::TimedOut
+        } else if remaining > 0 {
+            TraceEnd::Stuck
+        } else {
+            TraceEnd::Solved
+        };
+        DifficultyTrace {
+            steps,
+            remaining,
+            end,
+        }
+    })
+}
+
+// Pearson correlation of two equally long series, if neither is constant.
+pub fn pearson(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x) * (x - mean_x);
+        variance_y += (y - mean_y) * (y - mean_y);
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+// Spearman rank correlation, with tied values sharing their average rank.
+pub fn spearman(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    pearson(&ranks(xs), &ranks(ys))
+}
+
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order = (0..values.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end - 1) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::Rule;
+
+    // A 1x2 grid with a light square numbered 1 and the other square uncolored.
+    fn numbered() -> Grid {
+        let mut grid = Grid::new(1, 2);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_light(0, 0);
+        grid.set_area_number(0, 0, 1);
+        grid
+    }
+
+    fn trace(grid: &Grid) -> Vec<DifficultyTrace> {
+        test_options()
+            .map(|options| trace_difficulty(grid, options))
+            .collect()
+    }
+
+    #[test]
+    fn solved_puzzles_are_scored() {
+        for trace in trace(&numbered()) {
+            assert_eq!(trace.end, TraceEnd::Solved);
+            assert_eq!(trace.steps.len(), 1);
+            assert_eq!(trace.steps[0].forced, [(Coord { i: 0, j: 1 }, Color::Dark)]);
+            assert_eq!(trace.remaining, 0);
+        }
+    }
+
+    #[test]
+    fn unsolvable_puzzles_are_not_scored() {
+        let mut grid = numbered();
+        grid.color_light(0, 1);
+        for trace in trace(&grid) {
+            assert_eq!(trace.end, TraceEnd::Unsolvable);
+            assert!(!trace.is_solved());
+        }
+    }
+
+    #[test]
+    fn puzzles_with_several_solutions_get_stuck() {
+        let mut grid = numbered();
+        grid.clear_area_number(0, 0);
+        for trace in trace(&grid) {
+            assert_eq!(trace.end, TraceEnd::Stuck);
+            assert!(trace.steps.is_empty());
+            assert_eq!(trace.remaining, 1);
+        }
+    }
+}
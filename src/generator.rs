@@ -0,0 +1,392 @@
+use crate::backend::{with_session, CheckResult, Literal};
+use crate::checker::{color_at, dart_squares, ray, Regions};
+use crate::difficulty;
+use crate::grid::{Color, Coord, Direction, Grid, Rule, SquareIndex};
+use crate::minimizer::{self, MinimizeResult};
+use crate::solver::{colored_grid, SolveOptions};
+
+// Generates puzzles by coloring a grid at random under the global rules, putting a clue on every
+// square, and then taking clues away for as long as the solution stays unique.
+
+// The kinds of clue a generated puzzle may contain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClueKind {
+    Given,
+    AreaNumber,
+    Viewpoint,
+    Dart,
+}
+
+#[derive(Clone, Debug)]
+pub struct GeneratorOptions {
+    pub rows: usize,
+    pub cols: usize,
+    // Rules the coloring must follow regardless of clues, e.g. `ConnectAll` or ban patterns.
+    pub rules: Vec<Rule>,
+    pub clues: Vec<ClueKind>,
+    // A `DifficultyTrace::score` to aim for; the closest of all attempts is kept.
+    pub difficulty: f64,
+    pub attempts: usize,
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct GeneratedPuzzle {
+    pub grid: Grid,
+    pub solution: Grid,
+    pub difficulty: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Clue {
+    Given(Coord, Color),
+    AreaNumber(Coord, usize),
+    Viewpoint(Coord, usize),
+    Dart(Coord, Direction, usize, Color),
+}
+
+impl Clue {
+    fn key(&self) -> (Coord, ClueKind) {
+        match *self {
+            Clue::Given(coord, _) => (coord, ClueKind::Given),
+            Clue::AreaNumber(coord, _) => (coord, ClueKind::AreaNumber),
+            Clue::Viewpoint(coord, _) => (coord, ClueKind::Viewpoint),
+            Clue::Dart(coord, _, _, _) => (coord, ClueKind::Dart),
+        }
+    }
+}
+
+// SplitMix64, which is plenty for picking colorings and clue orders, and keeps generated puzzles
+// reproducible from their seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+pub fn generate(
+    options: &GeneratorOptions,
+    solve_options: SolveOptions,
+) -> Result<GeneratedPuzzle, String> {
+    if options.rows == 0 || options.cols == 0 {
+        return Err("the grid must have at least one square".to_string());
+    }
+    if options.clues.is_empty() {
+        return Err("no clue kinds are enabled".to_string());
+    }
+    let mut rng = Rng::new(options.seed);
+    let mut best: Option<GeneratedPuzzle> = None;
+    for _ in 0..options.attempts.max(1) {
+        let Some(puzzle) = generate_once(options, &mut rng, solve_options)? else {
+            continue;
+        };
+        let distance = |puzzle: &GeneratedPuzzle| (puzzle.difficulty - options.difficulty).abs();
+        if best
+            .as_ref()
+            .is_none_or(|best| distance(&puzzle) < distance(best))
+        {
+            best = Some(puzzle);
+        }
+    }
+    best.ok_or_else(|| "every attempt timed out".to_string())
+}
+
+// One candidate puzzle, or None if a check timed out or the clues allow several solutions.
+fn generate_once(
+    options: &GeneratorOptions,
+    rng: &mut Rng,
+    solve_options: SolveOptions,
+) -> Result<Option<GeneratedPuzzle>, String> {
+    let mut base = Grid::new(options.rows, options.cols);
+    for rule in &options.rules {
+        base.add_rule(rule.clone());
+    }
+    let Some(solution) = random_coloring(&base, rng, solve_options)? else {
+        return Ok(None);
+    };
+
+    let mut clues = all_clues(&solution, &options.clues, rng);
+    rng.shuffle(&mut clues);
+    let order = clues.iter().map(Clue::key).collect::<Vec<_>>();
+    let clued = with_clues(&base, &clues);
+    let grid = match minimizer::minimize_in_order(&clued, &order, solve_options) {
+        MinimizeResult::Minimized(minimized) => minimized.grid,
+        // Numbers alone may not pin down the coloring.
+        MinimizeResult::Multiple | MinimizeResult::Unknown => return Ok(None),
+        MinimizeResult::Unsolvable => return Err("the clues contradict their coloring".to_string()),
+    };
+    // A puzzle the trace cannot finish has no meaningful score.
+    let trace = difficulty::trace_difficulty(&grid, solve_options);
+    if !trace.is_solved() {
+        return Ok(None);
+    }
+    let difficulty = trace.score();
+    Ok(Some(GeneratedPuzzle {
+        grid,
+        solution,
+        difficulty,
+    }))
+}
+
+// Colors the squares one at a time in random order, each with a random color unless only the
+// other one still has a solution.
+fn random_coloring(
+    grid: &Grid,
+    rng: &mut Rng,
+    options: SolveOptions,
+) -> Result<Option<Grid>, String> {
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    let colors = with_session(backend, &prepared, options.timeout, false, |session| {
+        let mut colors = match session.check(&[]) {
+            CheckResult::Sat => session.model(),
+            CheckResult::Unsat => return Err("the rules allow no coloring".to_string()),
+            CheckResult::Unknown => return Ok(None),
+        };
+        let mut order = (0..colors.len()).collect::<Vec<_>>();
+        rng.shuffle(&mut order);
+        let mut assumptions = Vec::new();
+        for index in order {
+            let color = if rng.below(2) == 0 {
+                Color::Light
+            } else {
+                Color::Dark
+            };
+            let literal = Literal::Color(SquareIndex(index), color);
+            // The last model may already show the color is possible.
+            if colors[index] != color {
+                let mut attempt = assumptions.clone();
+                attempt.push(literal);
+                match session.check(&attempt) {
+                    CheckResult::Sat => colors = session.model(),
+                    CheckResult::Unsat => {
+                        assumptions.push(Literal::Color(SquareIndex(index), color.opposite()));
+                        continue;
+                    }
+                    CheckResult::Unknown => return Ok(None),
+                }
+            }
+            assumptions.push(literal);
+        }
+        Ok(Some(colors))
+    })?;
+    Ok(colors.map(|colors| colored_grid(grid, &prepared, &colors)))
+}
+
+// Every clue the solution supports: each square's color, plus one number of a random enabled kind.
+fn all_clues(solution: &Grid, kinds: &[ClueKind], rng: &mut Rng) -> Vec<Clue> {
+    let regions = Regions::new(solution);
+    let numbers = kinds
+        .iter()
+        .filter(|kind| **kind != ClueKind::Given)
+        .collect::<Vec<_>>();
+    let mut clues = Vec::new();
+    for (coord, square) in solution.squares() {
+        let own = square.color.unwrap();
+        if kinds.contains(&ClueKind::Given) {
+            clues.push(Clue::Given(coord, own));
+        }
+        if numbers.is_empty() {
+            continue;
+        }
+        match numbers[rng.below(numbers.len())] {
+            ClueKind::AreaNumber => clues.push(Clue::AreaNumber(coord, regions.size_at(coord))),
+            ClueKind::Viewpoint => {
+                let visible = 1 + Direction::ALL
+                    .iter()
+                    .map(|&direction| {
+                        ray(solution, coord, direction)
+                            .into_iter()
+                            .take_while(|&other| color_at(solution, other) == Some(own))
+                            .count()
+                    })
+                    .sum::<usize>();
+                clues.push(Clue::Viewpoint(coord, visible));
+            }
+            ClueKind::Dart => {
+                let directions = Direction::ALL
+                    .into_iter()
+                    .filter(|&direction| !dart_squares(solution, coord, direction).is_empty())
+                    .collect::<Vec<_>>();
+                if directions.is_empty() {
+                    continue;
+                }
+                let direction = directions[rng.below(directions.len())];
+                let count = dart_squares(solution, coord, direction)
+                    .into_iter()
+                    .filter(|&other| color_at(solution, other) == Some(own.opposite()))
+                    .count();
+                clues.push(Clue::Dart(coord, direction, count, own));
+            }
+            ClueKind::Given => unreachable!(),
+        }
+    }
+    clues
+}
+
+fn with_clues(base: &Grid, clues: &[Clue]) -> Grid {
+    let mut grid = base.clone();
+    let (mut area, mut viewpoint, mut dart) = (false, false, false);
+    for &clue in clues {
+        match clue {
+            Clue::Given(coord, color) => grid.set_color(coord.i as usize, coord.j as usize, color),
+            Clue::AreaNumber(coord, number) => {
+                grid.set_area_number(coord.i as usize, coord.j as usize, number);
+                area = true;
+            }
+            Clue::Viewpoint(coord, count) => {
+                grid.visible_count(coord.i as usize, coord.j as usize, count);
+                viewpoint = true;
+            }
+            Clue::Dart(coord, direction, count, color) => {
+                grid.dart_number(coord.i as usize, coord.j as usize, direction, count, color);
+                dart = true;
+            }
+        }
+    }
+    if area {
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+    }
+    if viewpoint {
+        grid.add_rule(Rule::VisibleCellCount);
+    }
+    if dart {
+        grid.add_rule(Rule::DartNumbers);
+    }
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::checker::check_grid;
+    use crate::solver::{check_uniqueness, try_solve_grid, GridSolveResult, Uniqueness};
+
+    fn generator_options(seed: u64) -> GeneratorOptions {
+        GeneratorOptions {
+            rows: 2,
+            cols: 3,
+            rules: vec![Rule::ConnectAll(Color::Dark)],
+            clues: vec![ClueKind::Given, ClueKind::AreaNumber],
+            difficulty: 0.0,
+            attempts: 3,
+            seed,
+        }
+    }
+
+    // Squares do not compare, but their debug output does.
+    fn squares(grid: &Grid) -> String {
+        format!("{:?}", grid.squares().collect::<Vec<_>>())
+    }
+
+    fn colors(grid: &Grid) -> Vec<Option<Color>> {
+        grid.squares().map(|(_, square)| square.color).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_puzzle() {
+        for options in test_options() {
+            let first = generate(&generator_options(7), options).unwrap();
+            let second = generate(&generator_options(7), options).unwrap();
+            assert_eq!(squares(&first.grid), squares(&second.grid));
+            assert_eq!(squares(&first.solution), squares(&second.solution));
+            assert_eq!(first.difficulty, second.difficulty);
+        }
+    }
+
+    #[test]
+    fn puzzles_have_their_solution_only() {
+        for options in test_options() {
+            for seed in 0..3 {
+                let generator_options = GeneratorOptions {
+                    attempts: 1,
+                    ..generator_options(seed)
+                };
+                let puzzle = generate(&generator_options, options).unwrap();
+                assert!(colors(&puzzle.solution).iter().all(Option::is_some));
+                assert!(check_grid(&puzzle.solution).is_valid());
+                let Uniqueness::Unique(solved) = check_uniqueness(&puzzle.grid, options) else {
+                    panic!("seed {} has no unique solution", seed);
+                };
+                assert_eq!(colors(&solved), colors(&puzzle.solution));
+            }
+        }
+    }
+
+    #[test]
+    fn the_closest_attempt_is_kept() {
+        for options in test_options() {
+            // The attempts `generate` makes, replayed with the same random numbers.
+            let mut rng = Rng::new(3);
+            let difficulties = (0..3)
+                .filter_map(|_| generate_once(&generator_options(3), &mut rng, options).unwrap())
+                .map(|puzzle| puzzle.difficulty)
+                .collect::<Vec<_>>();
+            assert!(!difficulties.is_empty());
+            let easiest = difficulties.iter().copied().fold(f64::INFINITY, f64::min);
+            let hardest = difficulties.iter().copied().fold(0.0, f64::max);
+
+            let aiming_at = |difficulty| GeneratorOptions {
+                difficulty,
+                ..generator_options(3)
+            };
+            assert_eq!(
+                generate(&aiming_at(0.0), options).unwrap().difficulty,
+                easiest
+            );
+            assert_eq!(
+                generate(&aiming_at(100.0), options).unwrap().difficulty,
+                hardest
+            );
+        }
+    }
+
+    #[test]
+    fn darts_count_past_holes() {
+        let mut solution = Grid::new(1, 3);
+        solution.color_dark(0, 0);
+        solution.remove_square(0, 1);
+        solution.color_light(0, 2);
+        let clues = all_clues(&solution, &[ClueKind::Dart], &mut Rng::new(1));
+        let left = Coord { i: 0, j: 0 };
+        let right = Coord { i: 0, j: 2 };
+        assert_eq!(
+            clues,
+            [
+                Clue::Dart(left, Direction::Right, 1, Color::Dark),
+                Clue::Dart(right, Direction::Left, 1, Color::Light)
+            ]
+        );
+
+        // The checker and every backend read the darts the same way.
+        let clued = with_clues(&solution, &clues);
+        assert!(check_grid(&clued).is_valid());
+        for options in test_options() {
+            assert!(matches!(
+                try_solve_grid(&clued, options),
+                GridSolveResult::Solved(_)
+            ));
+        }
+    }
+}
@@ -1,10 +1,18 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Debug, Display},
+};
 
 #[derive(Clone)]
 pub struct Grid {
     size: Coord,
     squares: Vec<Vec<Square>>,
     rules: Vec<Rule>,
+    // Galaxy centers on the (2 * rows + 1) x (2 * cols + 1) half-cell lattice, where odd
+    // coordinates are square centers and even ones are grid lines.
+    galaxies: Vec<Coord>,
+    // Lotus positions on the same lattice, with the orientation of their mirror axis.
+    lotuses: Vec<(Coord, Axis)>,
 }
 
 impl Debug for Grid {
@@ -28,7 +36,58 @@ impl Debug for Grid {
     }
 }
 
+// A reason a puzzle has no solution that can be seen from the givens alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Impossible {
+    // Regions of `color` must be empty, yet `coord` is given that color.
+    EmptyRegionSize {
+        coord: Coord,
+        color: Color,
+    },
+    // The region of the given square at `coord` needs `size` squares, but only `available` squares
+    // are reachable without crossing a square given the opposite color.
+    RegionTooSmall {
+        coord: Coord,
+        color: Color,
+        size: usize,
+        available: usize,
+    },
+}
+
+impl Display for Impossible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "impossible puzzle: ")?;
+        match self {
+            Impossible::EmptyRegionSize { coord, color } => write!(
+                f,
+                "{:?} regions must have 0 squares, but ({}, {}) is given {:?}",
+                color, coord.i, coord.j, color
+            ),
+            Impossible::RegionTooSmall {
+                coord,
+                color,
+                size,
+                available,
+            } => write!(
+                f,
+                "{:?} regions must have {} squares, but the one at ({}, {}) can reach only {}",
+                color, size, coord.i, coord.j, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Impossible {}
+
 impl Grid {
+    pub fn rows(&self) -> usize {
+        self.size.i as usize
+    }
+
+    pub fn cols(&self) -> usize {
+        self.size.j as usize
+    }
+
     pub fn square(&self, coord: Coord) -> Option<&Square> {
         if coord.i < 0
             || coord.i >= self.size.i
@@ -70,12 +129,16 @@ impl Grid {
                         area_number: None,
                         visible_count: None,
                         dart_number: None,
+                        letter: None,
+                        myopia: None,
                     };
                     cols
                 ];
                 rows
             ],
             rules: Vec::new(),
+            galaxies: Vec::new(),
+            lotuses: Vec::new(),
         }
     }
 
@@ -99,6 +162,23 @@ impl Grid {
         self.squares[row][col].color = Some(color);
     }
 
+    pub fn clear_color(&mut self, row: usize, col: usize) {
+        self.squares[row][col].color = None;
+    }
+
+    pub fn clear_area_number(&mut self, row: usize, col: usize) {
+        self.squares[row][col].area_number = None;
+    }
+
+    pub fn clear_visible_count(&mut self, row: usize, col: usize) {
+        self.squares[row][col].visible_count = None;
+    }
+
+    pub fn clear_dart_number(&mut self, row: usize, col: usize) {
+        self.squares[row][col].dart_number = None;
+        self.squares[row][col].color = None;
+    }
+
     pub fn join_right(&mut self, row: usize, col: usize) {
         self.squares[row][col].merge_with_right = true;
     }
@@ -111,6 +191,10 @@ impl Grid {
         self.rules.push(rule);
     }
 
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
     pub fn dart_number(
         &mut self,
         row: usize,
@@ -126,6 +210,113 @@ impl Grid {
     pub fn visible_count(&mut self, row: usize, col: usize, count: usize) {
         self.squares[row][col].visible_count = Some(count);
     }
+
+    pub fn set_letter(&mut self, row: usize, col: usize, letter: usize) {
+        self.squares[row][col].letter = Some(letter);
+    }
+
+    pub fn set_myopia(&mut self, row: usize, col: usize, arrows: &[Direction]) {
+        self.squares[row][col].myopia = Some(
+            arrows
+                .iter()
+                .fold(0, |mask, direction| mask | direction.bit()),
+        );
+    }
+
+    pub fn add_galaxy(&mut self, half_row: usize, half_col: usize) {
+        self.galaxies.push(Coord {
+            i: half_row as isize,
+            j: half_col as isize,
+        });
+    }
+
+    pub fn galaxies(&self) -> &[Coord] {
+        &self.galaxies
+    }
+
+    pub fn add_lotus(&mut self, half_row: usize, half_col: usize, axis: Axis) {
+        self.lotuses.push((
+            Coord {
+                i: half_row as isize,
+                j: half_col as isize,
+            },
+            axis,
+        ));
+    }
+
+    pub fn lotuses(&self) -> &[(Coord, Axis)] {
+        &self.lotuses
+    }
+
+    // Catches fixed region sizes that cannot be met given the colored squares and holes, which the
+    // solver would otherwise only report as unsolvable.
+    pub fn validate(&self) -> Result<(), Impossible> {
+        for rule in &self.rules {
+            let &Rule::RegionFixedSize(color, size) = rule else {
+                continue;
+            };
+            let mut reached = HashSet::new();
+            for (coord, square) in self.squares() {
+                if square.color != Some(color) || reached.contains(&coord) {
+                    continue;
+                }
+                if size == 0 {
+                    return Err(Impossible::EmptyRegionSize { coord, color });
+                }
+                let mut available = 0;
+                let mut stack = vec![coord];
+                reached.insert(coord);
+                while let Some(current) = stack.pop() {
+                    available += 1;
+                    let neighbors = [
+                        current.left(),
+                        current.right(self.size),
+                        current.above(),
+                        current.below(self.size),
+                    ];
+                    for neighbor in neighbors.into_iter().flatten() {
+                        let open = self
+                            .square(neighbor)
+                            .is_some_and(|square| square.color != Some(color.opposite()));
+                        if open && reached.insert(neighbor) {
+                            stack.push(neighbor);
+                        }
+                    }
+                }
+                if available < size {
+                    return Err(Impossible::RegionTooSmall {
+                        coord,
+                        color,
+                        size,
+                        available,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // The existing squares touching a point on the half-cell lattice: the square itself for a
+    // center, the two squares on either side of an edge, or the four around a vertex.
+    fn squares_at(&self, point: Coord) -> Vec<Coord> {
+        let around = |half: isize| {
+            if half % 2 == 1 {
+                vec![(half - 1) / 2]
+            } else {
+                vec![half / 2 - 1, half / 2]
+            }
+        };
+        let mut squares = Vec::new();
+        for i in around(point.i) {
+            for j in around(point.j) {
+                let coord = Coord { i, j };
+                if self.square(coord).is_some() {
+                    squares.push(coord);
+                }
+            }
+        }
+        squares
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -137,6 +328,10 @@ pub struct Square {
     pub area_number: Option<usize>,
     pub visible_count: Option<usize>,
     pub dart_number: Option<(Direction, usize)>,
+    // Letters are numbered from 0, which the UI shows as 'A'.
+    pub letter: Option<usize>,
+    // Myopia arrows as a mask of `Direction::bit`s.
+    pub myopia: Option<u8>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -147,6 +342,36 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    // The bit for this direction in a myopia mask, as stored in pdata.
+    pub fn bit(self) -> u8 {
+        match self {
+            Direction::Up => 1,
+            Direction::Down => 2,
+            Direction::Left => 4,
+            Direction::Right => 8,
+        }
+    }
+}
+
+// The mirror axis of a lotus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    Horizontal,
+    Vertical,
+    // Top left to bottom right.
+    Diagonal,
+    // Bottom left to top right.
+    AntiDiagonal,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct SquareIndex(pub usize);
 
@@ -172,7 +397,7 @@ pub struct Coord {
 }
 
 impl Coord {
-    fn right(self, size: Coord) -> Option<Coord> {
+    pub(crate) fn right(self, size: Coord) -> Option<Coord> {
         if self.j < size.j - 1 {
             Some(Coord {
                 i: self.i,
@@ -183,7 +408,7 @@ impl Coord {
         }
     }
 
-    fn below(self, size: Coord) -> Option<Coord> {
+    pub(crate) fn below(self, size: Coord) -> Option<Coord> {
         if self.i < size.i - 1 {
             Some(Coord {
                 i: self.i + 1,
@@ -194,7 +419,7 @@ impl Coord {
         }
     }
 
-    fn left(self) -> Option<Coord> {
+    pub(crate) fn left(self) -> Option<Coord> {
         if self.j > 0 {
             Some(Coord {
                 i: self.i,
@@ -205,7 +430,7 @@ impl Coord {
         }
     }
 
-    fn above(self) -> Option<Coord> {
+    pub(crate) fn above(self) -> Option<Coord> {
         if self.i > 0 {
             Some(Coord {
                 i: self.i - 1,
@@ -216,7 +441,7 @@ impl Coord {
         }
     }
 
-    fn neighbor(self, direction: Direction, size: Coord) -> Option<Coord> {
+    pub(crate) fn neighbor(self, direction: Direction, size: Coord) -> Option<Coord> {
         match direction {
             Direction::Up => self.above(),
             Direction::Down => self.below(size),
@@ -289,6 +514,18 @@ impl GridPattern {
         result
     }
 
+    // Whether the two patterns are the same up to translation, rotation and reflection.
+    pub fn is_congruent(&self, other: &GridPattern) -> bool {
+        if self.pattern.len() != other.pattern.len() || self.pattern.is_empty() {
+            return self.pattern.len() == other.pattern.len();
+        }
+        let canonical = self.clone().canonicalize();
+        other
+            .all_rotations_and_reflections()
+            .into_iter()
+            .any(|pattern| pattern.canonicalize() == canonical)
+    }
+
     pub fn offset(&self, by: Coord) -> GridPattern {
         let mut pattern = self.pattern.clone();
         for (coord, _) in &mut pattern {
@@ -301,10 +538,10 @@ impl GridPattern {
 
 impl Debug for GridPattern {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut min_x = std::isize::MAX;
-        let mut min_y = std::isize::MAX;
-        let mut max_x = std::isize::MIN;
-        let mut max_y = std::isize::MIN;
+        let mut min_x = isize::MAX;
+        let mut min_y = isize::MAX;
+        let mut max_x = isize::MIN;
+        let mut max_y = isize::MIN;
         for (coord, _) in &self.pattern {
             min_x = min_x.min(coord.i);
             min_y = min_y.min(coord.j);
@@ -347,12 +584,16 @@ pub enum Rule {
     ExactlyOneNumberPerRegion(Color),
     VisibleCellCount,
     RegionsHaveDifferentShapes(Color),
+    RegionsHaveSameShape(Color),
     NumbersAreOffByOne,
     DartNumbers,
-    // TODO: lotus, galaxy, letters
+    Galaxy,
+    Lotus,
+    Letters,
+    Myopia,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PreparedRule {
     SquareIsColor(SquareIndex, Color),
     SquaresAreSameColor(SquareIndex, SquareIndex),
@@ -365,7 +606,23 @@ pub enum PreparedRule {
     RegionAreaEqualsEither(SquareIndex, usize, usize),
     VisibleCellCountEither(SquareIndex, usize, usize),
     RegionsHaveDifferentShapes(Color),
+    RegionsHaveSameShape(Color),
     ColorCountInSet(usize, Color, Vec<SquareIndex>),
+    // The region containing all of the first squares is symmetric under the given mapping of
+    // squares to their images; a square whose image does not exist cannot be in the region.
+    SymmetricRegion(Vec<SquareIndex>, Vec<(SquareIndex, Option<SquareIndex>)>),
+    // Squares grouped by letter; each group shares a region, distinct from the other groups.
+    Letters(Vec<Vec<SquareIndex>>),
+    // For each direction: whether the square has an arrow that way, and how many squares there
+    // are before the edge of the grid or a hole.
+    Myopia(SquareIndex, Vec<(Direction, bool, usize)>),
+}
+
+// The squares a galaxy or lotus sits on, and each square's image under its symmetry, if that is an
+// existing square.
+pub(crate) struct Symmetry {
+    pub anchors: Vec<Coord>,
+    pub images: Vec<(Coord, Option<Coord>)>,
 }
 
 #[derive(Debug)]
@@ -378,12 +635,41 @@ pub struct PreparedSquare {
     pub below: Option<SquareIndex>,
 }
 
+// Where a prepared rule comes from, in terms a player would recognize.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RuleRef {
+    // The given color of a square.
+    Given(Coord),
+    // Two merged squares.
+    Merge(Coord, Coord),
+    // A rule by its index in `Grid::rules`, narrowed down to a single clue square for rules
+    // with one such clue per prepared rule, like area numbers.
+    Rule { index: usize, clue: Option<Coord> },
+}
+
 #[derive(Debug)]
 pub struct PreparedGrid {
     pub size: Coord,
     pub square_indexes: HashMap<Coord, SquareIndex>,
     pub squares: Vec<PreparedSquare>,
     pub rules: Vec<PreparedRule>,
+    // The origin of each of `rules`.
+    pub sources: Vec<RuleRef>,
+}
+
+impl PreparedRule {
+    // The clue square this rule is about, if it is about a single one.
+    fn clue(&self) -> Option<SquareIndex> {
+        match self {
+            PreparedRule::RegionAreaEqualsNumber(index, _)
+            | PreparedRule::VisibleCellCount(index, _)
+            | PreparedRule::RegionAreaEqualsEither(index, _, _)
+            | PreparedRule::VisibleCellCountEither(index, _, _)
+            | PreparedRule::Myopia(index, _) => Some(*index),
+            PreparedRule::SymmetricRegion(anchors, _) => anchors.first().copied(),
+            _ => None,
+        }
+    }
 }
 
 impl Grid {
@@ -454,7 +740,67 @@ impl Grid {
             Rule::RegionsHaveDifferentShapes(color) => {
                 prepared.push(PreparedRule::RegionsHaveDifferentShapes(*color));
             }
+            Rule::RegionsHaveSameShape(color) => {
+                prepared.push(PreparedRule::RegionsHaveSameShape(*color));
+            }
             Rule::NumbersAreOffByOne => {}
+            Rule::Galaxy | Rule::Lotus => {
+                for symmetry in self.symmetries(rule) {
+                    if symmetry.anchors.is_empty() {
+                        continue;
+                    }
+                    prepared.push(PreparedRule::SymmetricRegion(
+                        symmetry
+                            .anchors
+                            .iter()
+                            .map(|coord| square_indexes[coord])
+                            .collect(),
+                        symmetry
+                            .images
+                            .iter()
+                            .map(|(coord, image)| {
+                                (
+                                    square_indexes[coord],
+                                    image.map(|image| square_indexes[&image]),
+                                )
+                            })
+                            .collect(),
+                    ));
+                }
+            }
+            Rule::Letters => {
+                let mut groups = BTreeMap::<usize, Vec<SquareIndex>>::new();
+                for (coord, square) in self.squares() {
+                    if let Some(letter) = square.letter {
+                        groups
+                            .entry(letter)
+                            .or_default()
+                            .push(square_indexes[&coord]);
+                    }
+                }
+                prepared.push(PreparedRule::Letters(groups.into_values().collect()));
+            }
+            Rule::Myopia => {
+                for (coord, square) in self.squares() {
+                    if let Some(mask) = square.myopia {
+                        let rays = Direction::ALL
+                            .iter()
+                            .map(|&direction| {
+                                let mut length = 0;
+                                let mut current = coord.neighbor(direction, self.size);
+                                while let Some(coord) =
+                                    current.filter(|coord| square_indexes.contains_key(coord))
+                                {
+                                    length += 1;
+                                    current = coord.neighbor(direction, self.size);
+                                }
+                                (direction, mask & direction.bit() != 0, length)
+                            })
+                            .collect();
+                        prepared.push(PreparedRule::Myopia(square_indexes[&coord], rays));
+                    }
+                }
+            }
             Rule::DartNumbers => {
                 for (coord, square) in self.squares() {
                     if let Some((direction, number)) = square.dart_number {
@@ -479,6 +825,60 @@ impl Grid {
         }
     }
 
+    // The symmetric regions required by a galaxy or lotus rule, one per symbol.
+    pub(crate) fn symmetries(&self, rule: &Rule) -> Vec<Symmetry> {
+        match rule {
+            Rule::Galaxy => self
+                .galaxies
+                .iter()
+                .map(|&galaxy| self.symmetry(galaxy, |di, dj| (-di, -dj)))
+                .collect(),
+            Rule::Lotus => self
+                .lotuses
+                .iter()
+                .map(|&(lotus, axis)| {
+                    let mirror = match axis {
+                        Axis::Horizontal => |di: isize, dj: isize| (-di, dj),
+                        Axis::Vertical => |di: isize, dj: isize| (di, -dj),
+                        Axis::Diagonal => |di: isize, dj: isize| (dj, di),
+                        Axis::AntiDiagonal => |di: isize, dj: isize| (-dj, -di),
+                    };
+                    self.symmetry(lotus, mirror)
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // `transform` maps the offset of a square's center from `point`, in half-cell units, to the
+    // offset of its image.
+    fn symmetry(
+        &self,
+        point: Coord,
+        transform: impl Fn(isize, isize) -> (isize, isize),
+    ) -> Symmetry {
+        let image = |coord: Coord| {
+            let (di, dj) = transform(2 * coord.i + 1 - point.i, 2 * coord.j + 1 - point.j);
+            let (i, j) = (point.i + di, point.j + dj);
+            // A diagonal axis through an odd point maps centers onto grid lines.
+            if i.rem_euclid(2) != 1 || j.rem_euclid(2) != 1 {
+                return None;
+            }
+            let image = Coord {
+                i: (i - 1) / 2,
+                j: (j - 1) / 2,
+            };
+            self.square(image).map(|_| image)
+        };
+        Symmetry {
+            anchors: self.squares_at(point),
+            images: self
+                .squares()
+                .map(|(coord, _)| (coord, image(coord)))
+                .collect(),
+        }
+    }
+
     fn prepare_square(
         &self,
         square: &Square,
@@ -508,10 +908,8 @@ impl Grid {
         let mut prepared_squares: Vec<PreparedSquare> = Default::default();
         let mut square_indexes: HashMap<Coord, SquareIndex> = Default::default();
 
-        let mut next_index = 0;
-        for (coord, _) in self.squares() {
+        for (next_index, (coord, _)) in self.squares().enumerate() {
             let index = SquareIndex(next_index);
-            next_index += 1;
             square_indexes.insert(coord, index);
         }
 
@@ -551,17 +949,47 @@ impl Grid {
             .iter()
             .any(|rule| matches!(rule, Rule::NumbersAreOffByOne));
 
+        let coords = self.squares().map(|(coord, _)| coord).collect::<Vec<_>>();
+        // Dart counts only name the squares they count, so their clues are found by position.
+        let darts = self
+            .squares()
+            .filter(|(_, square)| square.dart_number.is_some())
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
         let mut rules = Vec::new();
-        for rule in &self.rules {
+        let mut sources = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
             self.prepare_rule(rule, &square_indexes, off_by_one, &mut rules);
+            if matches!(rule, Rule::DartNumbers) {
+                sources.extend(darts.iter().map(|&dart| RuleRef::Rule {
+                    index,
+                    clue: Some(dart),
+                }));
+                continue;
+            }
+            sources.extend(rules[sources.len()..].iter().map(|prepared| RuleRef::Rule {
+                index,
+                clue: prepared.clue().map(|clue| coords[clue.0]),
+            }));
         }
         for (coord, square) in self.squares() {
             self.prepare_square(&square, coord, &square_indexes, &mut rules);
+            sources.extend(
+                rules[sources.len()..]
+                    .iter()
+                    .map(|prepared| match prepared {
+                        PreparedRule::SquaresAreSameColor(a, b) => {
+                            RuleRef::Merge(coords[a.0], coords[b.0])
+                        }
+                        _ => RuleRef::Given(coord),
+                    }),
+            );
         }
 
         PreparedGrid {
             squares: prepared_squares,
             rules,
+            sources,
             square_indexes,
             size: self.size,
         }
@@ -0,0 +1,268 @@
+use crate::backend::{with_session, CheckResult, Literal, Session};
+use crate::grid::{Color, Coord, Grid, PreparedGrid, RuleRef};
+use crate::solver::SolveOptions;
+
+// Finds the next square a player can fill in, along with the rules that force it. Each prepared
+// rule is asserted under its own literal, so that the unsat core of "this square has the other
+// color" names the rules responsible.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hint {
+    pub cell: Coord,
+    pub color: Color,
+    // Rules and givens that force the color together; none of them can be left out.
+    pub reasons: Vec<RuleRef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HintResult {
+    Hint(Hint),
+    // Every square is already colored.
+    Solved,
+    // No uncolored square is forced, so the puzzle has several solutions from here.
+    NoForcedSquare,
+    Unsolvable,
+    Unknown,
+}
+
+// Of all forced squares, hints the one with the fewest reasons.
+pub fn next_hint(grid: &Grid, options: SolveOptions) -> HintResult {
+    if grid.squares().all(|(_, square)| square.color.is_some()) {
+        return HintResult::Solved;
+    }
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    with_session(backend, &prepared, options.timeout, true, |session| {
+        let rules = (0..prepared.rules.len())
+            .map(|rule| (Literal::Rule(rule), prepared.sources[rule]))
+            .collect::<Vec<_>>();
+        hint_in_session(session, grid, &prepared, &rules)
+    })
+}
+
+// Like `next_hint`, in a session where assuming the literals of `rules` enforces the rules of the
+// grid, each literal with where its rule comes from.
+pub(crate) fn hint_in_session(
+    session: &mut dyn Session,
+    grid: &Grid,
+    prepared: &PreparedGrid,
+    rules: &[(Literal, RuleRef)],
+) -> HintResult {
+    let uncolored = grid
+        .squares()
+        .filter(|(_, square)| square.color.is_none())
+        .map(|(coord, _)| coord)
+        .collect::<Vec<_>>();
+    if uncolored.is_empty() {
+        return HintResult::Solved;
+    }
+    let literals = rules
+        .iter()
+        .map(|&(literal, _)| literal)
+        .collect::<Vec<_>>();
+    let colors = match session.check(&literals) {
+        CheckResult::Unsat => return HintResult::Unsolvable,
+        CheckResult::Unknown => return HintResult::Unknown,
+        CheckResult::Sat => session.model(),
+    };
+    // A square can only be forced to its color in the first solution, and only if no other
+    // solution found along the way colors it differently.
+    let mut free = vec![false; colors.len()];
+    let mut unknown = false;
+    let mut best: Option<Hint> = None;
+    for coord in uncolored {
+        let index = prepared.square_indexes[&coord];
+        if free[index.0] {
+            continue;
+        }
+        let color = colors[index.0];
+        let opposite = Literal::Color(index, color.opposite());
+        let mut assumptions = literals.clone();
+        assumptions.push(opposite);
+        match session.check(&assumptions) {
+            CheckResult::Sat => {
+                for (n, other) in session.model().into_iter().enumerate() {
+                    free[n] |= other != colors[n];
+                }
+            }
+            CheckResult::Unknown => unknown = true,
+            CheckResult::Unsat => {
+                let core = session
+                    .core()
+                    .into_iter()
+                    .filter(|literal| *literal != opposite)
+                    .collect::<Vec<_>>();
+                let mut reasons = shrink_core(session, core, opposite)
+                    .iter()
+                    .filter_map(|literal| {
+                        rules
+                            .iter()
+                            .find(|(other, _)| other == literal)
+                            .map(|&(_, source)| source)
+                    })
+                    .collect::<Vec<_>>();
+                reasons.sort();
+                reasons.dedup();
+                if best
+                    .as_ref()
+                    .is_none_or(|best| reasons.len() < best.reasons.len())
+                {
+                    best = Some(Hint {
+                        cell: coord,
+                        color,
+                        reasons,
+                    });
+                }
+            }
+        }
+    }
+    match best {
+        Some(hint) => HintResult::Hint(hint),
+        None if unknown => HintResult::Unknown,
+        None => HintResult::NoForcedSquare,
+    }
+}
+
+// Cores are not minimal, so this drops the rules the conflict with `opposite` does not need, one
+// at a time. A check that times out keeps its rule.
+fn shrink_core(
+    session: &mut dyn Session,
+    mut core: Vec<Literal>,
+    opposite: Literal,
+) -> Vec<Literal> {
+    let mut n = 0;
+    while n < core.len() {
+        let mut assumptions = core.clone();
+        assumptions.remove(n);
+        assumptions.push(opposite);
+        if session.check(&assumptions) == CheckResult::Unsat {
+            core.remove(n);
+        } else {
+            n += 1;
+        }
+    }
+    core
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::{GridPattern, Rule};
+
+    // Checks that the reasons force the hint together, and that none of them does without the
+    // others.
+    fn assert_needed(grid: &Grid, hint: &Hint, options: SolveOptions) {
+        let prepared = grid.prepare();
+        let backend = options.backend.backend();
+        with_session(backend, &prepared, options.timeout, true, |session| {
+            let opposite =
+                Literal::Color(prepared.square_indexes[&hint.cell], hint.color.opposite());
+            let mut check = |reasons: &[RuleRef]| {
+                let mut assumptions = (0..prepared.rules.len())
+                    .filter(|&rule| reasons.contains(&prepared.sources[rule]))
+                    .map(Literal::Rule)
+                    .collect::<Vec<_>>();
+                assumptions.push(opposite);
+                session.check(&assumptions)
+            };
+            assert_eq!(check(&hint.reasons), CheckResult::Unsat);
+            for n in 0..hint.reasons.len() {
+                let mut reasons = hint.reasons.clone();
+                reasons.remove(n);
+                assert_eq!(
+                    check(&reasons),
+                    CheckResult::Sat,
+                    "{:?} is not needed",
+                    hint.reasons[n]
+                );
+            }
+        })
+    }
+
+    fn hint(grid: &Grid, options: SolveOptions) -> Hint {
+        match next_hint(grid, options) {
+            HintResult::Hint(hint) => hint,
+            result => panic!("{:?}: no hint but {:?}", options.backend, result),
+        }
+    }
+
+    fn at(i: isize, j: isize) -> Coord {
+        Coord { i, j }
+    }
+
+    #[test]
+    fn reasons_are_shrunk_to_what_is_needed() {
+        // The area number and one given force the square next to it; the other rules and givens
+        // are there to end up in the first core.
+        let mut grid = Grid::new(2, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.add_rule(Rule::ConnectAll(Color::Dark));
+        grid.color_light(0, 0);
+        grid.set_area_number(0, 0, 1);
+        grid.color_dark(0, 2);
+        for j in 0..3 {
+            grid.color_dark(1, j);
+        }
+        for options in test_options() {
+            let hint = hint(&grid, options);
+            assert_eq!((hint.cell, hint.color), (at(0, 1), Color::Dark));
+            // The number with either the given light square or the dark one below it.
+            assert_eq!(hint.reasons.len(), 2);
+            assert!(hint.reasons.contains(&RuleRef::Rule {
+                index: 0,
+                clue: Some(at(0, 0))
+            }));
+            assert_needed(&grid, &hint, options);
+        }
+    }
+
+    #[test]
+    fn the_square_with_fewest_reasons_is_hinted() {
+        // The first square is forced by the ban and two givens, the last one by an area number
+        // and its given.
+        let mut grid = Grid::new(1, 6);
+        grid.add_rule(Rule::BanPattern(GridPattern {
+            pattern: (0..3).map(|j| (at(0, j), Color::Light)).collect(),
+        }));
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_light(0, 1);
+        grid.color_light(0, 2);
+        grid.remove_square(0, 3);
+        grid.color_light(0, 4);
+        grid.set_area_number(0, 4, 1);
+        for options in test_options() {
+            let hint = hint(&grid, options);
+            assert_eq!((hint.cell, hint.color), (at(0, 5), Color::Dark));
+            assert_eq!(hint.reasons.len(), 2);
+            assert_needed(&grid, &hint, options);
+        }
+
+        grid.color_dark(0, 5);
+        for options in test_options() {
+            let hint = hint(&grid, options);
+            assert_eq!((hint.cell, hint.color), (at(0, 0), Color::Dark));
+            assert_eq!(hint.reasons.len(), 3);
+            assert_needed(&grid, &hint, options);
+        }
+    }
+
+    #[test]
+    fn results_without_a_hint() {
+        let mut free = Grid::new(1, 2);
+        free.add_rule(Rule::RegionAreaEqualsNumber);
+        free.color_light(0, 0);
+        let mut solved = free.clone();
+        solved.color_dark(0, 1);
+        let mut unsolvable = Grid::new(1, 3);
+        unsolvable.add_rule(Rule::RegionAreaEqualsNumber);
+        unsolvable.color_light(0, 0);
+        unsolvable.color_light(0, 1);
+        unsolvable.set_area_number(0, 0, 1);
+        for options in test_options() {
+            assert_eq!(next_hint(&free, options), HintResult::NoForcedSquare);
+            assert_eq!(next_hint(&solved, options), HintResult::Solved);
+            assert_eq!(next_hint(&unsolvable, options), HintResult::Unsolvable);
+        }
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+use crate::grid::{Color, Coord, Direction, PreparedGrid, PreparedRule, SquareIndex};
+
+// The constraints of a prepared grid in terms every backend understands: the color of each square,
+// and primitives over regions and runs of squares that a backend lowers in its own way. Each
+// prepared rule becomes one or more constraints, so they can be looked at, simplified and written
+// out without a solver.
+
+// A quantity a constraint can compare with numbers or with each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Size {
+    // The number of squares in the region of the square.
+    Area(SquareIndex),
+    // The number of squares visible from the square in all four directions, itself included.
+    Visible(SquareIndex),
+    // The number of squares past the square in the direction that have its color, up to the
+    // first square of the other color, a hole or the edge of the grid.
+    Ray(SquareIndex, Direction),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Constraint {
+    // At least one of the squares has its color. An empty clause never holds.
+    Clause(Vec<(SquareIndex, Color)>),
+    SameColor(SquareIndex, SquareIndex),
+    // All squares of the color form a single region.
+    Connected(Color),
+    // Exactly this many of the squares have their color.
+    Count(Vec<(SquareIndex, Color)>, usize),
+    SizeIn(Size, Vec<usize>),
+    SizeBelow(Size, usize),
+    SizesEqual(Size, Size),
+    // The second size is more than the first, unless it has reached the limit.
+    SizeBeyond(Size, Size, usize),
+    // Every region of the color has this many squares.
+    RegionsOfSize(Color, usize),
+    // Every region of the color has the same shape, up to rotation and reflection. Backends
+    // check shapes against each model, but may require the regions to have the same size.
+    RegionsHaveSameShape(Color),
+    RegionsHaveDifferentShapes(Color),
+    // The squares are all in different regions, and every region of the color contains one.
+    OnePerRegion(Color, Vec<SquareIndex>),
+    SameRegion(Vec<SquareIndex>),
+    DifferentRegions(Vec<SquareIndex>),
+    // The region of the square contains the image of each of its squares. A square without an
+    // image is not in the region.
+    Mirrored(SquareIndex, Vec<(SquareIndex, Option<SquareIndex>)>),
+}
+
+pub struct ConstraintSet {
+    // Each constraint with the index of its prepared rule.
+    pub constraints: Vec<(Constraint, usize)>,
+    // The coordinates of each square, by square index.
+    pub coords: Vec<Coord>,
+}
+
+impl ConstraintSet {
+    pub fn new(grid: &PreparedGrid) -> ConstraintSet {
+        let mut coords = vec![Coord { i: 0, j: 0 }; grid.squares.len()];
+        for (coord, index) in &grid.square_indexes {
+            coords[index.0] = *coord;
+        }
+        let mut set = ConstraintSet {
+            constraints: Vec::new(),
+            coords,
+        };
+        for (origin, rule) in grid.rules.iter().enumerate() {
+            for constraint in rule_constraints(rule, grid) {
+                set.constraints.push((constraint, origin));
+            }
+        }
+        set
+    }
+
+    // Rewrites the constraints into fewer or smaller ones that hold exactly when they did. Each
+    // constraint stays with its prepared rule, so rules can still be enforced separately.
+    pub fn simplify(&mut self) {
+        let mut seen = HashSet::new();
+        let mut simplified = Vec::new();
+        for (constraint, origin) in std::mem::take(&mut self.constraints) {
+            for constraint in simplify_constraint(constraint) {
+                if seen.insert((constraint.clone(), origin)) {
+                    simplified.push((constraint, origin));
+                }
+            }
+        }
+        self.constraints = simplified;
+    }
+
+    pub fn describe(&self, constraint: &Constraint) -> String {
+        let square = |index: &SquareIndex| {
+            let coord = self.coords[index.0];
+            format!("({}, {})", coord.i, coord.j)
+        };
+        let squares = |indexes: &[SquareIndex]| {
+            let squares = indexes.iter().map(square).collect::<Vec<_>>();
+            format!("[{}]", squares.join(", "))
+        };
+        let cells = |cells: &[(SquareIndex, Color)]| {
+            let cells = cells
+                .iter()
+                .map(|(index, color)| format!("{} {}", square(index), color_name(*color)))
+                .collect::<Vec<_>>();
+            format!("[{}]", cells.join(", "))
+        };
+        let size = |size: &Size| match size {
+            Size::Area(index) => format!("area of {}", square(index)),
+            Size::Visible(index) => format!("visible from {}", square(index)),
+            Size::Ray(index, direction) => format!("ray {:?} from {}", direction, square(index)),
+        };
+        match constraint {
+            Constraint::Clause(clause) => match clause.as_slice() {
+                [] => "never holds".to_string(),
+                [(index, color)] => format!("{} is {}", square(index), color_name(*color)),
+                _ => format!("one of {}", cells(clause)),
+            },
+            Constraint::SameColor(a, b) => {
+                format!("{} and {} have the same color", square(a), square(b))
+            }
+            Constraint::Connected(color) => format!("{} squares are connected", color_name(*color)),
+            Constraint::Count(counted, count) => format!("exactly {} of {}", count, cells(counted)),
+            Constraint::SizeIn(measured, numbers) => {
+                format!("{} is one of {:?}", size(measured), numbers)
+            }
+            Constraint::SizeBelow(measured, limit) => {
+                format!("{} is below {}", size(measured), limit)
+            }
+            Constraint::SizesEqual(a, b) => format!("{} equals {}", size(a), size(b)),
+            Constraint::SizeBeyond(near, far, limit) => format!(
+                "{} is more than {} unless it is {}",
+                size(far),
+                size(near),
+                limit
+            ),
+            Constraint::RegionsOfSize(color, area) => {
+                format!("every {} region has {} squares", color_name(*color), area)
+            }
+            Constraint::RegionsHaveSameShape(color) => {
+                format!("every {} region has the same shape", color_name(*color))
+            }
+            Constraint::RegionsHaveDifferentShapes(color) => {
+                format!("every {} region has a different shape", color_name(*color))
+            }
+            Constraint::OnePerRegion(color, indexes) => format!(
+                "every {} region contains one of {}, each in a different region",
+                color_name(*color),
+                squares(indexes)
+            ),
+            Constraint::SameRegion(indexes) => format!("{} share a region", squares(indexes)),
+            Constraint::DifferentRegions(indexes) => {
+                format!("{} are in different regions", squares(indexes))
+            }
+            Constraint::Mirrored(index, images) => {
+                let images = images
+                    .iter()
+                    .map(|(from, to)| match to {
+                        Some(to) => format!("{} -> {}", square(from), square(to)),
+                        None => format!("{} -> none", square(from)),
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "the region of {} maps onto itself: {}",
+                    square(index),
+                    images.join(", ")
+                )
+            }
+        }
+    }
+
+    pub fn to_json(&self, constraint: &Constraint) -> Value {
+        let square = |index: &SquareIndex| {
+            let coord = self.coords[index.0];
+            json!([coord.i, coord.j])
+        };
+        let squares = |indexes: &[SquareIndex]| indexes.iter().map(square).collect::<Vec<_>>();
+        let cells = |cells: &[(SquareIndex, Color)]| {
+            cells
+                .iter()
+                .map(|(index, color)| {
+                    let coord = self.coords[index.0];
+                    json!([coord.i, coord.j, color_name(*color)])
+                })
+                .collect::<Vec<_>>()
+        };
+        let size = |size: &Size| match size {
+            Size::Area(index) => json!({ "area": square(index) }),
+            Size::Visible(index) => json!({ "visible": square(index) }),
+            Size::Ray(index, direction) => {
+                json!({ "ray": square(index), "direction": format!("{:?}", direction) })
+            }
+        };
+        match constraint {
+            Constraint::Clause(clause) => json!({ "kind": "clause", "cells": cells(clause) }),
+            Constraint::SameColor(a, b) => {
+                json!({ "kind": "same_color", "squares": [square(a), square(b)] })
+            }
+            Constraint::Connected(color) => {
+                json!({ "kind": "connected", "color": color_name(*color) })
+            }
+            Constraint::Count(counted, count) => {
+                json!({ "kind": "count", "cells": cells(counted), "count": count })
+            }
+            Constraint::SizeIn(measured, numbers) => {
+                json!({ "kind": "size_in", "size": size(measured), "numbers": numbers })
+            }
+            Constraint::SizeBelow(measured, limit) => {
+                json!({ "kind": "size_below", "size": size(measured), "limit": limit })
+            }
+            Constraint::SizesEqual(a, b) => {
+                json!({ "kind": "sizes_equal", "sizes": [size(a), size(b)] })
+            }
+            Constraint::SizeBeyond(near, far, limit) => json!({
+                "kind": "size_beyond",
+                "near": size(near),
+                "far": size(far),
+                "limit": limit,
+            }),
+            Constraint::RegionsOfSize(color, area) => {
+                json!({ "kind": "regions_of_size", "color": color_name(*color), "size": area })
+            }
+            Constraint::RegionsHaveSameShape(color) => {
+                json!({ "kind": "same_shape", "color": color_name(*color) })
+            }
+            Constraint::RegionsHaveDifferentShapes(color) => {
+                json!({ "kind": "different_shapes", "color": color_name(*color) })
+            }
+            Constraint::OnePerRegion(color, indexes) => json!({
+                "kind": "one_per_region",
+                "color": color_name(*color),
+                "squares": squares(indexes),
+            }),
+            Constraint::SameRegion(indexes) => {
+                json!({ "kind": "same_region", "squares": squares(indexes) })
+            }
+            Constraint::DifferentRegions(indexes) => {
+                json!({ "kind": "different_regions", "squares": squares(indexes) })
+            }
+            Constraint::Mirrored(index, images) => json!({
+                "kind": "mirrored",
+                "square": square(index),
+                "images": images
+                    .iter()
+                    .map(|(from, to)| json!([square(from), to.as_ref().map(square)]))
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::Light => "light",
+        Color::Dark => "dark",
+    }
+}
+
+// The simplified constraints of a single prepared rule.
+pub fn simplified_rule_constraints(rule: &PreparedRule, grid: &PreparedGrid) -> Vec<Constraint> {
+    rule_constraints(rule, grid)
+        .into_iter()
+        .flat_map(simplify_constraint)
+        .collect()
+}
+
+fn rule_constraints(rule: &PreparedRule, grid: &PreparedGrid) -> Vec<Constraint> {
+    match rule {
+        PreparedRule::SquareIsColor(index, color) => {
+            vec![Constraint::Clause(vec![(*index, *color)])]
+        }
+        PreparedRule::SquaresAreSameColor(a, b) => vec![Constraint::SameColor(*a, *b)],
+        PreparedRule::BanPattern(grid_pattern) => {
+            let mut clauses = Vec::new();
+            for i in 0..grid.size.i {
+                'outer: for j in 0..grid.size.j {
+                    let offset = grid_pattern.offset(Coord { i, j });
+                    let mut clause = Vec::new();
+                    for (coord, color) in &offset.pattern {
+                        match grid.square_indexes.get(coord) {
+                            Some(index) => clause.push((*index, color.opposite())),
+                            None => continue 'outer,
+                        }
+                    }
+                    clauses.push(Constraint::Clause(clause));
+                }
+            }
+            clauses
+        }
+        PreparedRule::ConnectAll(color) => vec![Constraint::Connected(*color)],
+        PreparedRule::RegionFixedSize(color, 0) => grid
+            .squares
+            .iter()
+            .map(|square| Constraint::Clause(vec![(square.index, color.opposite())]))
+            .collect(),
+        PreparedRule::RegionFixedSize(color, size) => {
+            vec![Constraint::RegionsOfSize(*color, *size)]
+        }
+        PreparedRule::ExactlyOneNumberPerRegion(color, numbered_squares) => {
+            vec![Constraint::OnePerRegion(*color, numbered_squares.clone())]
+        }
+        PreparedRule::RegionAreaEqualsNumber(index, number) => {
+            vec![Constraint::SizeIn(Size::Area(*index), vec![*number])]
+        }
+        PreparedRule::VisibleCellCount(index, number) => {
+            vec![Constraint::SizeIn(Size::Visible(*index), vec![*number])]
+        }
+        PreparedRule::RegionAreaEqualsEither(index, a, b) => {
+            vec![Constraint::SizeIn(Size::Area(*index), vec![*a, *b])]
+        }
+        PreparedRule::VisibleCellCountEither(index, a, b) => {
+            vec![Constraint::SizeIn(Size::Visible(*index), vec![*a, *b])]
+        }
+        PreparedRule::RegionsHaveDifferentShapes(color) => {
+            vec![Constraint::RegionsHaveDifferentShapes(*color)]
+        }
+        PreparedRule::RegionsHaveSameShape(color) => vec![Constraint::RegionsHaveSameShape(*color)],
+        PreparedRule::ColorCountInSet(count, color, set) => {
+            let cells = set.iter().map(|index| (*index, *color)).collect();
+            vec![Constraint::Count(cells, *count)]
+        }
+        PreparedRule::SymmetricRegion(anchors, images) => vec![
+            Constraint::SameRegion(anchors.clone()),
+            Constraint::Mirrored(anchors[0], images.clone()),
+        ],
+        PreparedRule::Letters(groups) => {
+            let mut constraints = groups
+                .iter()
+                .map(|group| Constraint::SameRegion(group.clone()))
+                .collect::<Vec<_>>();
+            constraints.push(Constraint::DifferentRegions(
+                groups.iter().map(|group| group[0]).collect(),
+            ));
+            constraints
+        }
+        PreparedRule::Myopia(index, rays) => {
+            // The run of same-colored squares in a direction ends in a color change unless it
+            // covers every square up to the edge, in which case that direction sees no change.
+            let ray = |direction| Size::Ray(*index, direction);
+            let mut constraints = Vec::new();
+            for &(direction_a, arrow_a, length_a) in rays {
+                if !arrow_a {
+                    continue;
+                }
+                constraints.push(Constraint::SizeBelow(ray(direction_a), length_a));
+                for &(direction_b, arrow_b, length_b) in rays {
+                    if direction_b == direction_a {
+                        continue;
+                    }
+                    if arrow_b {
+                        constraints
+                            .push(Constraint::SizesEqual(ray(direction_a), ray(direction_b)));
+                    } else {
+                        constraints.push(Constraint::SizeBeyond(
+                            ray(direction_a),
+                            ray(direction_b),
+                            length_b,
+                        ));
+                    }
+                }
+            }
+            if rays.iter().all(|&(_, arrow, _)| !arrow) {
+                for &(direction, _, length) in rays {
+                    constraints.push(Constraint::SizeIn(ray(direction), vec![length]));
+                }
+            }
+            constraints
+        }
+    }
+}
+
+fn simplify_constraint(constraint: Constraint) -> Vec<Constraint> {
+    match constraint {
+        Constraint::Clause(mut clause) => {
+            clause.sort_by_key(|&(index, color)| (index.0, color));
+            clause.dedup();
+            // A clause with both colors of a square always holds.
+            if clause.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                return Vec::new();
+            }
+            vec![Constraint::Clause(clause)]
+        }
+        Constraint::SameColor(a, b) if a == b => Vec::new(),
+        Constraint::Count(cells, count) if count == 0 || count == cells.len() => cells
+            .into_iter()
+            .map(|(index, color)| {
+                let color = if count == 0 { color.opposite() } else { color };
+                Constraint::Clause(vec![(index, color)])
+            })
+            .collect(),
+        Constraint::Count(cells, count) if count > cells.len() => {
+            vec![Constraint::Clause(Vec::new())]
+        }
+        Constraint::SizeIn(size, mut numbers) => {
+            numbers.sort();
+            numbers.dedup();
+            vec![Constraint::SizeIn(size, numbers)]
+        }
+        Constraint::SizesEqual(a, b) if a == b => Vec::new(),
+        Constraint::SameRegion(indexes) | Constraint::DifferentRegions(indexes)
+            if indexes.len() < 2 =>
+        {
+            Vec::new()
+        }
+        constraint => vec![constraint],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{Grid, GridPattern, Rule};
+
+    // A dark corner, a merged bottom row with an area number, connected dark squares and no all
+    // light 2x2.
+    fn small_grid() -> PreparedGrid {
+        let mut grid = Grid::new(2, 2);
+        grid.color_dark(0, 0);
+        grid.join_right(1, 0);
+        grid.set_area_number(1, 1, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.add_rule(Rule::ConnectAll(Color::Dark));
+        grid.add_rule(Rule::BanPattern(GridPattern::square2x2(
+            Color::Light,
+            Color::Light,
+            Color::Light,
+            Color::Light,
+        )));
+        grid.prepare()
+    }
+
+    fn at(index: usize) -> SquareIndex {
+        SquareIndex(index)
+    }
+
+    fn colorings(squares: usize) -> impl Iterator<Item = Vec<Color>> {
+        (0..1u32 << squares).map(move |bits| {
+            (0..squares)
+                .map(|index| match bits >> index & 1 {
+                    0 => Color::Light,
+                    _ => Color::Dark,
+                })
+                .collect()
+        })
+    }
+
+    // The region of each square, as the lowest square in it.
+    fn regions(grid: &PreparedGrid, colors: &[Color]) -> Vec<usize> {
+        let mut regions = (0..colors.len()).collect::<Vec<_>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for square in &grid.squares {
+                let index = square.index.0;
+                for neighbor in [square.left, square.right, square.above, square.below] {
+                    let Some(SquareIndex(neighbor)) = neighbor else {
+                        continue;
+                    };
+                    if colors[neighbor] == colors[index] && regions[neighbor] < regions[index] {
+                        regions[index] = regions[neighbor];
+                        changed = true;
+                    }
+                }
+            }
+        }
+        regions
+    }
+
+    fn size(grid: &PreparedGrid, colors: &[Color], size: &Size) -> usize {
+        let ray = |index: SquareIndex, direction| {
+            let mut count = 0;
+            let mut current = index;
+            loop {
+                let square = &grid.squares[current.0];
+                let next = match direction {
+                    Direction::Up => square.above,
+                    Direction::Down => square.below,
+                    Direction::Left => square.left,
+                    Direction::Right => square.right,
+                };
+                match next {
+                    Some(next) if colors[next.0] == colors[index.0] => {
+                        count += 1;
+                        current = next;
+                    }
+                    _ => return count,
+                }
+            }
+        };
+        match *size {
+            Size::Area(index) => {
+                let regions = regions(grid, colors);
+                regions.iter().filter(|&&r| r == regions[index.0]).count()
+            }
+            Size::Visible(index) => {
+                1 + [
+                    Direction::Up,
+                    Direction::Down,
+                    Direction::Left,
+                    Direction::Right,
+                ]
+                .into_iter()
+                .map(|direction| ray(index, direction))
+                .sum::<usize>()
+            }
+            Size::Ray(index, direction) => ray(index, direction),
+        }
+    }
+
+    fn holds(grid: &PreparedGrid, colors: &[Color], constraint: &Constraint) -> bool {
+        let regions = regions(grid, colors);
+        let size = |measured: &Size| size(grid, colors, measured);
+        match constraint {
+            Constraint::Clause(clause) => clause
+                .iter()
+                .any(|&(index, color)| colors[index.0] == color),
+            Constraint::SameColor(a, b) => colors[a.0] == colors[b.0],
+            Constraint::Connected(color) => {
+                let mut of_color = (0..colors.len())
+                    .filter(|&index| colors[index] == *color)
+                    .map(|index| regions[index]);
+                of_color
+                    .next()
+                    .is_none_or(|first| of_color.all(|region| region == first))
+            }
+            Constraint::Count(cells, count) => {
+                cells
+                    .iter()
+                    .filter(|&&(index, color)| colors[index.0] == color)
+                    .count()
+                    == *count
+            }
+            Constraint::SizeIn(measured, numbers) => numbers.contains(&size(measured)),
+            Constraint::SizeBelow(measured, limit) => size(measured) < *limit,
+            Constraint::SizesEqual(a, b) => size(a) == size(b),
+            Constraint::SizeBeyond(near, far, limit) => {
+                size(far) > size(near) || size(far) == *limit
+            }
+            Constraint::SameRegion(indexes) => indexes
+                .iter()
+                .all(|index| regions[index.0] == regions[indexes[0].0]),
+            Constraint::DifferentRegions(indexes) => {
+                let found = indexes
+                    .iter()
+                    .map(|index| regions[index.0])
+                    .collect::<HashSet<_>>();
+                found.len() == indexes.len()
+            }
+            constraint => unreachable!("not evaluated here: {:?}", constraint),
+        }
+    }
+
+    fn solutions(grid: &PreparedGrid, set: &ConstraintSet) -> Vec<Vec<Color>> {
+        colorings(grid.squares.len())
+            .filter(|colors| {
+                set.constraints
+                    .iter()
+                    .all(|(constraint, _)| holds(grid, colors, constraint))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rules_are_lowered_in_order() {
+        let set = ConstraintSet::new(&small_grid());
+        let all_dark = (0..4).map(|index| (at(index), Color::Dark)).collect();
+        assert_eq!(
+            set.constraints,
+            [
+                (Constraint::SizeIn(Size::Area(at(3)), vec![3]), 0),
+                (Constraint::Connected(Color::Dark), 1),
+                (Constraint::Clause(all_dark), 2),
+                (Constraint::Clause(vec![(at(0), Color::Dark)]), 3),
+                (Constraint::SameColor(at(2), at(3)), 4),
+            ]
+        );
+        assert_eq!(
+            set.coords,
+            [
+                Coord { i: 0, j: 0 },
+                Coord { i: 0, j: 1 },
+                Coord { i: 1, j: 0 },
+                Coord { i: 1, j: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn simplify_keeps_the_same_solutions() {
+        let grid = Grid::new(2, 3).prepare();
+        let constraints = vec![
+            Constraint::Clause(vec![
+                (at(1), Color::Dark),
+                (at(0), Color::Light),
+                (at(1), Color::Dark),
+            ]),
+            Constraint::Clause(vec![(at(0), Color::Light), (at(1), Color::Dark)]),
+            Constraint::Clause(vec![(at(2), Color::Light), (at(2), Color::Dark)]),
+            Constraint::SameColor(at(4), at(4)),
+            Constraint::Count(vec![(at(0), Color::Dark), (at(3), Color::Dark)], 0),
+            Constraint::Count(vec![(at(1), Color::Light), (at(4), Color::Light)], 2),
+            Constraint::Count(vec![(at(2), Color::Dark), (at(5), Color::Light)], 1),
+            Constraint::SizeIn(Size::Area(at(5)), vec![3, 2, 3]),
+            Constraint::SizesEqual(Size::Visible(at(0)), Size::Visible(at(0))),
+            Constraint::SizeBelow(Size::Ray(at(0), Direction::Right), 2),
+            Constraint::SizeBeyond(
+                Size::Ray(at(3), Direction::Right),
+                Size::Ray(at(3), Direction::Up),
+                1,
+            ),
+            Constraint::SameRegion(vec![at(2)]),
+            Constraint::SameRegion(vec![at(2), at(5)]),
+            Constraint::DifferentRegions(vec![at(0)]),
+            Constraint::Connected(Color::Light),
+        ];
+        let mut set = ConstraintSet::new(&grid);
+        set.constraints = constraints
+            .into_iter()
+            .map(|constraint| (constraint, 0))
+            .collect();
+        let before = solutions(&grid, &set);
+        set.simplify();
+        assert!(!before.is_empty());
+        assert_eq!(solutions(&grid, &set), before);
+        assert_eq!(
+            set.constraints[0],
+            (
+                Constraint::Clause(vec![(at(0), Color::Light), (at(1), Color::Dark)]),
+                0
+            )
+        );
+        assert_eq!(set.constraints.len(), 11);
+
+        // Counting more squares than there are makes an empty clause, which never holds.
+        set.constraints = vec![(Constraint::Count(vec![(at(0), Color::Dark)], 2), 0)];
+        set.simplify();
+        assert_eq!(set.constraints, [(Constraint::Clause(Vec::new()), 0)]);
+        assert!(solutions(&grid, &set).is_empty());
+    }
+
+    #[test]
+    fn simplify_keeps_constraints_with_their_rules() {
+        let grid = small_grid();
+        let mut set = ConstraintSet::new(&grid);
+        let before = solutions(&grid, &set);
+        set.constraints
+            .push((Constraint::Connected(Color::Dark), 1));
+        set.constraints
+            .push((Constraint::Connected(Color::Dark), 2));
+        set.simplify();
+        assert_eq!(solutions(&grid, &set), before);
+        let origins = set
+            .constraints
+            .iter()
+            .map(|&(_, origin)| origin)
+            .collect::<Vec<_>>();
+        assert_eq!(origins, [0, 1, 2, 3, 4, 2]);
+    }
+
+    #[test]
+    fn descriptions_and_json_are_stable() {
+        let set = ConstraintSet::new(&small_grid());
+        let descriptions = set
+            .constraints
+            .iter()
+            .map(|(constraint, _)| set.describe(constraint))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            descriptions,
+            [
+                "area of (1, 1) is one of [3]",
+                "dark squares are connected",
+                "one of [(0, 0) dark, (0, 1) dark, (1, 0) dark, (1, 1) dark]",
+                "(0, 0) is dark",
+                "(1, 0) and (1, 1) have the same color",
+            ]
+        );
+        let json = set
+            .constraints
+            .iter()
+            .map(|(constraint, _)| set.to_json(constraint).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            json,
+            [
+                r#"{"kind":"size_in","numbers":[3],"size":{"area":[1,1]}}"#,
+                r#"{"color":"dark","kind":"connected"}"#,
+                r#"{"cells":[[0,0,"dark"],[0,1,"dark"],[1,0,"dark"],[1,1,"dark"]],"kind":"clause"}"#,
+                r#"{"cells":[[0,0,"dark"]],"kind":"clause"}"#,
+                r#"{"kind":"same_color","squares":[[1,0],[1,1]]}"#,
+            ]
+        );
+        let ray = Constraint::SizeBeyond(
+            Size::Ray(at(0), Direction::Right),
+            Size::Ray(at(0), Direction::Down),
+            1,
+        );
+        assert_eq!(
+            set.describe(&ray),
+            "ray Down from (0, 0) is more than ray Right from (0, 0) unless it is 1"
+        );
+        assert_eq!(
+            set.to_json(&ray).to_string(),
+            r#"{"far":{"direction":"Down","ray":[0,0]},"kind":"size_beyond","limit":1,"near":{"direction":"Right","ray":[0,0]}}"#
+        );
+    }
+}
@@ -0,0 +1,26 @@
+pub mod backend;
+pub mod checker;
+pub mod cnf;
+#[cfg(feature = "z3")]
+pub mod constraints;
+pub mod corpus;
+pub mod difficulty;
+pub mod generator;
+pub mod grid;
+pub mod hints;
+pub mod ir;
+pub mod minimizer;
+pub mod pdata;
+pub mod propagate;
+pub mod sat;
+pub mod session;
+pub mod solutions;
+pub mod solver;
+
+pub use backend::BackendKind;
+pub use session::SolveSession;
+pub use solutions::Solutions;
+pub use solver::{
+    DeduceOutcome, DeduceProgress, Deduction, DeductionMismatch, SolveOptions, SolveOutcome,
+    SolveStatus, Solver, Uniqueness,
+};
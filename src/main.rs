@@ -1,288 +1,1242 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
 
-use constraints::GridConstraints;
-use grid::Direction;
-use grid::{Color, Coord, Grid, Rule};
-use rayon::iter::IntoParallelIterator;
+use clap::builder::PossibleValue;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ioi::corpus::CorpusEntry;
+use ioi::difficulty::TraceEnd;
+use ioi::generator::{ClueKind, GeneratorOptions};
+use ioi::grid::{Color, Coord, Grid, GridPattern, Rule, RuleRef};
+use ioi::hints::HintResult;
+use ioi::ir::ConstraintSet;
+use ioi::minimizer::MinimizeResult;
+use ioi::pdata::{self, Puzzle};
+use ioi::propagate::Propagator;
+use ioi::{
+    checker, corpus, difficulty, BackendKind, DeduceProgress, Deduction, SolveOptions, SolveStatus,
+    Solver, Uniqueness,
+};
+use rayon::iter::IntoParallelRefIterator;
 use rayon::iter::ParallelIterator;
-use z3::Params;
+use serde_json::json;
 
-use crate::constraints::print_solved_grid;
-use crate::grid::GridPattern;
+const EXIT_SOLVED: u8 = 0;
+const EXIT_ERROR: u8 = 1;
+const EXIT_UNSOLVABLE: u8 = 2;
+const EXIT_UNKNOWN: u8 = 3;
+const EXIT_MULTIPLE: u8 = 4;
 
-mod constraints;
-pub mod grid;
+#[derive(Parser)]
+#[command(about = "Solver for logicGrid puzzles")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-fn solve_simple(grid: &Grid) {
-    match try_solve_grid(grid, 1000000) {
-        GridSolveResult::Solved(solved) => {
-            println!("Solved: \n{:?}", solved);
+    /// Timeout in seconds for each solver check.
+    #[arg(long, global = true, default_value_t = 60)]
+    timeout: u32,
+
+    /// Solver to hand the puzzle to.
+    #[arg(long, global = true, value_enum, default_value_t = BackendArg(BackendKind::default()))]
+    backend: BackendArg,
+
+    /// Number of worker threads. Defaults to one per core.
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Find a solution for a puzzle.
+    Solve {
+        #[command(flatten)]
+        input: Input,
+        /// Also look for a second solution.
+        #[arg(long)]
+        unique: bool,
+    },
+    /// List every solution of a puzzle, or count them.
+    Enumerate {
+        #[command(flatten)]
+        input: Input,
+        /// Stop after this many solutions.
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Only print the number of solutions.
+        #[arg(long)]
+        count: bool,
+    },
+    /// Repeatedly find forced cells, leaving the ones that can be either color.
+    Deduce(Input),
+    /// Color the cells that simple local reasoning forces, without a solver.
+    Propagate(Input),
+    /// Show the next forced cell and the rules that force it.
+    Hint(Input),
+    /// Estimate how hard a puzzle is from its deduction trace.
+    Difficulty(Input),
+    /// Compare estimated difficulty with the difficulty of every puzzle in a corpus.
+    DifficultyReport { corpus: PathBuf },
+    /// Check a solution, given as one line of L/D per row, against a puzzle's rules.
+    Check {
+        #[command(flatten)]
+        input: Input,
+        solution: PathBuf,
+    },
+    /// Print the constraints a puzzle is handed to a backend as.
+    Constraints {
+        #[command(flatten)]
+        input: Input,
+        /// Print them as built from the rules, without simplifying.
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Solve every supported puzzle in a decoded.json corpus.
+    Batch { corpus: PathBuf },
+    /// Check that every backend finds the same solutions for a decoded.json corpus.
+    CompareBackends { corpus: PathBuf },
+    /// Remove the clues a puzzle with a unique solution does not need.
+    Minimize(Input),
+    /// Generate a random puzzle with a unique solution.
+    Generate(Generate),
+}
+
+#[derive(Args)]
+struct Generate {
+    rows: usize,
+    cols: usize,
+
+    /// Kinds of clue the puzzle may use.
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [ClueArg::Given, ClueArg::Area])]
+    clues: Vec<ClueArg>,
+
+    /// Require all squares of this color to be connected.
+    #[arg(long, value_enum)]
+    connect: Vec<ColorArg>,
+
+    /// Ban 2x2 blocks of a single color.
+    #[arg(long)]
+    no_2x2: bool,
+
+    /// Target difficulty score, as reported by the difficulty command.
+    #[arg(long, default_value_t = 3.0)]
+    difficulty: f64,
+
+    /// Number of puzzles to generate before keeping the closest to the target difficulty.
+    #[arg(long, default_value_t = 5)]
+    attempts: usize,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ClueArg {
+    Given,
+    Area,
+    Viewpoint,
+    Dart,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct BackendArg(BackendKind);
+
+// Written out, since which backends exist depends on the enabled features.
+impl ValueEnum for BackendArg {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            #[cfg(feature = "z3")]
+            BackendArg(BackendKind::Z3),
+            BackendArg(BackendKind::Sat),
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(self.0.backend().name()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ColorArg {
+    Light,
+    Dark,
+}
+
+#[derive(Args)]
+struct Input {
+    /// A pdata file, or a decoded.json corpus together with --pid.
+    file: PathBuf,
+
+    /// Which puzzle to pick from a decoded.json corpus.
+    #[arg(long)]
+    pid: Option<String>,
+
+    /// The pdata file is base64 encoded, as in Puzzles.json.
+    #[arg(long)]
+    base64: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+// Loads the entries of a corpus, warning about the ones that are malformed.
+fn load_corpus(path: &Path) -> Result<Vec<CorpusEntry>, String> {
+    let corpus = corpus::load_corpus(path).map_err(|err| err.to_string())?;
+    for err in &corpus.errors {
+        eprintln!("warning: {}: {}", path.display(), err);
+    }
+    Ok(corpus.entries)
+}
+
+fn load_puzzle(input: &Input) -> Result<Puzzle, String> {
+    let path = input.file.display();
+    if let Some(pid) = &input.pid {
+        let entries = load_corpus(&input.file)?;
+        let entry = entries
+            .into_iter()
+            .find(|entry| &entry.pid == pid)
+            .ok_or_else(|| format!("{}: no puzzle with pid {}", path, pid))?;
+        if !entry.untranslatable.is_empty() {
+            let reasons = entry
+                .untranslatable
+                .iter()
+                .map(|untranslatable| untranslatable.to_string())
+                .collect::<Vec<_>>();
+            return Err(format!("puzzle {}: {}", pid, reasons.join(", ")));
         }
-        GridSolveResult::Unsolvable => {
-            println!("Unsolvable");
+        return Ok(Puzzle {
+            grid: entry.grid,
+            solution: entry.solution,
+        });
+    }
+    let mut data = std::fs::read(&input.file).map_err(|err| format!("{}: {}", path, err))?;
+    if input.base64 {
+        data = pdata::decode_base64(&String::from_utf8_lossy(&data))
+            .ok_or_else(|| format!("{}: invalid base64", path))?;
+    }
+    pdata::decode_puzzle(&data).map_err(|err| format!("{}: {}", path, err))
+}
+
+fn load_solution(path: &Path, grid: &Grid) -> Result<Grid, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let lines = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let mut solved = grid.clone();
+    for (coord, _) in grid.squares() {
+        let cell = lines
+            .get(coord.i as usize)
+            .and_then(|line| line.chars().nth(coord.j as usize));
+        let color = match cell {
+            Some('L') => Color::Light,
+            Some('D') => Color::Dark,
+            _ => return Err(format!("{}: no color for cell {:?}", path.display(), coord)),
+        };
+        if grid
+            .square(coord)
+            .and_then(|square| square.color)
+            .is_some_and(|given| given != color)
+        {
+            return Err(format!(
+                "{}: cell {:?} contradicts its given color",
+                path.display(),
+                coord
+            ));
         }
-        GridSolveResult::Unknown => {
-            println!("Unknown");
+        solved.set_color(coord.i as usize, coord.j as usize, color);
+    }
+    Ok(solved)
+}
+
+// One string per row: L/D for colored cells, '.' for uncolored cells and ' ' for holes.
+fn grid_rows(grid: &Grid) -> Vec<String> {
+    (0..grid.rows() as isize)
+        .map(|i| {
+            (0..grid.cols() as isize)
+                .map(|j| match grid.square(Coord { i, j }) {
+                    Some(square) => match square.color {
+                        Some(Color::Light) => 'L',
+                        Some(Color::Dark) => 'D',
+                        None => '.',
+                    },
+                    None => ' ',
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn deduction_name(deduction: Deduction) -> &'static str {
+    match deduction {
+        Deduction::Forced(Color::Light) => "light",
+        Deduction::Forced(Color::Dark) => "dark",
+        Deduction::Free => "free",
+        Deduction::Undetermined => "undetermined",
+    }
+}
+
+fn solve_status(status: SolveStatus) -> (&'static str, u8) {
+    match status {
+        SolveStatus::Solved => ("solved", EXIT_SOLVED),
+        SolveStatus::Unsolvable => ("unsolvable", EXIT_UNSOLVABLE),
+        SolveStatus::Unknown => ("unknown", EXIT_UNKNOWN),
+    }
+}
+
+fn run_solve(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let outcome = solver.solve(&puzzle.grid);
+    let seconds = outcome.elapsed.as_secs_f64();
+    let (status, code) = solve_status(outcome.status);
+    let matches_expected = match (&outcome.grid, &puzzle.solution) {
+        (Some(solved), Some(solution)) => Some(solution.matches(solved)),
+        _ => None,
+    };
+    match format {
+        Format::Text => {
+            println!("{} in {:.3}s", status, seconds);
+            if let Some(impossible) = &outcome.impossible {
+                println!("{}", impossible);
+            }
+            if let Some(solved) = &outcome.grid {
+                print!("{:?}", solved);
+            }
+            if matches_expected == Some(false) {
+                println!("Solution differs from the expected solution");
+            }
+        }
+        Format::Json => {
+            println!(
+                "{}",
+                json!({
+                    "status": status,
+                    "seconds": seconds,
+                    "grid": outcome.grid.as_ref().map(grid_rows),
+                    "impossible": outcome.impossible.as_ref().map(ToString::to_string),
+                    "matches_expected": matches_expected,
+                })
+            );
         }
     }
+    Ok(code)
 }
 
-fn solve_underconstrained(grid: &mut Grid) {
-    let mut unfillable = HashSet::new();
+fn run_unique(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let (status, code, solutions) = match solver.check_uniqueness(&puzzle.grid) {
+        Uniqueness::Unique(solved) => ("unique", EXIT_SOLVED, vec![solved]),
+        Uniqueness::Multiple(first, second) => ("multiple", EXIT_MULTIPLE, vec![first, second]),
+        Uniqueness::Unsolvable => ("unsolvable", EXIT_UNSOLVABLE, vec![]),
+        Uniqueness::Unknown => ("unknown", EXIT_UNKNOWN, vec![]),
+    };
+    // The squares where the two solutions disagree.
+    let differences = match solutions.as_slice() {
+        [first, second] => first
+            .squares()
+            .zip(second.squares())
+            .filter(|((_, a), (_, b))| a.color != b.color)
+            .map(|((coord, _), _)| coord)
+            .collect(),
+        _ => Vec::new(),
+    };
+    match format {
+        Format::Text => {
+            println!("{}", status);
+            for solved in &solutions {
+                print!("{:?}", solved);
+                println!();
+            }
+            for coord in &differences {
+                println!("Differs: {:?}", coord);
+            }
+        }
+        Format::Json => {
+            println!(
+                "{}",
+                json!({
+                    "status": status,
+                    "solutions": solutions.iter().map(grid_rows).collect::<Vec<_>>(),
+                    "differences": differences
+                        .iter()
+                        .map(|coord| [coord.i, coord.j])
+                        .collect::<Vec<_>>(),
+                })
+            );
+        }
+    }
+    Ok(code)
+}
 
-    let mut timeout = 1;
-    loop {
-        println!("Begin parallel solve with timeout: {}", timeout);
-        let solved = par_solve_grid(&grid, &unfillable, timeout);
-        if solved.is_empty() {
-            break;
+fn run_enumerate(
+    solver: &Solver,
+    format: Format,
+    input: &Input,
+    limit: Option<usize>,
+    count_only: bool,
+) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let mut solutions = solver.solutions(&puzzle.grid, limit);
+    let mut count = 0;
+    for solved in solutions.by_ref() {
+        count += 1;
+        if count_only {
+            continue;
         }
-        let any_filled = solved.iter().any(|(_, result)| match result {
-            SolveResult::Definitely(_) => true,
-            _ => false,
-        });
-        for (coord, result) in solved {
-            match result {
-                SolveResult::Definitely(color) => {
-                    println!("Definitely: {:?} -> {:?}", coord, color);
-                    grid.set_color(coord.i as usize, coord.j as usize, color);
-                }
-                SolveResult::Unfillable => {
-                    println!("Unfillable: {:?}", coord);
-                    unfillable.insert(coord);
-                }
-                SolveResult::Unknown => {}
+        match format {
+            Format::Text => {
+                print!("{:?}", solved);
+                println!();
+            }
+            Format::Json => println!("{}", json!({ "solution": grid_rows(&solved) })),
+        }
+    }
+    let complete = solutions.is_exhausted();
+    match format {
+        Format::Text if complete => println!("{} solutions", count),
+        Format::Text => println!("at least {} solutions", count),
+        Format::Json => println!("{}", json!({ "count": count, "complete": complete })),
+    }
+    Ok(match (complete, count) {
+        (true, 0) => EXIT_UNSOLVABLE,
+        (true, 1) => EXIT_SOLVED,
+        (false, 0 | 1) => EXIT_UNKNOWN,
+        _ => EXIT_MULTIPLE,
+    })
+}
+
+fn run_deduce(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let outcome = solver.solve(&puzzle.grid);
+    let (status, code) = solve_status(outcome.status);
+    if code != EXIT_SOLVED {
+        let impossible = outcome.impossible.as_ref().map(ToString::to_string);
+        match format {
+            Format::Text => match &impossible {
+                Some(impossible) => println!("{}: {}", status, impossible),
+                None => println!("{}", status),
+            },
+            Format::Json => println!("{}", json!({ "status": status, "impossible": impossible })),
+        }
+        return Ok(code);
+    }
+    let deduced = solver.deduce_with_progress(&puzzle.grid, |progress| match progress {
+        DeduceProgress::Round { timeout } => {
+            eprintln!("Begin parallel solve with timeout: {}", timeout)
+        }
+        DeduceProgress::Propagated(fixed) => eprintln!(
+            "Propagated: {:?} -> {:?} by {}",
+            fixed.coord,
+            fixed.color,
+            propagator_name(fixed.propagator)
+        ),
+        DeduceProgress::Forced(coord, color) => eprintln!("Definitely: {:?} -> {:?}", coord, color),
+        DeduceProgress::Unfillable(coord) => eprintln!("Unfillable: {:?}", coord),
+    });
+    let seconds = (outcome.elapsed + deduced.elapsed).as_secs_f64();
+    if let Some(contradiction) = deduced.contradiction {
+        let contradiction = describe_rule_ref(&puzzle.grid, &contradiction);
+        match format {
+            Format::Text => println!("unsolvable in {:.3}s\nBroken: {}", seconds, contradiction),
+            Format::Json => println!(
+                "{}",
+                json!({ "status": "unsolvable", "seconds": seconds, "contradiction": contradiction })
+            ),
+        }
+        return Ok(EXIT_UNSOLVABLE);
+    }
+    let deductions = deduced.deductions();
+    let undetermined = deductions
+        .iter()
+        .filter(|(_, deduction)| *deduction == Deduction::Undetermined)
+        .count();
+    let mismatches = match &puzzle.solution {
+        Some(solution) => deduced.mismatches(solution),
+        None => Vec::new(),
+    };
+    let status = if !mismatches.is_empty() {
+        "mismatch"
+    } else if undetermined == 0 {
+        "deduced"
+    } else {
+        "unknown"
+    };
+    match format {
+        Format::Text => {
+            println!("{} in {:.3}s", status, seconds);
+            print!("{:?}", deduced.grid);
+            for coord in &deduced.unfillable {
+                println!("Unfillable: {:?}", coord);
+            }
+            for mismatch in &mismatches {
+                println!(
+                    "Mismatch at {:?}: expected {}, got {}",
+                    mismatch.coord,
+                    deduction_name(mismatch.expected),
+                    deduction_name(mismatch.actual)
+                );
+            }
+        }
+        Format::Json => {
+            println!(
+                "{}",
+                json!({
+                    "status": status,
+                    "seconds": seconds,
+                    "grid": grid_rows(&deduced.grid),
+                    "unfillable": deduced
+                        .unfillable
+                        .iter()
+                        .map(|coord| [coord.i, coord.j])
+                        .collect::<Vec<_>>(),
+                    "mismatches": mismatches
+                        .iter()
+                        .map(|mismatch| json!({
+                            "cell": [mismatch.coord.i, mismatch.coord.j],
+                            "expected": deduction_name(mismatch.expected),
+                            "actual": deduction_name(mismatch.actual),
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+            );
+        }
+    }
+    Ok(if !mismatches.is_empty() {
+        EXIT_UNSOLVABLE
+    } else if undetermined == 0 {
+        EXIT_SOLVED
+    } else {
+        EXIT_UNKNOWN
+    })
+}
+
+fn run_propagate(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let outcome = solver.propagate(&puzzle.grid);
+    let (status, code) = match outcome.contradiction {
+        Some(_) => ("unsolvable", EXIT_UNSOLVABLE),
+        None => ("propagated", EXIT_SOLVED),
+    };
+    let contradiction = outcome
+        .contradiction
+        .map(|source| describe_rule_ref(&puzzle.grid, &source));
+    let color_name = |color| match color {
+        Color::Light => "light",
+        Color::Dark => "dark",
+    };
+    match format {
+        Format::Text => {
+            println!("{}", status);
+            if let Some(contradiction) = &contradiction {
+                println!("Broken: {}", contradiction);
+            }
+            for row in grid_rows(&outcome.grid) {
+                println!("{}", row);
+            }
+            for fixed in &outcome.fixed {
+                println!(
+                    "{:?} is {} by {}: {}",
+                    fixed.coord,
+                    color_name(fixed.color),
+                    propagator_name(fixed.propagator),
+                    describe_rule_ref(&puzzle.grid, &fixed.source)
+                );
+            }
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "status": status,
+                "contradiction": contradiction,
+                "grid": grid_rows(&outcome.grid),
+                "fixed": outcome
+                    .fixed
+                    .iter()
+                    .map(|fixed| json!({
+                        "cell": [fixed.coord.i, fixed.coord.j],
+                        "color": color_name(fixed.color),
+                        "propagator": propagator_name(fixed.propagator),
+                        "rule": describe_rule_ref(&puzzle.grid, &fixed.source),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        ),
+    }
+    Ok(code)
+}
+
+fn propagator_name(propagator: Propagator) -> &'static str {
+    match propagator {
+        Propagator::Merge => "merge",
+        Propagator::BanPattern => "ban pattern",
+        Propagator::Connectivity => "connectivity",
+        Propagator::AreaSaturation => "area saturation",
+        Propagator::DartCount => "dart count",
+    }
+}
+
+fn run_hint(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let (status, code, hint) = match solver.hint(&puzzle.grid) {
+        HintResult::Hint(hint) => ("hint", EXIT_SOLVED, Some(hint)),
+        HintResult::Solved => ("solved", EXIT_SOLVED, None),
+        HintResult::NoForcedSquare => ("no forced cell", EXIT_MULTIPLE, None),
+        HintResult::Unsolvable => ("unsolvable", EXIT_UNSOLVABLE, None),
+        HintResult::Unknown => ("unknown", EXIT_UNKNOWN, None),
+    };
+    let Some(hint) = hint else {
+        match format {
+            Format::Text => println!("{}", status),
+            Format::Json => println!("{}", json!({ "status": status })),
+        }
+        return Ok(code);
+    };
+    let color = match hint.color {
+        Color::Light => "light",
+        Color::Dark => "dark",
+    };
+    let reasons = hint
+        .reasons
+        .iter()
+        .map(|reason| describe_rule_ref(&puzzle.grid, reason))
+        .collect::<Vec<_>>();
+    match format {
+        Format::Text => {
+            println!("{:?} is {}", hint.cell, color);
+            for reason in &reasons {
+                println!("  because of {}", reason);
+            }
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "status": status,
+                "cell": [hint.cell.i, hint.cell.j],
+                "color": color,
+                "reasons": reasons,
+            })
+        ),
+    }
+    Ok(code)
+}
+
+fn describe_rule_ref(grid: &Grid, rule_ref: &RuleRef) -> String {
+    match rule_ref {
+        RuleRef::Given(coord) => format!("the given color at ({}, {})", coord.i, coord.j),
+        RuleRef::Merge(a, b) => format!("the merge of ({}, {}) and ({}, {})", a.i, a.j, b.i, b.j),
+        RuleRef::Rule { index, clue } => {
+            let name = rule_name(&grid.rules()[*index]);
+            match clue {
+                Some(coord) => format!("{} at ({}, {})", name, coord.i, coord.j),
+                None => name,
+            }
+        }
+    }
+}
+
+fn rule_name(rule: &Rule) -> String {
+    match rule {
+        Rule::BanPattern(pattern) => {
+            let cells = pattern
+                .pattern
+                .iter()
+                .map(|(coord, color)| format!("{:?} at ({}, {})", color, coord.i, coord.j))
+                .collect::<Vec<_>>();
+            format!("ban pattern [{}]", cells.join(", "))
+        }
+        rule => format!("{:?}", rule),
+    }
+}
+
+fn run_difficulty(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let trace = solver.difficulty(&puzzle.grid);
+    match format {
+        Format::Text => {
+            for (n, step) in trace.steps.iter().enumerate() {
+                println!(
+                    "Round {}: {} forced, {} multi-cell, {} conflicts",
+                    n + 1,
+                    step.forced.len(),
+                    step.multi_cell,
+                    step.conflicts
+                );
+            }
+            match trace.end {
+                TraceEnd::Solved => println!("Score: {:.2}", trace.score()),
+                TraceEnd::Unsolvable => println!("unsolvable"),
+                TraceEnd::Stuck => println!("{} cells left undetermined", trace.remaining),
+                TraceEnd::TimedOut => println!("timed out"),
             }
         }
-        if !any_filled {
-            timeout *= 2;
-        }
-    }
-    println!("Final grid: \n{:?}", grid);
-}
-
-fn main() {
-    let mut grid = Grid::new(8, 8);
-    grid.add_rule(Rule::ConnectAll(Color::Dark));
-    grid.add_rule(Rule::ConnectAll(Color::Light));
-    grid.color_light(0, 2);
-    grid.color_light(0, 3);
-    grid.color_dark(0, 5);
-    grid.add_rule(Rule::BanPattern(GridPattern::square2x2(
-        Color::Dark,
-        Color::Dark,
-        Color::Dark,
-        Color::Dark,
-    )));
-    grid.add_rule(Rule::BanPattern(GridPattern::square2x2(
-        Color::Light,
-        Color::Light,
-        Color::Light,
-        Color::Light,
-    )));
-    // grid.add_rule(Rule::BanPattern(GridPattern::square2x2(
-    //     Color::Light,
-    //     Color::Dark,
-    //     Color::Dark,
-    //     Color::Light,
-    // )));
-    solve_underconstrained(&mut grid);
-
-    // let mut grid = Grid::new(4, 4);
-    // grid.add_rule(Rule::DartNumbers);
-    // grid.add_rule(Rule::VisibleCellCount);
-    // grid.visible_count(0, 2, 7);
-    // grid.color_dark(0, 2);
-    // grid.dart_number(3, 0, Direction::Right, 2, Color::Dark);
-    // solve_simple(&grid);
-
-    // let mut grid = Grid::new(17, 17);
-    // for i in 0..17 {
-    //     grid.remove_square(1, i);
-    //     grid.remove_square(i, 1);
-    // }
-    // grid.remove_square(0, 0);
-
-    // for i in 2..17 {
-    //     grid.dart_number(0, i, Direction::Down, 3, Color::Light);
-    //     grid.dart_number(i, 0, Direction::Right, 3, Color::Light);
-    // }
-
-    // for (r, c, n) in [
-    //     (2, 9, 2),
-    //     (3, 5, 4),
-    //     (3, 13, 9),
-    //     (5, 3, 5),
-    //     (5, 15, 9),
-    //     (9, 2, 7),
-    //     (9, 9, 6),
-    //     (9, 16, 7),
-    //     (13, 3, 8),
-    //     (13, 15, 5),
-    //     (15, 5, 6),
-    //     (15, 13, 7),
-    //     (16, 9, 5),
-    // ] {
-    //     grid.visible_count(r, c, n);
-    //     grid.color_light(r, c);
-    // }
-
-    // grid.add_rule(Rule::BanPattern(GridPattern {
-    //     pattern: vec![
-    //         (Coord { i: 0, j: 0 }, Color::Dark),
-    //         (Coord { i: 0, j: 1 }, Color::Dark),
-    //     ],
-    // }));
-    // grid.add_rule(Rule::BanPattern(GridPattern {
-    //     pattern: vec![
-    //         (Coord { i: 0, j: 0 }, Color::Dark),
-    //         (Coord { i: 1, j: 1 }, Color::Dark),
-    //     ],
-    // }));
-    // grid.add_rule(Rule::VisibleCellCount);
-    // grid.add_rule(Rule::DartNumbers);
-
-    // solve_simple(&grid);
-
-    // let mut grid = Grid::new(5, 12);
-    // grid.add_rule(Rule::RegionAreaEqualsNumber);
-    // for (r, c, n) in [
-    //     (0, 2, 4),
-    //     (0, 5, 3),
-    //     (0, 8, 4),
-    //     (0, 11, 6),
-    //     (2, 1, 4),
-    //     (2, 4, 3),
-    //     (2, 7, 4),
-    //     (2, 10, 6),
-    //     (4, 0, 4),
-    //     (4, 3, 3),
-    //     (4, 6, 4),
-    //     (4, 9, 6),
-    // ] {
-    //     grid.set_area_number(r, c, n);
-    //     grid.color_light(r, c);
-    // }
-    // grid.color_light(1, 3);
-    // grid.color_light(3, 8);
-    // solve_simple(&grid);
-
-    // let mut grid = Grid::new(9, 9);
-    // grid.add_rule(Rule::RegionAreaEqualsNumber);
-    // for (r, c, n) in [
-    //     (1, 4, 5),
-    //     (2, 2, 1),
-    //     (4, 1, 4),
-    //     (6, 2, 19),
-    //     (7, 4, 1),
-    //     (6, 6, 3),
-    //     (4, 7, 3),
-    //     (2, 6, 4),
-    // ] {
-    //     grid.set_area_number(r, c, n);
-    //     grid.color_light(r, c);
-    // }
-    // grid.add_rule(Rule::BanPattern(GridPattern {
-    //     pattern: vec![
-    //         (Coord { i: 0, j: 0 }, Color::Dark),
-    //         (Coord { i: 0, j: 1 }, Color::Dark),
-    //         (Coord { i: 1, j: 0 }, Color::Dark),
-    //         (Coord { i: 1, j: 1 }, Color::Dark),
-    //     ],
-    // }));
-    // grid.add_rule(Rule::ConnectAll(Color::Dark));
-    // grid.add_rule(Rule::ExactlyOneNumberPerRegion(Color::Light));
-    // solve_simple(&grid);
-}
-
-enum GridSolveResult {
-    Solved(Grid),
-    Unsolvable,
-    Unknown,
-}
-
-fn try_solve_grid(grid: &Grid, timeout: u32) -> GridSolveResult {
-    println!("Trying to solve: \n{:?}", grid);
-    let prepared = grid.prepare();
-    let config = z3::Config::new();
-    let ctx = z3::Context::new(&config);
-    let constraints = GridConstraints::new(&prepared, &ctx);
-    let solver = z3::Solver::new(&ctx);
-    let mut params = Params::new(&ctx);
-    params.set_u32("timeout", timeout * 1000);
-    solver.set_params(&params);
-    constraints.assert(&solver);
-
-    match solver.check() {
-        z3::SatResult::Unsat => GridSolveResult::Unsolvable,
-        z3::SatResult::Unknown => GridSolveResult::Unknown,
-        z3::SatResult::Sat => {
-            let model = solver.get_model().unwrap();
-            let mut grid = grid.clone();
-            for (coord, index) in &prepared.square_indexes {
-                let color = model
-                    .eval(&constraints.squares[index.0].color, false)
-                    .unwrap();
-                let color = match color.as_bool() {
-                    Some(true) => Color::Light,
-                    Some(false) => Color::Dark,
-                    None => panic!("Model did not evaluate color for cell"),
+        Format::Json => println!(
+            "{}",
+            json!({
+                "end": trace_end_name(trace.end),
+                "score": trace.is_solved().then(|| trace.score()),
+                "rounds": trace
+                    .steps
+                    .iter()
+                    .map(|step| json!({
+                        "forced": step
+                            .forced
+                            .iter()
+                            .map(|(coord, _)| [coord.i, coord.j])
+                            .collect::<Vec<_>>(),
+                        "multi_cell": step.multi_cell,
+                        "conflicts": step.conflicts,
+                    }))
+                    .collect::<Vec<_>>(),
+                "remaining": trace.remaining,
+            })
+        ),
+    }
+    Ok(match trace.end {
+        TraceEnd::Solved => EXIT_SOLVED,
+        TraceEnd::Unsolvable => EXIT_UNSOLVABLE,
+        TraceEnd::Stuck => EXIT_MULTIPLE,
+        TraceEnd::TimedOut => EXIT_UNKNOWN,
+    })
+}
+
+fn trace_end_name(end: TraceEnd) -> &'static str {
+    match end {
+        TraceEnd::Solved => "solved",
+        TraceEnd::Unsolvable => "unsolvable",
+        TraceEnd::Stuck => "stuck",
+        TraceEnd::TimedOut => "timed out",
+    }
+}
+
+fn run_difficulty_report(
+    solver: &Solver,
+    format: Format,
+    corpus_path: &Path,
+) -> Result<u8, String> {
+    let entries = load_corpus(corpus_path)?;
+    let results = entries
+        .par_iter()
+        .filter(|entry| entry.untranslatable.is_empty())
+        .map(|entry| (entry, solver.difficulty(&entry.grid)))
+        .collect::<Vec<_>>();
+    // Only solved traces say how hard a puzzle is.
+    let solved = results
+        .iter()
+        .filter(|(_, trace)| trace.is_solved())
+        .collect::<Vec<_>>();
+    let difficulties = solved
+        .iter()
+        .map(|(entry, _)| entry.difficulty as f64)
+        .collect::<Vec<_>>();
+    let scores = solved
+        .iter()
+        .map(|(_, trace)| trace.score())
+        .collect::<Vec<_>>();
+    let pearson = difficulty::pearson(&difficulties, &scores);
+    let spearman = difficulty::spearman(&difficulties, &scores);
+    match format {
+        Format::Text => {
+            for (entry, trace) in &results {
+                let score = match trace.end {
+                    TraceEnd::Solved => format!("{:.2}", trace.score()),
+                    end => trace_end_name(end).to_string(),
                 };
-                grid.set_color(coord.i as usize, coord.j as usize, color);
+                println!(
+                    "{}\t{}\t{}\t{} rounds",
+                    entry.pid,
+                    entry.difficulty,
+                    score,
+                    trace.steps.len()
+                );
             }
-            GridSolveResult::Solved(grid)
+            println!("Puzzles: {} ({} solved)", results.len(), solved.len());
+            let show = |correlation: Option<f64>| match correlation {
+                Some(correlation) => format!("{:.3}", correlation),
+                None => "n/a".to_string(),
+            };
+            println!("Pearson: {}", show(pearson));
+            println!("Spearman: {}", show(spearman));
         }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "puzzles": results
+                    .iter()
+                    .map(|(entry, trace)| json!({
+                        "pid": entry.pid,
+                        "difficulty": entry.difficulty,
+                        "end": trace_end_name(trace.end),
+                        "score": trace.is_solved().then(|| trace.score()),
+                        "rounds": trace.steps.len(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "pearson": pearson,
+                "spearman": spearman,
+            })
+        ),
     }
+    Ok(EXIT_SOLVED)
 }
 
-enum SolveResult {
-    Definitely(Color),
-    Unfillable,
-    Unknown,
+fn run_check(format: Format, input: &Input, solution: &Path) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let solved = load_solution(solution, &puzzle.grid)?;
+    let report = checker::check_grid(&solved);
+    let (status, code) = if report.is_valid() {
+        ("valid", EXIT_SOLVED)
+    } else {
+        ("invalid", EXIT_UNSOLVABLE)
+    };
+    let broken = report
+        .rules
+        .iter()
+        .filter(|check| !check.holds())
+        .collect::<Vec<_>>();
+    match format {
+        Format::Text => {
+            println!("{}", status);
+            for coord in &report.broken_merges {
+                println!("Broken merge: {:?}", coord);
+            }
+            for check in &broken {
+                println!("{}: {:?}", rule_name(&check.rule), check.violations);
+            }
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "status": status,
+                "broken_merges": report
+                    .broken_merges
+                    .iter()
+                    .map(|coord| [coord.i, coord.j])
+                    .collect::<Vec<_>>(),
+                "violations": broken
+                    .iter()
+                    .map(|check| json!({
+                        "rule": rule_name(&check.rule),
+                        "cells": check
+                            .violations
+                            .iter()
+                            .map(|coord| [coord.i, coord.j])
+                            .collect::<Vec<_>>(),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        ),
+    }
+    Ok(code)
 }
 
-fn par_solve_grid(
-    grid: &Grid,
-    unfillable: &HashSet<Coord>,
-    timeout: u32,
-) -> Vec<(Coord, SolveResult)> {
-    let unfilled_squares = grid
-        .squares()
-        .filter(|(coord, square)| !unfillable.contains(coord) && square.color.is_none());
-    let grid_with_squares_filled = unfilled_squares
-        .flat_map(|(coord, _)| {
-            let mut grid0 = grid.clone();
-            grid0.color_light(coord.i as usize, coord.j as usize);
-            let mut grid1 = grid.clone();
-            grid1.color_dark(coord.i as usize, coord.j as usize);
-            [(coord, Color::Light, grid0), (coord, Color::Dark, grid1)].into_iter()
+fn run_constraints(format: Format, input: &Input, raw: bool) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let prepared = puzzle.grid.prepare();
+    let mut set = ConstraintSet::new(&prepared);
+    if !raw {
+        set.simplify();
+    }
+    match format {
+        Format::Text => {
+            let mut last = None;
+            for (constraint, origin) in &set.constraints {
+                if last != Some(origin) {
+                    println!(
+                        "{}:",
+                        describe_rule_ref(&puzzle.grid, &prepared.sources[*origin])
+                    );
+                    last = Some(origin);
+                }
+                println!("  {}", set.describe(constraint));
+            }
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "constraints": set
+                    .constraints
+                    .iter()
+                    .map(|(constraint, origin)| json!({
+                        "rule": describe_rule_ref(&puzzle.grid, &prepared.sources[*origin]),
+                        "constraint": set.to_json(constraint),
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        ),
+    }
+    Ok(EXIT_SOLVED)
+}
+
+fn run_batch(solver: &Solver, format: Format, corpus_path: &Path) -> Result<u8, String> {
+    let entries = load_corpus(corpus_path)?;
+    let results = entries
+        .par_iter()
+        .map(|entry| {
+            if !entry.untranslatable.is_empty() {
+                return (entry, "unsupported", None);
+            }
+            let outcome = solver.solve(&entry.grid);
+            let status = match (&outcome.grid, &entry.solution) {
+                (Some(solved), _) if !checker::check_grid(solved).is_valid() => "invalid",
+                (Some(solved), Some(solution)) if !solution.matches(solved) => "mismatch",
+                _ => solve_status(outcome.status).0,
+            };
+            (entry, status, Some(outcome.elapsed.as_secs_f64()))
         })
         .collect::<Vec<_>>();
-    let result = grid_with_squares_filled
-        .into_par_iter()
-        .map(|(coord, color, grid)| {
-            let result = try_solve_grid(&grid, timeout);
-            (coord, color, result)
+
+    let mut counts = HashMap::new();
+    for (entry, status, seconds) in &results {
+        *counts.entry(*status).or_insert(0) += 1;
+        match format {
+            Format::Text => match seconds {
+                Some(seconds) => println!("{}\t{}\t{:.3}s", entry.pid, status, seconds),
+                None => {
+                    let reasons = entry
+                        .untranslatable
+                        .iter()
+                        .map(|untranslatable| untranslatable.to_string())
+                        .collect::<Vec<_>>();
+                    println!("{}\t{}\t{}", entry.pid, status, reasons.join(", "));
+                }
+            },
+            Format::Json => println!(
+                "{}",
+                json!({
+                    "pid": entry.pid,
+                    "difficulty": entry.difficulty,
+                    "status": status,
+                    "seconds": seconds,
+                    "unsupported": entry
+                        .untranslatable
+                        .iter()
+                        .map(|untranslatable| untranslatable.to_string())
+                        .collect::<Vec<_>>(),
+                })
+            ),
+        }
+    }
+    if format == Format::Text {
+        let mut counts = counts.iter().collect::<Vec<_>>();
+        counts.sort();
+        for (status, count) in counts {
+            println!("{}: {}", status, count);
+        }
+    }
+
+    Ok(
+        if ["unsolvable", "mismatch", "invalid"]
+            .iter()
+            .any(|status| counts.contains_key(status))
+        {
+            EXIT_UNSOLVABLE
+        } else if counts.contains_key("unknown") {
+            EXIT_UNKNOWN
+        } else {
+            EXIT_SOLVED
+        },
+    )
+}
+
+// The uniqueness check's verdict, with the solution if it is unique.
+fn uniqueness_summary(uniqueness: &Uniqueness) -> (&'static str, Option<Vec<String>>) {
+    match uniqueness {
+        Uniqueness::Unique(solution) => ("unique", Some(grid_rows(solution))),
+        Uniqueness::Multiple(..) => ("multiple", None),
+        Uniqueness::Unsolvable => ("unsolvable", None),
+        Uniqueness::Unknown => ("unknown", None),
+    }
+}
+
+fn run_compare_backends(solver: &Solver, format: Format, corpus_path: &Path) -> Result<u8, String> {
+    let entries = load_corpus(corpus_path)?;
+    let solvers = BackendKind::ALL
+        .iter()
+        .map(|&backend| {
+            Solver::new(SolveOptions {
+                backend,
+                ..*solver.options()
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = entries
+        .par_iter()
+        .filter(|entry| entry.untranslatable.is_empty())
+        .map(|entry| {
+            let outcomes = solvers
+                .iter()
+                .map(|solver| {
+                    let start = Instant::now();
+                    let summary = uniqueness_summary(&solver.check_uniqueness(&entry.grid));
+                    (summary, start.elapsed().as_secs_f64())
+                })
+                .collect::<Vec<_>>();
+            // Timeouts say nothing about the answer, so only the other outcomes must agree.
+            let known = outcomes
+                .iter()
+                .filter(|((status, _), _)| *status != "unknown")
+                .map(|(summary, _)| summary)
+                .collect::<Vec<_>>();
+            let status = if known.windows(2).any(|pair| pair[0] != pair[1]) {
+                "differs"
+            } else if known.len() < outcomes.len() {
+                "unknown"
+            } else {
+                "same"
+            };
+            (entry, status, outcomes)
         })
         .collect::<Vec<_>>();
 
-    let mut coord_solves = HashMap::new();
-    let mut coord_result = HashMap::new();
-    for (coord, color, result) in result {
-        coord_result.entry(coord).or_insert(SolveResult::Unknown);
-        match result {
-            GridSolveResult::Solved(grid) => {
-                let entry = coord_solves.entry(coord).or_insert(0);
-                *entry += 1;
-                if entry == &2 {
-                    coord_result.insert(coord, SolveResult::Unfillable);
-                }
+    let mut counts = HashMap::new();
+    for (entry, status, outcomes) in &results {
+        *counts.entry(*status).or_insert(0) += 1;
+        match format {
+            Format::Text => {
+                let backends = solvers
+                    .iter()
+                    .zip(outcomes)
+                    .map(|(solver, ((verdict, _), seconds))| {
+                        let name = solver.options().backend.backend().name();
+                        format!("{} {} {:.3}s", name, verdict, seconds)
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}\t{}\t{}", entry.pid, status, backends.join("\t"));
+            }
+            Format::Json => println!(
+                "{}",
+                json!({
+                    "pid": entry.pid,
+                    "status": status,
+                    "backends": solvers
+                        .iter()
+                        .zip(outcomes)
+                        .map(|(solver, ((verdict, _), seconds))| json!({
+                            "backend": solver.options().backend.backend().name(),
+                            "status": verdict,
+                            "seconds": seconds,
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+            ),
+        }
+    }
+    if format == Format::Text {
+        let mut counts = counts.iter().collect::<Vec<_>>();
+        counts.sort();
+        for (status, count) in counts {
+            println!("{}: {}", status, count);
+        }
+    }
+
+    Ok(if counts.contains_key("differs") {
+        EXIT_ERROR
+    } else if counts.contains_key("unknown") {
+        EXIT_UNKNOWN
+    } else {
+        EXIT_SOLVED
+    })
+}
+
+fn clue_kind_name(kind: ClueKind) -> &'static str {
+    match kind {
+        ClueKind::Given => "given",
+        ClueKind::AreaNumber => "area",
+        ClueKind::Viewpoint => "visible",
+        ClueKind::Dart => "dart",
+    }
+}
+
+fn run_minimize(solver: &Solver, format: Format, input: &Input) -> Result<u8, String> {
+    let puzzle = load_puzzle(input)?;
+    let (status, code, minimized) = match solver.minimize(&puzzle.grid) {
+        MinimizeResult::Minimized(minimized) => {
+            let code = if minimized.incomplete {
+                EXIT_UNKNOWN
+            } else {
+                EXIT_SOLVED
+            };
+            ("minimized", code, Some(minimized))
+        }
+        MinimizeResult::Multiple => ("multiple", EXIT_MULTIPLE, None),
+        MinimizeResult::Unsolvable => ("unsolvable", EXIT_UNSOLVABLE, None),
+        MinimizeResult::Unknown => ("unknown", EXIT_UNKNOWN, None),
+    };
+    let Some(minimized) = minimized else {
+        match format {
+            Format::Text => println!("{}", status),
+            Format::Json => println!("{}", json!({ "status": status })),
+        }
+        return Ok(code);
+    };
+    let describe = |clues: &[(Coord, ClueKind)]| {
+        clues
+            .iter()
+            .map(|(coord, kind)| format!("{} at ({}, {})", clue_kind_name(*kind), coord.i, coord.j))
+            .collect::<Vec<_>>()
+    };
+    match format {
+        Format::Text => {
+            for clue in describe(&minimized.removed) {
+                println!("Redundant: {}", clue);
             }
-            GridSolveResult::Unsolvable => {
-                coord_result.insert(coord, SolveResult::Definitely(color.opposite()));
+            println!(
+                "Kept {} of {} clues",
+                minimized.kept.len(),
+                minimized.kept.len() + minimized.removed.len()
+            );
+            if minimized.incomplete {
+                println!("Some checks timed out; kept clues may still be redundant");
             }
-            _ => {}
+            print!("{:?}", minimized.grid);
+            for line in clue_lines(&minimized.grid) {
+                println!("{}", line);
+            }
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "status": status,
+                "kept": describe(&minimized.kept),
+                "removed": describe(&minimized.removed),
+                "incomplete": minimized.incomplete,
+                "grid": grid_rows(&minimized.grid),
+                "clues": clue_lines(&minimized.grid),
+            })
+        ),
+    }
+    Ok(code)
+}
+
+// The clues of a puzzle other than given colors, one line each.
+fn clue_lines(grid: &Grid) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (coord, square) in grid.squares() {
+        let at = format!("({}, {})", coord.i, coord.j);
+        if let Some(number) = square.area_number {
+            lines.push(format!("area {} at {}", number, at));
+        }
+        if let Some(count) = square.visible_count {
+            lines.push(format!("visible {} at {}", count, at));
+        }
+        if let Some((direction, number)) = square.dart_number {
+            lines.push(format!("dart {:?} {} at {}", direction, number, at));
+        }
+    }
+    lines
+}
+
+fn run_generate(solver: &Solver, format: Format, args: &Generate) -> Result<u8, String> {
+    let mut rules = args
+        .connect
+        .iter()
+        .map(|color| {
+            Rule::ConnectAll(match color {
+                ColorArg::Light => Color::Light,
+                ColorArg::Dark => Color::Dark,
+            })
+        })
+        .collect::<Vec<_>>();
+    if args.no_2x2 {
+        for color in [Color::Light, Color::Dark] {
+            rules.push(Rule::BanPattern(GridPattern::square2x2(
+                color, color, color, color,
+            )));
+        }
+    }
+    let options = GeneratorOptions {
+        rows: args.rows,
+        cols: args.cols,
+        rules,
+        clues: args
+            .clues
+            .iter()
+            .map(|clue| match clue {
+                ClueArg::Given => ClueKind::Given,
+                ClueArg::Area => ClueKind::AreaNumber,
+                ClueArg::Viewpoint => ClueKind::Viewpoint,
+                ClueArg::Dart => ClueKind::Dart,
+            })
+            .collect(),
+        difficulty: args.difficulty,
+        attempts: args.attempts,
+        seed: args.seed,
+    };
+    let puzzle = solver.generate(&options)?;
+    match format {
+        Format::Text => {
+            print!("{:?}", puzzle.grid);
+            for line in clue_lines(&puzzle.grid) {
+                println!("{}", line);
+            }
+            println!();
+            print!("{:?}", puzzle.solution);
+            println!("Score: {:.2}", puzzle.difficulty);
+        }
+        Format::Json => println!(
+            "{}",
+            json!({
+                "grid": grid_rows(&puzzle.grid),
+                "clues": clue_lines(&puzzle.grid),
+                "rules": puzzle.grid.rules().iter().map(rule_name).collect::<Vec<_>>(),
+                "solution": grid_rows(&puzzle.solution),
+                "score": puzzle.difficulty,
+            })
+        ),
+    }
+    Ok(EXIT_SOLVED)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Some(threads) = cli.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to configure thread pool");
+    }
+    let solver = Solver::new(SolveOptions {
+        timeout: cli.timeout,
+        backend: cli.backend.0,
+    });
+    let format = cli.format;
+    let result = match &cli.command {
+        Command::Solve { input, unique } => {
+            if *unique {
+                run_unique(&solver, format, input)
+            } else {
+                run_solve(&solver, format, input)
+            }
+        }
+        Command::Enumerate {
+            input,
+            limit,
+            count,
+        } => run_enumerate(&solver, format, input, *limit, *count),
+        Command::Deduce(input) => run_deduce(&solver, format, input),
+        Command::Propagate(input) => run_propagate(&solver, format, input),
+        Command::Hint(input) => run_hint(&solver, format, input),
+        Command::Difficulty(input) => run_difficulty(&solver, format, input),
+        Command::DifficultyReport { corpus } => run_difficulty_report(&solver, format, corpus),
+        Command::Check { input, solution } => run_check(format, input, solution),
+        Command::Constraints { input, raw } => run_constraints(format, input, *raw),
+        Command::Batch { corpus } => run_batch(&solver, format, corpus),
+        Command::CompareBackends { corpus } => run_compare_backends(&solver, format, corpus),
+        Command::Minimize(input) => run_minimize(&solver, format, input),
+        Command::Generate(args) => run_generate(&solver, format, args),
+    };
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::from(EXIT_ERROR)
         }
     }
-    coord_result.into_iter().collect()
 }
@@ -0,0 +1,295 @@
+use crate::backend::{with_session, CheckResult, Literal};
+use crate::generator::ClueKind;
+use crate::grid::{Coord, Grid, Rule, RuleRef};
+use crate::solver::SolveOptions;
+
+// Finds the clues a puzzle with a unique solution can do without. Each clue is asserted under its
+// own literal and the solution is blocked, so a set of clues is enough exactly when assuming only
+// their literals is unsatisfiable.
+
+#[derive(Clone, Debug)]
+pub struct Minimized {
+    // The puzzle with only the kept clues.
+    pub grid: Grid,
+    pub kept: Vec<(Coord, ClueKind)>,
+    pub removed: Vec<(Coord, ClueKind)>,
+    // Whether some check timed out, so a kept clue may still be redundant.
+    pub incomplete: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum MinimizeResult {
+    Minimized(Minimized),
+    // The puzzle has several solutions even with every clue.
+    Multiple,
+    Unsolvable,
+    Unknown,
+}
+
+// The clues that may be removed, givens first as numbers tend to make for nicer puzzles. A dart
+// includes the color of its square. Area numbers are left alone under `ExactlyOneNumberPerRegion`,
+// where their squares matter beyond the numbers.
+pub fn removable_clues(grid: &Grid) -> Vec<(Coord, ClueKind)> {
+    let one_number_per_region = grid
+        .rules()
+        .iter()
+        .any(|rule| matches!(rule, Rule::ExactlyOneNumberPerRegion(_)));
+    let mut givens = Vec::new();
+    let mut numbers = Vec::new();
+    for (coord, square) in grid.squares() {
+        if square.color.is_some() && square.dart_number.is_none() {
+            givens.push((coord, ClueKind::Given));
+        }
+        if square.area_number.is_some() && !one_number_per_region {
+            numbers.push((coord, ClueKind::AreaNumber));
+        }
+        if square.visible_count.is_some() {
+            numbers.push((coord, ClueKind::Viewpoint));
+        }
+        if square.dart_number.is_some() {
+            numbers.push((coord, ClueKind::Dart));
+        }
+    }
+    givens.extend(numbers);
+    givens
+}
+
+pub fn minimize_clues(grid: &Grid, options: SolveOptions) -> MinimizeResult {
+    minimize_in_order(grid, &removable_clues(grid), options)
+}
+
+// Tries to remove `clues` one at a time in order, leaving a set of clues none of which can be
+// removed alone. Clues that are not in `removable_clues` are kept.
+pub fn minimize_in_order(
+    grid: &Grid,
+    clues: &[(Coord, ClueKind)],
+    options: SolveOptions,
+) -> MinimizeResult {
+    let removable = removable_clues(grid);
+    let clues = clues
+        .iter()
+        .filter(|clue| removable.contains(clue))
+        .copied()
+        .collect::<Vec<_>>();
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    with_session(backend, &prepared, options.timeout, true, |session| {
+        // The prepared rules of each clue; everything else is always assumed.
+        let clue_rules = clues
+            .iter()
+            .map(|&clue| clue_rules(grid, &prepared.sources, clue))
+            .collect::<Vec<_>>();
+        let fixed = (0..prepared.rules.len())
+            .filter(|rule| !clue_rules.iter().flatten().any(|other| other == rule))
+            .map(Literal::Rule)
+            .collect::<Vec<_>>();
+        let assumptions = |kept: &[bool]| {
+            let mut assumptions = fixed.clone();
+            for (rules, _) in clue_rules.iter().zip(kept).filter(|(_, &kept)| kept) {
+                assumptions.extend(rules.iter().map(|&rule| Literal::Rule(rule)));
+            }
+            assumptions
+        };
+
+        let mut kept = vec![true; clues.len()];
+        let colors = match session.check(&assumptions(&kept)) {
+            CheckResult::Sat => session.model(),
+            CheckResult::Unsat => return MinimizeResult::Unsolvable,
+            CheckResult::Unknown => return MinimizeResult::Unknown,
+        };
+        session.block(&colors);
+        match session.check(&assumptions(&kept)) {
+            CheckResult::Sat => return MinimizeResult::Multiple,
+            CheckResult::Unknown => return MinimizeResult::Unknown,
+            CheckResult::Unsat => {}
+        }
+
+        let mut incomplete = false;
+        for n in 0..clues.len() {
+            kept[n] = false;
+            match session.check(&assumptions(&kept)) {
+                CheckResult::Unsat => {}
+                CheckResult::Sat => kept[n] = true,
+                CheckResult::Unknown => {
+                    kept[n] = true;
+                    incomplete = true;
+                }
+            }
+        }
+        let mut minimized = grid.clone();
+        let (mut kept_clues, mut removed) = (Vec::new(), Vec::new());
+        for (&clue, kept) in clues.iter().zip(kept) {
+            if kept {
+                kept_clues.push(clue);
+            } else {
+                remove_clue(&mut minimized, clue);
+                removed.push(clue);
+            }
+        }
+        MinimizeResult::Minimized(Minimized {
+            grid: minimized,
+            kept: kept_clues,
+            removed,
+            incomplete,
+        })
+    })
+}
+
+fn clue_rules(grid: &Grid, sources: &[RuleRef], (coord, kind): (Coord, ClueKind)) -> Vec<usize> {
+    (0..sources.len())
+        .filter(|&rule| match (sources[rule], kind) {
+            (RuleRef::Given(given), ClueKind::Given | ClueKind::Dart) => given == coord,
+            (
+                RuleRef::Rule {
+                    index,
+                    clue: Some(clue),
+                },
+                _,
+            ) if clue == coord => matches!(
+                (&grid.rules()[index], kind),
+                (Rule::RegionAreaEqualsNumber, ClueKind::AreaNumber)
+                    | (Rule::VisibleCellCount, ClueKind::Viewpoint)
+                    | (Rule::DartNumbers, ClueKind::Dart)
+            ),
+            _ => false,
+        })
+        .collect()
+}
+
+fn remove_clue(grid: &mut Grid, (coord, kind): (Coord, ClueKind)) {
+    let (row, col) = (coord.i as usize, coord.j as usize);
+    match kind {
+        ClueKind::Given => grid.clear_color(row, col),
+        ClueKind::AreaNumber => grid.clear_area_number(row, col),
+        ClueKind::Viewpoint => grid.clear_visible_count(row, col),
+        ClueKind::Dart => grid.clear_dart_number(row, col),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::{Color, Direction, GridPattern};
+    use crate::solver::{check_uniqueness, Uniqueness};
+
+    fn colors(grid: &Grid) -> Vec<Option<Color>> {
+        grid.squares().map(|(_, square)| square.color).collect()
+    }
+
+    // Every square given and numbered with the area of its region.
+    fn fully_clued() -> Grid {
+        let mut grid = Grid::new(2, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        for (row, col, dark, area) in [
+            (0, 0, false, 3),
+            (0, 1, false, 3),
+            (0, 2, true, 2),
+            (1, 0, true, 1),
+            (1, 1, false, 3),
+            (1, 2, true, 2),
+        ] {
+            if dark {
+                grid.color_dark(row, col);
+            } else {
+                grid.color_light(row, col);
+            }
+            grid.set_area_number(row, col, area);
+        }
+        grid
+    }
+
+    fn minimized(grid: &Grid, clues: &[(Coord, ClueKind)], options: SolveOptions) -> Minimized {
+        match minimize_in_order(grid, clues, options) {
+            MinimizeResult::Minimized(minimized) => minimized,
+            result => panic!("{:?}: not minimized but {:?}", options.backend, result),
+        }
+    }
+
+    #[test]
+    fn kept_clues_are_unique_and_all_needed() {
+        let grid = fully_clued();
+        let clues = removable_clues(&grid);
+        assert_eq!(clues.len(), 12);
+        for options in test_options() {
+            let minimized = minimized(&grid, &clues, options);
+            assert!(!minimized.incomplete);
+            assert!(!minimized.removed.is_empty());
+            assert_eq!(minimized.kept.len() + minimized.removed.len(), clues.len());
+
+            let Uniqueness::Unique(solved) = check_uniqueness(&minimized.grid, options) else {
+                panic!("the minimized puzzle is not unique");
+            };
+            assert_eq!(colors(&solved), colors(&grid));
+            for &clue in &minimized.kept {
+                let mut without = minimized.grid.clone();
+                remove_clue(&mut without, clue);
+                assert!(
+                    matches!(
+                        check_uniqueness(&without, options),
+                        Uniqueness::Multiple(..)
+                    ),
+                    "{:?} could be removed",
+                    clue
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn removing_a_dart_clears_its_color() {
+        // Light and dark alternate, so the given light square forces the dart square either way.
+        let mut grid = Grid::new(1, 2);
+        for color in [Color::Light, Color::Dark] {
+            grid.add_rule(Rule::BanPattern(GridPattern {
+                pattern: vec![(Coord { i: 0, j: 0 }, color), (Coord { i: 0, j: 1 }, color)],
+            }));
+        }
+        grid.add_rule(Rule::DartNumbers);
+        grid.dart_number(0, 0, Direction::Right, 1, Color::Dark);
+        grid.color_light(0, 1);
+        let dart = (Coord { i: 0, j: 0 }, ClueKind::Dart);
+        let given = (Coord { i: 0, j: 1 }, ClueKind::Given);
+        assert_eq!(removable_clues(&grid), [given, dart]);
+
+        for options in test_options() {
+            let minimized = minimized(&grid, &[dart, given], options);
+            assert_eq!(minimized.removed, [dart]);
+            assert_eq!(minimized.kept, [given]);
+            let square = minimized.grid.square(dart.0).unwrap();
+            assert_eq!(square.color, None);
+            assert_eq!(square.dart_number, None);
+        }
+    }
+
+    #[test]
+    fn numbers_stay_under_one_number_per_region() {
+        // Each region keeps one number.
+        let mut grid = fully_clued();
+        grid.clear_area_number(0, 1);
+        grid.clear_area_number(1, 1);
+        grid.clear_area_number(1, 2);
+        grid.add_rule(Rule::ExactlyOneNumberPerRegion(Color::Light));
+        let clues = removable_clues(&grid);
+        assert_eq!(clues.len(), 6);
+        assert!(clues.iter().all(|&(_, kind)| kind == ClueKind::Given));
+
+        // Asking for them changes nothing.
+        let numbers = grid
+            .squares()
+            .filter(|(_, square)| square.area_number.is_some())
+            .map(|(coord, _)| (coord, ClueKind::AreaNumber))
+            .collect::<Vec<_>>();
+        assert_eq!(numbers.len(), 3);
+        for options in test_options() {
+            let minimized = minimized(&grid, &numbers, options);
+            assert!(minimized.kept.is_empty() && minimized.removed.is_empty());
+            let left = minimized
+                .grid
+                .squares()
+                .filter(|(_, square)| square.area_number.is_some())
+                .count();
+            assert_eq!(left, 3);
+        }
+    }
+}
@@ -0,0 +1,1009 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+
+use crate::grid::{Axis, Color, Coord, Direction, Grid, GridPattern, Rule};
+
+// Decoder for the binary logicGrid `pdata` format. This mirrors `Decoder` in
+// capture/puzzles.py; see that file for the layout.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Topology {
+    Merge(Vec<usize>),
+    Hole(Vec<usize>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodedRule {
+    Light(Vec<usize>),
+    Dark(Vec<usize>),
+    Area(Vec<(usize, usize)>),
+    Viewpoint(Vec<(usize, usize)>),
+    // (cell, number, direction)
+    Dart(Vec<(usize, usize, usize)>),
+    // Locations on the (2 * rows + 1) x (2 * cols + 1) half-cell lattice.
+    Galaxy(Vec<usize>),
+    Lotus(Vec<(usize, usize)>),
+    Myopia(Vec<(usize, usize)>),
+    Letters(Vec<(usize, usize)>),
+    BanPatterns(Vec<GridPattern>),
+    ConnectAll(Color),
+    OneSymbolPerRegion(Color),
+    ShapesDistinct(Color),
+    RegionArea(Color, usize),
+    ShapesSame(Color),
+    Unknown(usize, Vec<u8>),
+}
+
+impl DecodedRule {
+    // The name capture/puzzles.py gives this rule in decoded.json.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DecodedRule::Light(_) => "light",
+            DecodedRule::Dark(_) => "dark",
+            DecodedRule::Area(_) => "area",
+            DecodedRule::Viewpoint(_) => "viewpoint",
+            DecodedRule::Dart(_) => "dart",
+            DecodedRule::Galaxy(_) => "galaxy",
+            DecodedRule::Lotus(_) => "lotus",
+            DecodedRule::Myopia(_) => "myopia",
+            DecodedRule::Letters(_) => "letters",
+            DecodedRule::BanPatterns(_) => "ban_patterns",
+            DecodedRule::ConnectAll(Color::Light) => "connect_all_light",
+            DecodedRule::ConnectAll(Color::Dark) => "connect_all_dark",
+            DecodedRule::OneSymbolPerRegion(Color::Light) => "one_symbol_per_light",
+            DecodedRule::OneSymbolPerRegion(Color::Dark) => "one_symbol_per_dark",
+            DecodedRule::ShapesDistinct(Color::Light) => "light_shapes_distinct",
+            DecodedRule::ShapesDistinct(Color::Dark) => "dark_shapes_distinct",
+            DecodedRule::RegionArea(Color::Light, _) => "light_area",
+            DecodedRule::RegionArea(Color::Dark, _) => "dark_area",
+            DecodedRule::ShapesSame(Color::Light) => "light_shapes_same",
+            DecodedRule::ShapesSame(Color::Dark) => "dark_shapes_same",
+            DecodedRule::Unknown(..) => "unknown",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Solution {
+    // Kind 1: every cell is coloured.
+    Full(Vec<Vec<Color>>),
+    // Kind 2: the "Underconstrained Grid" case. Blank cells are the ones that are not forced.
+    Underconstrained(Vec<Vec<Option<Color>>>),
+}
+
+impl Solution {
+    pub fn color(&self, row: usize, col: usize) -> Option<Color> {
+        match self {
+            Solution::Full(cells) => Some(cells[row][col]),
+            Solution::Underconstrained(cells) => cells[row][col],
+        }
+    }
+
+    // Whether every existing square of `grid` has the color this solution expects, ignoring
+    // squares the solution leaves blank.
+    pub fn matches(&self, grid: &Grid) -> bool {
+        grid.squares().all(
+            |(coord, square)| match self.color(coord.i as usize, coord.j as usize) {
+                Some(color) => square.color == Some(color),
+                None => true,
+            },
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedGrid {
+    pub rows: usize,
+    pub cols: usize,
+    pub topology: Vec<Topology>,
+    pub rules: Vec<DecodedRule>,
+    pub solution: Option<Solution>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub grid: Grid,
+    pub solution: Option<Solution>,
+}
+
+// A decoded rule that cannot be expressed as a `grid::Rule`. `rule` indexes `DecodedGrid::rules`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Untranslatable {
+    Unsupported { rule: usize, name: &'static str },
+    DartWithoutColor { rule: usize, cell: usize },
+}
+
+impl Untranslatable {
+    pub fn rule(&self) -> usize {
+        match self {
+            Untranslatable::Unsupported { rule, .. } => *rule,
+            Untranslatable::DartWithoutColor { rule, .. } => *rule,
+        }
+    }
+}
+
+impl Display for Untranslatable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Untranslatable::Unsupported { name, .. } => {
+                write!(f, "rule `{}` is not supported by the solver", name)
+            }
+            Untranslatable::DartWithoutColor { cell, .. } => {
+                write!(f, "dart at cell {} has no given color", cell)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnexpectedEof,
+    VarintOverflow,
+    UnsupportedPuzzleKind(usize),
+    UnsupportedTopology(usize),
+    UnsupportedRule(usize),
+    UnsupportedSolution(usize),
+    InvalidSize(usize, usize),
+    CellOutOfRange(usize),
+    InvalidMergeEdge(usize),
+    MergeIntoHole(usize),
+    InvalidDirection(usize),
+    Untranslatable(Untranslatable),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            DecodeErrorKind::UnexpectedEof => write!(f, "unexpected end of data")?,
+            DecodeErrorKind::VarintOverflow => write!(f, "varint does not fit in usize")?,
+            DecodeErrorKind::UnsupportedPuzzleKind(kind) => {
+                write!(f, "unsupported puzzle kind {}", kind)?
+            }
+            DecodeErrorKind::UnsupportedTopology(kind) => {
+                write!(f, "unsupported topology kind {}", kind)?
+            }
+            DecodeErrorKind::UnsupportedRule(kind) => {
+                write!(f, "unsupported rule kind {:#x}", kind)?
+            }
+            DecodeErrorKind::UnsupportedSolution(kind) => {
+                write!(f, "unsupported solution kind {}", kind)?
+            }
+            DecodeErrorKind::InvalidSize(rows, cols) => {
+                write!(f, "invalid grid size {}x{}", rows, cols)?
+            }
+            DecodeErrorKind::CellOutOfRange(cell) => write!(f, "cell {} is out of range", cell)?,
+            DecodeErrorKind::InvalidMergeEdge(edge) => write!(f, "invalid merge edge {}", edge)?,
+            DecodeErrorKind::MergeIntoHole(edge) => write!(f, "merge edge {} joins a hole", edge)?,
+            DecodeErrorKind::InvalidDirection(direction) => {
+                write!(f, "invalid direction {}", direction)?
+            }
+            DecodeErrorKind::Untranslatable(untranslatable) => write!(f, "{}", untranslatable)?,
+        }
+        write!(f, " at byte {}", self.offset)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Keeps cell arithmetic well away from overflow on malformed input.
+pub(crate) const MAX_SIZE: usize = 0xffff;
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    rows: usize,
+    cols: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn error(&self, offset: usize, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError { offset, kind }
+    }
+
+    fn read(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| self.error(self.pos, DecodeErrorKind::UnexpectedEof))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>, DecodeError> {
+        (0..count).map(|_| self.read()).collect()
+    }
+
+    fn read_varint(&mut self) -> Result<usize, DecodeError> {
+        let start = self.pos;
+        let mut result: usize = 0;
+        loop {
+            let b = self.read()?;
+            result |= (b & 0x7f) as usize;
+            if b & 0x80 == 0 {
+                break;
+            }
+            if result > usize::MAX >> 7 {
+                return Err(self.error(start, DecodeErrorKind::VarintOverflow));
+            }
+            result <<= 7;
+        }
+        Ok(result)
+    }
+
+    fn read_cell(&mut self) -> Result<usize, DecodeError> {
+        let start = self.pos;
+        let cell = self.read_varint()?;
+        if cell >= self.rows * self.cols {
+            return Err(self.error(start, DecodeErrorKind::CellOutOfRange(cell)));
+        }
+        Ok(cell)
+    }
+
+    fn read_half_cell(&mut self) -> Result<usize, DecodeError> {
+        let start = self.pos;
+        let location = self.read_varint()?;
+        if location >= (2 * self.rows + 1) * (2 * self.cols + 1) {
+            return Err(self.error(start, DecodeErrorKind::CellOutOfRange(location)));
+        }
+        Ok(location)
+    }
+
+    fn read_direction(&mut self, limit: usize) -> Result<usize, DecodeError> {
+        let start = self.pos;
+        let direction = self.read_varint()?;
+        if direction > limit {
+            return Err(self.error(start, DecodeErrorKind::InvalidDirection(direction)));
+        }
+        Ok(direction)
+    }
+
+    fn read_list<T>(
+        &mut self,
+        mut read_item: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let count = self.read_varint()?;
+        (0..count).map(|_| read_item(self)).collect()
+    }
+
+    // Records the byte offset of each merge edge in `edge_offsets`.
+    fn read_topology(
+        &mut self,
+        edge_offsets: &mut HashMap<usize, usize>,
+    ) -> Result<Topology, DecodeError> {
+        let start = self.pos;
+        match self.read_varint()? {
+            0 => Ok(Topology::Merge(self.read_list(|d| {
+                let start = d.pos;
+                let edge = d.read_varint()?;
+                if merge_edge(d.rows, d.cols, edge).is_none() {
+                    return Err(d.error(start, DecodeErrorKind::InvalidMergeEdge(edge)));
+                }
+                edge_offsets.entry(edge).or_insert(start);
+                Ok(edge)
+            })?)),
+            1 => Ok(Topology::Hole(self.read_list(Self::read_cell)?)),
+            kind => Err(self.error(start, DecodeErrorKind::UnsupportedTopology(kind))),
+        }
+    }
+
+    fn read_pattern(&mut self) -> Result<GridPattern, DecodeError> {
+        let rows = self.read_varint()?;
+        let cols = self.read_varint()?;
+        let count = rows.saturating_mul(cols);
+        let data = self.read_bytes(count.div_ceil(4))?;
+        let cells = (0..count)
+            .map(|index| (data[index / 4] >> (6 - 2 * (index % 4))) & 3)
+            .collect::<Vec<_>>();
+        Ok(pattern_from_cells(cols, &cells))
+    }
+
+    fn read_rule(&mut self) -> Result<DecodedRule, DecodeError> {
+        let start = self.pos;
+        let rule = match self.read_varint()? {
+            0 => DecodedRule::Light(self.read_list(Self::read_cell)?),
+            1 => DecodedRule::Dark(self.read_list(Self::read_cell)?),
+            2 => DecodedRule::Area(self.read_list(|d| Ok((d.read_cell()?, d.read_varint()?)))?),
+            3 => {
+                DecodedRule::Viewpoint(self.read_list(|d| Ok((d.read_cell()?, d.read_varint()?)))?)
+            }
+            4 => DecodedRule::Dart(
+                self.read_list(|d| Ok((d.read_cell()?, d.read_varint()?, d.read_direction(3)?)))?,
+            ),
+            5 => DecodedRule::Galaxy(self.read_list(Self::read_half_cell)?),
+            6 => DecodedRule::Lotus(
+                self.read_list(|d| Ok((d.read_half_cell()?, d.read_direction(3)?)))?,
+            ),
+            7 => DecodedRule::Myopia(
+                self.read_list(|d| Ok((d.read_cell()?, d.read_direction(15)?)))?,
+            ),
+            8 => DecodedRule::Letters(self.read_list(|d| Ok((d.read_cell()?, d.read_varint()?)))?),
+            0x40 => DecodedRule::BanPatterns(self.read_list(Self::read_pattern)?),
+            0x41 => DecodedRule::ConnectAll(Color::Light),
+            0x42 => DecodedRule::ConnectAll(Color::Dark),
+            0x43 => DecodedRule::OneSymbolPerRegion(Color::Light),
+            0x44 => DecodedRule::OneSymbolPerRegion(Color::Dark),
+            0x45 => DecodedRule::ShapesDistinct(Color::Light),
+            0x46 => DecodedRule::ShapesDistinct(Color::Dark),
+            0x47 => DecodedRule::RegionArea(Color::Light, self.read_varint()?),
+            0x48 => DecodedRule::RegionArea(Color::Dark, self.read_varint()?),
+            0x49 => DecodedRule::ShapesSame(Color::Light),
+            0x4a => DecodedRule::ShapesSame(Color::Dark),
+            // Layout unknown; capture/puzzles.py skips 10 bytes as well.
+            0x7f => DecodedRule::Unknown(0x7f, self.read_bytes(10)?),
+            kind => return Err(self.error(start, DecodeErrorKind::UnsupportedRule(kind))),
+        };
+        Ok(rule)
+    }
+
+    fn read_solution(&mut self) -> Result<Solution, DecodeError> {
+        let start = self.pos;
+        let (rows, cols) = (self.rows, self.cols);
+        match self.read_varint()? {
+            1 => {
+                let data = self.read_bytes((rows * cols).div_ceil(8))?;
+                Ok(Solution::Full(
+                    (0..rows)
+                        .map(|i| {
+                            (0..cols)
+                                .map(|j| {
+                                    let index = i * cols + j;
+                                    match (data[index / 8] >> (7 - index % 8)) & 1 {
+                                        0 => Color::Light,
+                                        _ => Color::Dark,
+                                    }
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                ))
+            }
+            2 => {
+                let data = self.read_bytes((rows * cols).div_ceil(4))?;
+                Ok(Solution::Underconstrained(
+                    (0..rows)
+                        .map(|i| {
+                            (0..cols)
+                                .map(|j| {
+                                    let index = i * cols + j;
+                                    cell_color((data[index / 4] >> (6 - 2 * (index % 4))) & 3)
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                ))
+            }
+            kind => Err(self.error(start, DecodeErrorKind::UnsupportedSolution(kind))),
+        }
+    }
+
+    // Returns the decoded grid along with the byte offset of each rule.
+    fn decode(&mut self) -> Result<(DecodedGrid, Vec<usize>), DecodeError> {
+        self.read_varint()?;
+        let start = self.pos;
+        let kind = self.read_varint()?;
+        if kind > 0 {
+            return Err(self.error(start, DecodeErrorKind::UnsupportedPuzzleKind(kind)));
+        }
+        self.read_varint()?;
+        let start = self.pos;
+        self.rows = self.read_varint()?;
+        self.cols = self.read_varint()?;
+        if !(1..=MAX_SIZE).contains(&self.rows) || !(1..=MAX_SIZE).contains(&self.cols) {
+            return Err(self.error(start, DecodeErrorKind::InvalidSize(self.rows, self.cols)));
+        }
+
+        let mut edge_offsets = HashMap::new();
+        let topology = self.read_list(|d| d.read_topology(&mut edge_offsets))?;
+        let mut rule_offsets = Vec::new();
+        let rules = self.read_list(|d| {
+            rule_offsets.push(d.pos);
+            d.read_rule()
+        })?;
+        // Some captures end right after the rules.
+        let solution = if self.pos < self.data.len() {
+            Some(self.read_solution()?)
+        } else {
+            None
+        };
+        let decoded = DecodedGrid {
+            rows: self.rows,
+            cols: self.cols,
+            topology,
+            rules,
+            solution,
+        };
+        if let Some(edge) = decoded.merge_into_hole() {
+            return Err(self.error(edge_offsets[&edge], DecodeErrorKind::MergeIntoHole(edge)));
+        }
+        Ok((decoded, rule_offsets))
+    }
+}
+
+// 2-bit cell encoding shared by ban patterns and kind 2 solutions.
+pub(crate) fn cell_color(cell: u8) -> Option<Color> {
+    match cell {
+        0 => Some(Color::Light),
+        1 => Some(Color::Dark),
+        _ => None,
+    }
+}
+
+// Builds a ban pattern from row-major 2-bit cells, leaving out blank cells.
+pub(crate) fn pattern_from_cells(cols: usize, cells: &[u8]) -> GridPattern {
+    let pattern = cells
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &cell)| {
+            let coord = Coord {
+                i: (index / cols) as isize,
+                j: (index % cols) as isize,
+            };
+            cell_color(cell).map(|color| (coord, color))
+        })
+        .collect();
+    GridPattern { pattern }
+}
+
+// Splits a location on the half-cell lattice into its row and column.
+pub(crate) fn half_cell(cols: usize, location: usize) -> (usize, usize) {
+    (location / (2 * cols + 1), location % (2 * cols + 1))
+}
+
+// Maps a merge edge id to the square it joins and the direction of the join, using the same
+// arithmetic as `prepareGrid` in ui/src/Grid.js. Edges are numbered with the top border first,
+// then for each row its vertical edges (including both borders) followed by the edges below it.
+pub(crate) fn merge_edge(
+    rows: usize,
+    cols: usize,
+    edge: usize,
+) -> Option<(usize, usize, Direction)> {
+    let edge_id = edge / 2;
+    if edge_id < cols {
+        return None;
+    }
+    let row = (edge_id - cols) / (2 * cols + 1);
+    let edge_of_row = edge_id - cols - row * (2 * cols + 1);
+    if row >= rows {
+        return None;
+    }
+    if edge_of_row < cols + 1 {
+        if edge_of_row == 0 || edge_of_row == cols {
+            return None;
+        }
+        Some((row, edge_of_row - 1, Direction::Right))
+    } else {
+        if row + 1 >= rows {
+            return None;
+        }
+        Some((row, edge_of_row - cols - 1, Direction::Down))
+    }
+}
+
+impl DecodedGrid {
+    fn holes(&self) -> HashSet<usize> {
+        self.topology
+            .iter()
+            .flat_map(|topology| match topology {
+                Topology::Hole(cells) => cells.as_slice(),
+                Topology::Merge(_) => &[],
+            })
+            .copied()
+            .collect()
+    }
+
+    // Whether the merge `edge` joins a square to a hole, which no `Grid` can represent.
+    fn merges_hole(&self, edge: usize, holes: &HashSet<usize>) -> bool {
+        match merge_edge(self.rows, self.cols, edge) {
+            Some((row, col, direction)) => {
+                let cell = row * self.cols + col;
+                let other = match direction {
+                    Direction::Right => cell + 1,
+                    _ => cell + self.cols,
+                };
+                holes.contains(&cell) || holes.contains(&other)
+            }
+            None => false,
+        }
+    }
+
+    // The first merge edge that joins a square to a hole, if any.
+    pub fn merge_into_hole(&self) -> Option<usize> {
+        let holes = self.holes();
+        self.topology
+            .iter()
+            .flat_map(|topology| match topology {
+                Topology::Merge(edges) => edges.as_slice(),
+                Topology::Hole(_) => &[],
+            })
+            .copied()
+            .find(|&edge| self.merges_hole(edge, &holes))
+    }
+
+    // Builds a `Grid` from everything that can be translated, returning the rules that could not.
+    // Merges into holes are left out; see `merge_into_hole`.
+    pub fn to_grid(&self) -> (Grid, Vec<Untranslatable>) {
+        let cols = self.cols;
+        let mut grid = Grid::new(self.rows, self.cols);
+        let mut untranslatable = Vec::new();
+
+        let holes = self.holes();
+        for &cell in &holes {
+            grid.remove_square(cell / cols, cell % cols);
+        }
+        for topology in &self.topology {
+            if let Topology::Merge(edges) = topology {
+                for &edge in edges {
+                    if self.merges_hole(edge, &holes) {
+                        continue;
+                    }
+                    match merge_edge(self.rows, self.cols, edge) {
+                        Some((row, col, Direction::Right)) => grid.join_right(row, col),
+                        Some((row, col, _)) => grid.join_bottom(row, col),
+                        None => {}
+                    }
+                }
+            }
+        }
+
+        let mut has_galaxy = false;
+        let mut has_lotus = false;
+        let mut has_letters = false;
+        let mut has_myopia = false;
+        let mut has_area = false;
+        let mut has_viewpoint = false;
+        let mut darts = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            match rule {
+                DecodedRule::Light(cells) => {
+                    for &cell in cells {
+                        grid.color_light(cell / cols, cell % cols);
+                    }
+                }
+                DecodedRule::Dark(cells) => {
+                    for &cell in cells {
+                        grid.color_dark(cell / cols, cell % cols);
+                    }
+                }
+                DecodedRule::Area(cells) => {
+                    for &(cell, number) in cells {
+                        grid.set_area_number(cell / cols, cell % cols, number);
+                    }
+                    has_area = true;
+                }
+                DecodedRule::Viewpoint(cells) => {
+                    for &(cell, count) in cells {
+                        grid.visible_count(cell / cols, cell % cols, count);
+                    }
+                    has_viewpoint = true;
+                }
+                DecodedRule::Dart(cells) => {
+                    // Darts take the given color of their cell, so apply them once all givens are in.
+                    darts.push((index, cells));
+                }
+                DecodedRule::BanPatterns(patterns) => {
+                    for pattern in patterns {
+                        if !pattern.pattern.is_empty() {
+                            grid.add_rule(Rule::BanPattern(pattern.clone()));
+                        }
+                    }
+                }
+                DecodedRule::ConnectAll(color) => grid.add_rule(Rule::ConnectAll(*color)),
+                DecodedRule::OneSymbolPerRegion(color) => {
+                    grid.add_rule(Rule::ExactlyOneNumberPerRegion(*color))
+                }
+                DecodedRule::ShapesDistinct(color) => {
+                    grid.add_rule(Rule::RegionsHaveDifferentShapes(*color))
+                }
+                DecodedRule::RegionArea(color, size) => {
+                    grid.add_rule(Rule::RegionFixedSize(*color, *size))
+                }
+                DecodedRule::ShapesSame(color) => grid.add_rule(Rule::RegionsHaveSameShape(*color)),
+                DecodedRule::Galaxy(locations) => {
+                    for &location in locations {
+                        let (half_row, half_col) = half_cell(self.cols, location);
+                        grid.add_galaxy(half_row, half_col);
+                    }
+                    has_galaxy = true;
+                }
+                DecodedRule::Lotus(lotuses) => {
+                    for &(location, direction) in lotuses {
+                        let (half_row, half_col) = half_cell(self.cols, location);
+                        let axis = match direction {
+                            0 => Axis::Horizontal,
+                            1 => Axis::Diagonal,
+                            2 => Axis::Vertical,
+                            _ => Axis::AntiDiagonal,
+                        };
+                        grid.add_lotus(half_row, half_col, axis);
+                    }
+                    has_lotus = true;
+                }
+                DecodedRule::Letters(cells) => {
+                    for &(cell, letter) in cells {
+                        grid.set_letter(cell / cols, cell % cols, letter);
+                    }
+                    has_letters = true;
+                }
+                DecodedRule::Myopia(cells) => {
+                    for &(cell, mask) in cells {
+                        let arrows = Direction::ALL
+                            .into_iter()
+                            .filter(|direction| mask & direction.bit() as usize != 0)
+                            .collect::<Vec<_>>();
+                        grid.set_myopia(cell / cols, cell % cols, &arrows);
+                    }
+                    has_myopia = true;
+                }
+                DecodedRule::Unknown(..) => {
+                    untranslatable.push(Untranslatable::Unsupported {
+                        rule: index,
+                        name: rule.name(),
+                    });
+                }
+            }
+        }
+
+        for &(index, cells) in &darts {
+            for &(cell, number, direction) in cells {
+                let (row, col) = (cell / cols, cell % cols);
+                let direction = match direction {
+                    0 => Direction::Up,
+                    1 => Direction::Down,
+                    2 => Direction::Left,
+                    _ => Direction::Right,
+                };
+                let coord = Coord {
+                    i: row as isize,
+                    j: col as isize,
+                };
+                match grid.square(coord).and_then(|square| square.color) {
+                    Some(color) => grid.dart_number(row, col, direction, number, color),
+                    None => {
+                        untranslatable.push(Untranslatable::DartWithoutColor { rule: index, cell })
+                    }
+                }
+            }
+        }
+
+        if has_galaxy {
+            grid.add_rule(Rule::Galaxy);
+        }
+        if has_lotus {
+            grid.add_rule(Rule::Lotus);
+        }
+        if has_letters {
+            grid.add_rule(Rule::Letters);
+        }
+        if has_myopia {
+            grid.add_rule(Rule::Myopia);
+        }
+        if has_area {
+            grid.add_rule(Rule::RegionAreaEqualsNumber);
+        }
+        if has_viewpoint {
+            grid.add_rule(Rule::VisibleCellCount);
+        }
+        if !darts.is_empty() {
+            grid.add_rule(Rule::DartNumbers);
+        }
+        (grid, untranslatable)
+    }
+}
+
+// Puzzles.json stores `pdata` as standard base64.
+pub fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            data.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(data)
+}
+
+// Decodes `pdata` without translating it into a `Grid`.
+pub fn decode(data: &[u8]) -> Result<DecodedGrid, DecodeError> {
+    Decoder {
+        data,
+        pos: 0,
+        rows: 0,
+        cols: 0,
+    }
+    .decode()
+    .map(|(decoded, _)| decoded)
+}
+
+// Decodes `pdata` into a `Grid` and its expected solution. Rules the solver cannot express are
+// reported as errors at the offset of the rule.
+pub fn decode_puzzle(data: &[u8]) -> Result<Puzzle, DecodeError> {
+    let (decoded, rule_offsets) = Decoder {
+        data,
+        pos: 0,
+        rows: 0,
+        cols: 0,
+    }
+    .decode()?;
+    let (grid, untranslatable) = decoded.to_grid();
+    if let Some(first) = untranslatable.into_iter().next() {
+        return Err(DecodeError {
+            offset: rule_offsets[first.rule()],
+            kind: DecodeErrorKind::Untranslatable(first),
+        });
+    }
+    Ok(Puzzle {
+        grid,
+        solution: decoded.solution,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 3x3 puzzle with a merge, a hole, a dark given, an area number, a dart and connectivity,
+    // followed by a full solution. Its first varint takes two bytes.
+    const PUZZLE: &str = "giwAAAMDAgABCAEBCAQBAQQCAQADBAEEAQFBAS8A";
+    // A 2x2 puzzle with a ban pattern and a fixed dark area, followed by a partial solution.
+    const PATTERN_PUZZLE: &str = "AAAAAgIAAkABAQIPSIECAhs=";
+
+    fn encode_varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![(value & 0x7f) as u8];
+        value >>= 7;
+        while value > 0 {
+            bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.reverse();
+        bytes
+    }
+
+    fn decoder(data: &[u8]) -> Decoder<'_> {
+        Decoder {
+            data,
+            pos: 0,
+            rows: 0,
+            cols: 0,
+        }
+    }
+
+    fn error(data: &[u8]) -> DecodeError {
+        decode(data).unwrap_err()
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16383,
+            16384,
+            usize::MAX >> 7,
+            usize::MAX,
+        ] {
+            let bytes = encode_varint(value);
+            let mut decoder = decoder(&bytes);
+            assert_eq!(decoder.read_varint(), Ok(value));
+            assert_eq!(decoder.pos, bytes.len());
+        }
+    }
+
+    #[test]
+    fn base64_decodes() {
+        let data = decode_base64(PUZZLE).unwrap();
+        assert_eq!(
+            data,
+            [
+                130, 44, 0, 0, 3, 3, 2, 0, 1, 8, 1, 1, 8, 4, 1, 1, 4, 2, 1, 0, 3, 4, 1, 4, 1, 1,
+                65, 1, 47, 0
+            ]
+        );
+        assert_eq!(
+            decode_base64("AAAA Ag\nIAAk ABAQIPSIECAhs="),
+            decode_base64(PATTERN_PUZZLE)
+        );
+        assert_eq!(decode_base64("AA*A"), None);
+    }
+
+    #[test]
+    fn decodes_known_puzzle() {
+        let decoded = decode(&decode_base64(PUZZLE).unwrap()).unwrap();
+        assert_eq!((decoded.rows, decoded.cols), (3, 3));
+        assert_eq!(
+            decoded.topology,
+            [Topology::Merge(vec![8]), Topology::Hole(vec![8])]
+        );
+        assert_eq!(
+            decoded.rules,
+            [
+                DecodedRule::Dark(vec![4]),
+                DecodedRule::Area(vec![(0, 3)]),
+                DecodedRule::Dart(vec![(4, 1, 1)]),
+                DecodedRule::ConnectAll(Color::Light),
+            ]
+        );
+        let (l, d) = (Color::Light, Color::Dark);
+        assert_eq!(
+            decoded.solution,
+            Some(Solution::Full(vec![
+                vec![l, l, d],
+                vec![l, d, d],
+                vec![d, d, l]
+            ]))
+        );
+
+        let puzzle = decode_puzzle(&decode_base64(PUZZLE).unwrap()).unwrap();
+        let grid = &puzzle.grid;
+        let square = |i, j| grid.square(Coord { i, j });
+        assert!(square(0, 0).unwrap().merge_with_right);
+        assert!(square(2, 2).is_none());
+        assert_eq!(square(0, 0).unwrap().area_number, Some(3));
+        let dart = square(1, 1).unwrap();
+        assert_eq!(dart.color, Some(Color::Dark));
+        assert_eq!(dart.dart_number, Some((Direction::Down, 1)));
+        assert!(matches!(
+            grid.rules(),
+            [
+                Rule::ConnectAll(Color::Light),
+                Rule::RegionAreaEqualsNumber,
+                Rule::DartNumbers
+            ]
+        ));
+    }
+
+    #[test]
+    fn decodes_patterns_and_partial_solution() {
+        let decoded = decode(&decode_base64(PATTERN_PUZZLE).unwrap()).unwrap();
+        let pattern = GridPattern {
+            pattern: vec![
+                (Coord { i: 0, j: 0 }, Color::Light),
+                (Coord { i: 0, j: 1 }, Color::Light),
+            ],
+        };
+        assert_eq!(
+            decoded.rules,
+            [
+                DecodedRule::BanPatterns(vec![pattern]),
+                DecodedRule::RegionArea(Color::Dark, 130),
+            ]
+        );
+        assert_eq!(
+            decoded.solution,
+            Some(Solution::Underconstrained(vec![
+                vec![Some(Color::Light), Some(Color::Dark)],
+                vec![None, None]
+            ]))
+        );
+    }
+
+    #[test]
+    fn truncated_data_reports_where_it_ends() {
+        let data = decode_base64(PUZZLE).unwrap();
+        // Inside the first varint.
+        assert_eq!(
+            error(&data[..1]),
+            DecodeError {
+                offset: 1,
+                kind: DecodeErrorKind::UnexpectedEof,
+            }
+        );
+        // Inside the solution.
+        assert_eq!(
+            error(&data[..data.len() - 1]),
+            DecodeError {
+                offset: data.len() - 1,
+                kind: DecodeErrorKind::UnexpectedEof,
+            }
+        );
+    }
+
+    #[test]
+    fn overflowing_varints_report_where_they_start() {
+        let mut data = vec![0x82];
+        data.extend([0xff; 8]);
+        data.push(0x7f);
+        assert_eq!(
+            error(&data),
+            DecodeError {
+                offset: 0,
+                kind: DecodeErrorKind::VarintOverflow,
+            }
+        );
+
+        // The cell of a light given, after a 2x2 header, no topology and one rule.
+        let mut data = vec![0, 0, 0, 2, 2, 0, 1, 0, 1];
+        data.extend([0xff; 10]);
+        data.push(0x7f);
+        assert_eq!(
+            error(&data),
+            DecodeError {
+                offset: 9,
+                kind: DecodeErrorKind::VarintOverflow,
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_values_report_their_offset() {
+        assert_eq!(
+            error(&[0, 0, 0, 2, 2, 0, 1, 9]),
+            DecodeError {
+                offset: 7,
+                kind: DecodeErrorKind::UnsupportedRule(9),
+            }
+        );
+        assert_eq!(
+            error(&[0, 0, 0, 2, 2, 0, 1, 0, 2, 3, 4]),
+            DecodeError {
+                offset: 10,
+                kind: DecodeErrorKind::CellOutOfRange(4),
+            }
+        );
+        assert_eq!(
+            error(&[0, 0, 0, 0, 2]),
+            DecodeError {
+                offset: 3,
+                kind: DecodeErrorKind::InvalidSize(0, 2),
+            }
+        );
+    }
+
+    // A 2x2 grid whose only merge joins square 0 to the hole at square 1.
+    const MERGE_INTO_HOLE: [u8; 13] = [0, 0, 0, 2, 2, 2, 0, 1, 6, 1, 1, 1, 0];
+
+    #[test]
+    fn merge_into_hole_is_an_error() {
+        let err = decode(&MERGE_INTO_HOLE).unwrap_err();
+        assert_eq!(
+            err,
+            DecodeError {
+                offset: 8,
+                kind: DecodeErrorKind::MergeIntoHole(6),
+            }
+        );
+        assert!(decode_puzzle(&MERGE_INTO_HOLE).is_err());
+    }
+
+    #[test]
+    fn merge_into_hole_is_left_out_of_the_grid() {
+        // Edge 10 joins square 0 to square 2 below it.
+        let decoded = DecodedGrid {
+            rows: 2,
+            cols: 2,
+            topology: vec![Topology::Merge(vec![10]), Topology::Hole(vec![2])],
+            rules: Vec::new(),
+            solution: None,
+        };
+        assert_eq!(decoded.merge_into_hole(), Some(10));
+        let (grid, untranslatable) = decoded.to_grid();
+        assert!(untranslatable.is_empty());
+        let prepared = grid.prepare();
+        assert_eq!(prepared.squares.len(), 3);
+        assert!(prepared.rules.is_empty());
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::HashSet;
+
+use crate::backend::neighbors;
+use crate::grid::{Color, Coord, Grid, PreparedGrid, PreparedRule, RuleRef, SquareIndex};
+
+// Colors squares by local reasoning over the prepared rules, without a solver. Each propagator
+// looks at one rule and the colors known so far, and only colors a square when every solution
+// must have that color there, so the rules are run again and again until none of them colors
+// anything new.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Propagator {
+    // A square merged with a colored square.
+    Merge,
+    // The last square that keeps a banned pattern from appearing.
+    BanPattern,
+    // A square outside every path between squares that must connect, or on all of them.
+    Connectivity,
+    // A region that has all of its squares, or only one square left to grow into.
+    AreaSaturation,
+    // A dart that already counts enough squares, or needs every square left.
+    DartCount,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fix {
+    pub index: SquareIndex,
+    pub color: Color,
+    pub propagator: Propagator,
+    // The index of the prepared rule that forced the color.
+    pub rule: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Propagation {
+    // The color of each square, by square index, where known.
+    pub colors: Vec<Option<Color>>,
+    // The squares propagation colored, in order. Given colors are not included.
+    pub fixed: Vec<Fix>,
+    // The index of a prepared rule that cannot hold with the colors found, if any. The puzzle then
+    // has no solution, and the colors found need not mean anything.
+    pub contradiction: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedSquare {
+    pub coord: Coord,
+    pub color: Color,
+    pub propagator: Propagator,
+    pub source: RuleRef,
+}
+
+#[derive(Clone, Debug)]
+pub struct PropagateOutcome {
+    // The input grid with every square propagation colored.
+    pub grid: Grid,
+    pub fixed: Vec<FixedSquare>,
+    pub contradiction: Option<RuleRef>,
+}
+
+pub fn propagate_grid(grid: &Grid) -> PropagateOutcome {
+    let prepared = grid.prepare();
+    let propagation = propagate(&prepared);
+    let mut coords = vec![Coord { i: 0, j: 0 }; prepared.squares.len()];
+    for (coord, index) in &prepared.square_indexes {
+        coords[index.0] = *coord;
+    }
+    let mut propagated = grid.clone();
+    let fixed = propagation
+        .fixed
+        .iter()
+        .map(|fix| {
+            let coord = coords[fix.index.0];
+            propagated.set_color(coord.i as usize, coord.j as usize, fix.color);
+            FixedSquare {
+                coord,
+                color: fix.color,
+                propagator: fix.propagator,
+                source: prepared.sources[fix.rule],
+            }
+        })
+        .collect();
+    PropagateOutcome {
+        grid: propagated,
+        fixed,
+        contradiction: propagation.contradiction.map(|rule| prepared.sources[rule]),
+    }
+}
+
+pub fn propagate(grid: &PreparedGrid) -> Propagation {
+    let mut state = State {
+        colors: vec![None; grid.squares.len()],
+        neighbors: neighbors(grid),
+        fixed: Vec::new(),
+        contradiction: None,
+    };
+    for (rule, prepared) in grid.rules.iter().enumerate() {
+        if let PreparedRule::SquareIsColor(index, color) = prepared {
+            if state.colors[index.0].is_some_and(|known| known != *color) {
+                state.contradiction = Some(rule);
+            }
+            state.colors[index.0] = Some(*color);
+        }
+    }
+
+    // The squares each placement of a banned pattern covers, with the colors they must not all
+    // have.
+    let mut placements = Vec::new();
+    for (rule, prepared) in grid.rules.iter().enumerate() {
+        let PreparedRule::BanPattern(grid_pattern) = prepared else {
+            continue;
+        };
+        for i in 0..grid.size.i {
+            'outer: for j in 0..grid.size.j {
+                let offset = grid_pattern.offset(Coord { i, j });
+                let mut cells = Vec::new();
+                for (coord, color) in &offset.pattern {
+                    match grid.square_indexes.get(coord) {
+                        Some(index) => cells.push((index.0, *color)),
+                        None => continue 'outer,
+                    }
+                }
+                placements.push((rule, cells));
+            }
+        }
+    }
+
+    while state.contradiction.is_none() {
+        let before = state.fixed.len();
+        for (rule, cells) in &placements {
+            state.ban_pattern(*rule, cells);
+        }
+        for (rule, prepared) in grid.rules.iter().enumerate() {
+            match prepared {
+                PreparedRule::SquaresAreSameColor(a, b) => state.merge(rule, a.0, b.0),
+                PreparedRule::ConnectAll(color) => state.connect(rule, *color),
+                PreparedRule::RegionFixedSize(color, 0) => {
+                    for index in 0..state.colors.len() {
+                        state.set(index, color.opposite(), Propagator::AreaSaturation, rule);
+                    }
+                }
+                PreparedRule::RegionFixedSize(color, size) => {
+                    let mut seen = HashSet::new();
+                    for start in 0..state.colors.len() {
+                        if state.colors[start] == Some(*color) && !seen.contains(&start) {
+                            let region = state.region(start, |known| known == Some(*color));
+                            seen.extend(region.iter().copied());
+                            state.saturate(rule, start, *color, *size);
+                        }
+                    }
+                }
+                PreparedRule::RegionAreaEqualsNumber(index, number) => {
+                    if let Some(color) = state.colors[index.0] {
+                        state.saturate(rule, index.0, color, *number);
+                    }
+                }
+                PreparedRule::ColorCountInSet(count, color, set) => {
+                    state.count(rule, *count, *color, set);
+                }
+                _ => {}
+            }
+            if state.contradiction.is_some() {
+                break;
+            }
+        }
+        if state.fixed.len() == before {
+            break;
+        }
+    }
+    Propagation {
+        colors: state.colors,
+        fixed: state.fixed,
+        contradiction: state.contradiction,
+    }
+}
+
+struct State {
+    colors: Vec<Option<Color>>,
+    neighbors: Vec<Vec<usize>>,
+    fixed: Vec<Fix>,
+    contradiction: Option<usize>,
+}
+
+impl State {
+    fn set(&mut self, index: usize, color: Color, propagator: Propagator, rule: usize) {
+        match self.colors[index] {
+            Some(known) if known != color => self.contradiction = Some(rule),
+            Some(_) => {}
+            None => {
+                self.colors[index] = Some(color);
+                self.fixed.push(Fix {
+                    index: SquareIndex(index),
+                    color,
+                    propagator,
+                    rule,
+                });
+            }
+        }
+    }
+
+    fn merge(&mut self, rule: usize, a: usize, b: usize) {
+        match (self.colors[a], self.colors[b]) {
+            (Some(color), _) => self.set(b, color, Propagator::Merge, rule),
+            (None, Some(color)) => self.set(a, color, Propagator::Merge, rule),
+            (None, None) => {}
+        }
+    }
+
+    fn ban_pattern(&mut self, rule: usize, cells: &[(usize, Color)]) {
+        let mut unknown = None;
+        for &(index, color) in cells {
+            match self.colors[index] {
+                Some(known) if known != color => return,
+                Some(_) => {}
+                // Two unknown squares can still break the pattern either way.
+                None if unknown.is_some() => return,
+                None => unknown = Some((index, color)),
+            }
+        }
+        match unknown {
+            Some((index, color)) => self.set(index, color.opposite(), Propagator::BanPattern, rule),
+            None => self.contradiction = Some(rule),
+        }
+    }
+
+    // The squares connected to `start` through squares whose known color passes `allowed`.
+    fn region(&self, start: usize, allowed: impl Fn(Option<Color>) -> bool) -> Vec<usize> {
+        let mut region = vec![start];
+        let mut in_region = vec![false; self.colors.len()];
+        in_region[start] = true;
+        let mut next = 0;
+        while next < region.len() {
+            let current = region[next];
+            next += 1;
+            for &neighbor in &self.neighbors[current] {
+                if !in_region[neighbor] && allowed(self.colors[neighbor]) {
+                    in_region[neighbor] = true;
+                    region.push(neighbor);
+                }
+            }
+        }
+        region
+    }
+
+    // The region of `start` has the given color and size.
+    fn saturate(&mut self, rule: usize, start: usize, color: Color, size: usize) {
+        let region = self.region(start, |known| known == Some(color));
+        if region.len() > size {
+            self.contradiction = Some(rule);
+            return;
+        }
+        let mut frontier = Vec::new();
+        for &index in &region {
+            for &neighbor in &self.neighbors[index] {
+                if self.colors[neighbor].is_none() && !frontier.contains(&neighbor) {
+                    frontier.push(neighbor);
+                }
+            }
+        }
+        if region.len() == size {
+            // Any more squares of the color would join the region.
+            for index in frontier {
+                self.set(index, color.opposite(), Propagator::AreaSaturation, rule);
+            }
+            return;
+        }
+        match frontier.as_slice() {
+            [] => self.contradiction = Some(rule),
+            &[index] => self.set(index, color, Propagator::AreaSaturation, rule),
+            _ => {}
+        }
+        if self.contradiction.is_some() {
+            return;
+        }
+        // The region can only grow into squares it can reach without crossing the other color.
+        let reach = self.region(start, |known| known != Some(color.opposite()));
+        if reach.len() < size {
+            self.contradiction = Some(rule);
+        } else if reach.len() == size {
+            for index in reach {
+                self.set(index, color, Propagator::AreaSaturation, rule);
+            }
+        }
+    }
+
+    fn connect(&mut self, rule: usize, color: Color) {
+        let Some(root) = self.colors.iter().position(|&known| known == Some(color)) else {
+            return;
+        };
+        let allowed = |known: Option<Color>| known != Some(color.opposite());
+        let reach = self.region(root, allowed);
+        let mut reached = vec![false; self.colors.len()];
+        for &index in &reach {
+            reached[index] = true;
+        }
+        for (index, &reached) in reached.iter().enumerate() {
+            if reached {
+                continue;
+            }
+            match self.colors[index] {
+                Some(known) if known == color => {
+                    self.contradiction = Some(rule);
+                    return;
+                }
+                Some(_) => {}
+                None => self.set(index, color.opposite(), Propagator::Connectivity, rule),
+            }
+        }
+
+        // An unknown square whose removal cuts squares of the color off from the root must have
+        // the color. These are the articulation points of the reachable squares that separate a
+        // subtree of the search with a square of the color in it.
+        let n = self.colors.len();
+        let mut order = vec![usize::MAX; n];
+        let mut low = vec![0; n];
+        let mut colored = vec![0; n];
+        let mut cuts = Vec::new();
+        // Each entry is a square and how many of its neighbors have been looked at.
+        let mut stack = vec![(root, 0)];
+        let mut parent = vec![usize::MAX; n];
+        order[root] = 0;
+        let mut visited = 1;
+        while let Some(&(current, next)) = stack.last() {
+            if next == 0 && self.colors[current] == Some(color) {
+                colored[current] += 1;
+            }
+            if let Some(&neighbor) = self.neighbors[current].get(next) {
+                stack.last_mut().unwrap().1 += 1;
+                if !reached[neighbor] {
+                    continue;
+                }
+                if order[neighbor] == usize::MAX {
+                    order[neighbor] = visited;
+                    low[neighbor] = visited;
+                    visited += 1;
+                    parent[neighbor] = current;
+                    stack.push((neighbor, 0));
+                } else if neighbor != parent[current] {
+                    low[current] = low[current].min(order[neighbor]);
+                }
+                continue;
+            }
+            stack.pop();
+            let up = parent[current];
+            if up == usize::MAX {
+                continue;
+            }
+            low[up] = low[up].min(low[current]);
+            colored[up] += colored[current];
+            if low[current] >= order[up] && colored[current] > 0 && up != root {
+                cuts.push(up);
+            }
+        }
+        for index in cuts {
+            self.set(index, color, Propagator::Connectivity, rule);
+        }
+    }
+
+    fn count(&mut self, rule: usize, count: usize, color: Color, set: &[SquareIndex]) {
+        let known = set
+            .iter()
+            .filter(|index| self.colors[index.0] == Some(color))
+            .count();
+        let unknown = set
+            .iter()
+            .filter(|index| self.colors[index.0].is_none())
+            .collect::<Vec<_>>();
+        if known > count || known + unknown.len() < count {
+            self.contradiction = Some(rule);
+        } else if known == count {
+            for index in unknown {
+                self.set(index.0, color.opposite(), Propagator::DartCount, rule);
+            }
+        } else if known + unknown.len() == count {
+            for index in unknown {
+                self.set(index.0, color, Propagator::DartCount, rule);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{Direction, GridPattern, Rule};
+
+    fn at(i: isize, j: isize) -> Coord {
+        Coord { i, j }
+    }
+
+    // The squares propagation colored, in order.
+    fn fixed(grid: &Grid) -> Vec<(Coord, Color, Propagator)> {
+        let outcome = propagate_grid(grid);
+        assert_eq!(outcome.contradiction, None);
+        outcome
+            .fixed
+            .iter()
+            .map(|fixed| (fixed.coord, fixed.color, fixed.propagator))
+            .collect()
+    }
+
+    #[test]
+    fn merged_squares_share_their_color() {
+        let mut grid = Grid::new(1, 2);
+        grid.join_right(0, 0);
+        grid.color_dark(0, 0);
+        let outcome = propagate_grid(&grid);
+        assert_eq!(outcome.fixed[0].source, RuleRef::Merge(at(0, 0), at(0, 1)));
+        assert_eq!(fixed(&grid), [(at(0, 1), Color::Dark, Propagator::Merge)]);
+    }
+
+    #[test]
+    fn the_last_square_of_a_banned_pattern_is_forced() {
+        let mut grid = Grid::new(1, 3);
+        grid.add_rule(Rule::BanPattern(GridPattern {
+            pattern: (0..3).map(|j| (at(0, j), Color::Light)).collect(),
+        }));
+        grid.color_light(0, 0);
+        grid.color_light(0, 2);
+        assert_eq!(
+            fixed(&grid),
+            [(at(0, 1), Color::Dark, Propagator::BanPattern)]
+        );
+        grid.color_light(0, 1);
+        let outcome = propagate_grid(&grid);
+        assert_eq!(
+            outcome.contradiction,
+            Some(RuleRef::Rule {
+                index: 0,
+                clue: None
+            })
+        );
+    }
+
+    #[test]
+    fn a_cut_square_is_forced_to_connect() {
+        let mut grid = Grid::new(1, 3);
+        grid.add_rule(Rule::ConnectAll(Color::Dark));
+        grid.color_dark(0, 0);
+        grid.color_dark(0, 2);
+        assert_eq!(
+            fixed(&grid),
+            [(at(0, 1), Color::Dark, Propagator::Connectivity)]
+        );
+    }
+
+    #[test]
+    fn a_square_on_one_of_two_paths_is_not_forced() {
+        let mut grid = Grid::new(2, 2);
+        grid.add_rule(Rule::ConnectAll(Color::Dark));
+        grid.color_dark(0, 0);
+        grid.color_dark(1, 1);
+        assert_eq!(fixed(&grid), []);
+    }
+
+    #[test]
+    fn squares_cut_off_from_the_color_are_forced_away() {
+        let mut grid = Grid::new(1, 3);
+        grid.add_rule(Rule::ConnectAll(Color::Dark));
+        grid.color_dark(0, 0);
+        grid.color_light(0, 1);
+        assert_eq!(
+            fixed(&grid),
+            [(at(0, 2), Color::Light, Propagator::Connectivity)]
+        );
+    }
+
+    #[test]
+    fn a_region_grows_into_its_only_frontier_square() {
+        let mut grid = Grid::new(1, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_dark(0, 0);
+        grid.set_area_number(0, 0, 2);
+        // Once it has both its squares, the square after them is closed off.
+        assert_eq!(
+            fixed(&grid),
+            [
+                (at(0, 1), Color::Dark, Propagator::AreaSaturation),
+                (at(0, 2), Color::Light, Propagator::AreaSaturation)
+            ]
+        );
+    }
+
+    #[test]
+    fn a_region_without_a_frontier_must_be_complete() {
+        let mut grid = Grid::new(1, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_dark(0, 0);
+        grid.set_area_number(0, 0, 1);
+        grid.color_light(0, 1);
+        assert_eq!(fixed(&grid), []);
+        grid.set_area_number(0, 0, 2);
+        let outcome = propagate_grid(&grid);
+        assert_eq!(
+            outcome.contradiction,
+            Some(RuleRef::Rule {
+                index: 0,
+                clue: Some(at(0, 0))
+            })
+        );
+    }
+
+    #[test]
+    fn darts_color_the_squares_they_count() {
+        // A dark dart counts light squares.
+        let mut grid = Grid::new(1, 4);
+        grid.add_rule(Rule::DartNumbers);
+        grid.dart_number(0, 0, Direction::Right, 1, Color::Dark);
+        grid.color_light(0, 2);
+        assert_eq!(
+            fixed(&grid),
+            [
+                (at(0, 1), Color::Dark, Propagator::DartCount),
+                (at(0, 3), Color::Dark, Propagator::DartCount)
+            ]
+        );
+        grid.dart_number(0, 0, Direction::Right, 3, Color::Dark);
+        assert_eq!(
+            fixed(&grid),
+            [
+                (at(0, 1), Color::Light, Propagator::DartCount),
+                (at(0, 3), Color::Light, Propagator::DartCount)
+            ]
+        );
+        grid.dart_number(0, 0, Direction::Right, 4, Color::Dark);
+        assert!(propagate_grid(&grid).contradiction.is_some());
+    }
+}
@@ -0,0 +1,870 @@
+use std::ops::Not;
+use std::time::Instant;
+
+use crate::backend::CheckResult;
+
+// A small CDCL SAT solver: two watched literals, first-UIP learning with clause minimization,
+// VSIDS branching with phase saving, Luby restarts and activity-based clause deletion. Checks
+// take assumptions, report the assumptions behind an unsat result, and clauses can be added
+// between checks.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Lit(u32);
+
+impl Lit {
+    pub fn new(var: usize, positive: bool) -> Lit {
+        Lit(((var as u32) << 1) | (!positive) as u32)
+    }
+
+    pub fn var(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 & 1 == 0
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Not for Lit {
+    type Output = Lit;
+
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Value {
+    True,
+    False,
+    Unassigned,
+}
+
+struct Clause {
+    lits: Vec<Lit>,
+    learnt: bool,
+    activity: f64,
+    deleted: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Watch {
+    clause: usize,
+    // Some other literal of the clause; if it is true, the clause need not be looked at.
+    blocker: Lit,
+}
+
+// Variables ordered by activity, highest first.
+#[derive(Default)]
+struct VarHeap {
+    heap: Vec<usize>,
+    // Position of each variable in `heap`, if it is there.
+    positions: Vec<Option<usize>>,
+}
+
+impl VarHeap {
+    fn contains(&self, var: usize) -> bool {
+        self.positions[var].is_some()
+    }
+
+    fn insert(&mut self, var: usize, activity: &[f64]) {
+        if self.contains(var) {
+            return;
+        }
+        self.positions[var] = Some(self.heap.len());
+        self.heap.push(var);
+        self.sift_up(self.heap.len() - 1, activity);
+    }
+
+    fn pop(&mut self, activity: &[f64]) -> Option<usize> {
+        let top = *self.heap.first()?;
+        let last = self.heap.pop().unwrap();
+        self.positions[top] = None;
+        if !self.heap.is_empty() {
+            self.heap[0] = last;
+            self.positions[last] = Some(0);
+            self.sift_down(0, activity);
+        }
+        Some(top)
+    }
+
+    // Restores the order after the variable's activity went up.
+    fn increased(&mut self, var: usize, activity: &[f64]) {
+        if let Some(position) = self.positions[var] {
+            self.sift_up(position, activity);
+        }
+    }
+
+    fn sift_up(&mut self, mut position: usize, activity: &[f64]) {
+        let var = self.heap[position];
+        while position > 0 {
+            let parent = (position - 1) / 2;
+            if activity[self.heap[parent]] >= activity[var] {
+                break;
+            }
+            self.heap[position] = self.heap[parent];
+            self.positions[self.heap[position]] = Some(position);
+            position = parent;
+        }
+        self.heap[position] = var;
+        self.positions[var] = Some(position);
+    }
+
+    fn sift_down(&mut self, mut position: usize, activity: &[f64]) {
+        let var = self.heap[position];
+        loop {
+            let left = 2 * position + 1;
+            if left >= self.heap.len() {
+                break;
+            }
+            let right = left + 1;
+            let child = if right < self.heap.len()
+                && activity[self.heap[right]] > activity[self.heap[left]]
+            {
+                right
+            } else {
+                left
+            };
+            if activity[self.heap[child]] <= activity[var] {
+                break;
+            }
+            self.heap[position] = self.heap[child];
+            self.positions[self.heap[position]] = Some(position);
+            position = child;
+        }
+        self.heap[position] = var;
+        self.positions[var] = Some(position);
+    }
+}
+
+pub struct SatSolver {
+    clauses: Vec<Clause>,
+    learnts: usize,
+    watches: Vec<Vec<Watch>>,
+
+    values: Vec<Value>,
+    levels: Vec<usize>,
+    reasons: Vec<Option<usize>>,
+    trail: Vec<Lit>,
+    // Where each decision level starts in `trail`.
+    trail_limits: Vec<usize>,
+    propagated: usize,
+
+    activity: Vec<f64>,
+    activity_increment: f64,
+    clause_increment: f64,
+    order: VarHeap,
+    phases: Vec<bool>,
+    seen: Vec<bool>,
+
+    // False once the clauses are unsatisfiable without any assumptions.
+    ok: bool,
+    model: Vec<bool>,
+    core: Vec<Lit>,
+    conflicts: u64,
+    deadline: Option<Instant>,
+}
+
+impl Default for SatSolver {
+    fn default() -> Self {
+        SatSolver::new()
+    }
+}
+
+impl SatSolver {
+    pub fn new() -> SatSolver {
+        SatSolver {
+            clauses: Vec::new(),
+            learnts: 0,
+            watches: Vec::new(),
+            values: Vec::new(),
+            levels: Vec::new(),
+            reasons: Vec::new(),
+            trail: Vec::new(),
+            trail_limits: Vec::new(),
+            propagated: 0,
+            activity: Vec::new(),
+            activity_increment: 1.0,
+            clause_increment: 1.0,
+            order: VarHeap::default(),
+            phases: Vec::new(),
+            seen: Vec::new(),
+            ok: true,
+            model: Vec::new(),
+            core: Vec::new(),
+            conflicts: 0,
+            deadline: None,
+        }
+    }
+
+    pub fn new_var(&mut self) -> usize {
+        let var = self.values.len();
+        self.values.push(Value::Unassigned);
+        self.levels.push(0);
+        self.reasons.push(None);
+        self.activity.push(0.0);
+        self.phases.push(false);
+        self.seen.push(false);
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        self.order.positions.push(None);
+        self.order.insert(var, &self.activity);
+        var
+    }
+
+    pub fn var_count(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn clause_count(&self) -> usize {
+        self.clauses.len() - self.learnts
+    }
+
+    // Conflicts over all checks so far.
+    pub fn conflicts(&self) -> u64 {
+        self.conflicts
+    }
+
+    // Checks give up with `Unknown` once this has passed.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    // The value of a variable in the last satisfying assignment.
+    pub fn model_value(&self, var: usize) -> bool {
+        self.model[var]
+    }
+
+    // After an unsat check, assumptions that are unsatisfiable together.
+    pub fn core(&self) -> &[Lit] {
+        &self.core
+    }
+
+    fn value(&self, lit: Lit) -> Value {
+        match self.values[lit.var()] {
+            Value::Unassigned => Value::Unassigned,
+            Value::True if lit.is_positive() => Value::True,
+            Value::False if !lit.is_positive() => Value::True,
+            _ => Value::False,
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.trail_limits.len()
+    }
+
+    pub fn add_clause(&mut self, lits: &[Lit]) {
+        if !self.ok {
+            return;
+        }
+        self.backtrack(0);
+        let mut lits = lits.to_vec();
+        lits.sort();
+        lits.dedup();
+        if lits.windows(2).any(|pair| pair[0] == !pair[1]) {
+            return;
+        }
+        if lits.iter().any(|&lit| self.value(lit) == Value::True) {
+            return;
+        }
+        lits.retain(|&lit| self.value(lit) != Value::False);
+        match lits.len() {
+            0 => self.ok = false,
+            1 => {
+                self.assign(lits[0], None);
+                if self.propagate().is_some() {
+                    self.ok = false;
+                }
+            }
+            _ => {
+                self.attach(lits, false);
+            }
+        }
+    }
+
+    fn attach(&mut self, lits: Vec<Lit>, learnt: bool) -> usize {
+        let index = self.clauses.len();
+        self.watches[(!lits[0]).index()].push(Watch {
+            clause: index,
+            blocker: lits[1],
+        });
+        self.watches[(!lits[1]).index()].push(Watch {
+            clause: index,
+            blocker: lits[0],
+        });
+        self.clauses.push(Clause {
+            lits,
+            learnt,
+            activity: 0.0,
+            deleted: false,
+        });
+        if learnt {
+            self.learnts += 1;
+        }
+        index
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<usize>) {
+        let var = lit.var();
+        self.values[var] = if lit.is_positive() {
+            Value::True
+        } else {
+            Value::False
+        };
+        self.levels[var] = self.level();
+        self.reasons[var] = reason;
+        self.trail.push(lit);
+    }
+
+    fn backtrack(&mut self, level: usize) {
+        if self.level() <= level {
+            return;
+        }
+        let start = self.trail_limits[level];
+        for n in (start..self.trail.len()).rev() {
+            let var = self.trail[n].var();
+            self.phases[var] = self.trail[n].is_positive();
+            self.values[var] = Value::Unassigned;
+            self.reasons[var] = None;
+            self.order.insert(var, &self.activity);
+        }
+        self.trail.truncate(start);
+        self.trail_limits.truncate(level);
+        self.propagated = start;
+    }
+
+    // Propagates the trail, returning a clause that became false.
+    fn propagate(&mut self) -> Option<usize> {
+        while self.propagated < self.trail.len() {
+            let lit = self.trail[self.propagated];
+            self.propagated += 1;
+            let false_lit = !lit;
+            let mut watches = std::mem::take(&mut self.watches[lit.index()]);
+            let mut kept = 0;
+            let mut conflict = None;
+            let mut n = 0;
+            while n < watches.len() {
+                let watch = watches[n];
+                n += 1;
+                if self.value(watch.blocker) == Value::True {
+                    watches[kept] = watch;
+                    kept += 1;
+                    continue;
+                }
+                let clause = &mut self.clauses[watch.clause];
+                if clause.deleted {
+                    continue;
+                }
+                // Keep the false literal second, so the first is the one implied.
+                if clause.lits[0] == false_lit {
+                    clause.lits.swap(0, 1);
+                }
+                let first = clause.lits[0];
+                let watch = Watch {
+                    clause: watch.clause,
+                    blocker: first,
+                };
+                if self.value(first) == Value::True {
+                    watches[kept] = watch;
+                    kept += 1;
+                    continue;
+                }
+                let replacement = (2..self.clauses[watch.clause].lits.len())
+                    .find(|&k| self.value(self.clauses[watch.clause].lits[k]) != Value::False);
+                if let Some(k) = replacement {
+                    let clause = &mut self.clauses[watch.clause];
+                    clause.lits.swap(1, k);
+                    let new_watch = !clause.lits[1];
+                    self.watches[new_watch.index()].push(watch);
+                    continue;
+                }
+                watches[kept] = watch;
+                kept += 1;
+                if self.value(first) == Value::False {
+                    conflict = Some(watch.clause);
+                    while n < watches.len() {
+                        watches[kept] = watches[n];
+                        kept += 1;
+                        n += 1;
+                    }
+                } else {
+                    self.assign(first, Some(watch.clause));
+                }
+            }
+            watches.truncate(kept);
+            self.watches[lit.index()] = watches;
+            if conflict.is_some() {
+                self.propagated = self.trail.len();
+                return conflict;
+            }
+        }
+        None
+    }
+
+    fn bump_var(&mut self, var: usize) {
+        self.activity[var] += self.activity_increment;
+        if self.activity[var] > 1e100 {
+            for activity in &mut self.activity {
+                *activity *= 1e-100;
+            }
+            self.activity_increment *= 1e-100;
+        }
+        self.order.increased(var, &self.activity);
+    }
+
+    fn bump_clause(&mut self, clause: usize) {
+        self.clauses[clause].activity += self.clause_increment;
+        if self.clauses[clause].activity > 1e20 {
+            for clause in &mut self.clauses {
+                clause.activity *= 1e-20;
+            }
+            self.clause_increment *= 1e-20;
+        }
+    }
+
+    // First-UIP conflict analysis. Returns the learnt clause, asserting literal first, and the
+    // level to go back to.
+    fn analyze(&mut self, mut conflict: usize) -> (Vec<Lit>, usize) {
+        let mut learnt = vec![Lit(0)];
+        let mut pending = 0;
+        let mut index = self.trail.len();
+        let mut implied: Option<Lit> = None;
+        loop {
+            if self.clauses[conflict].learnt {
+                self.bump_clause(conflict);
+            }
+            let skip = usize::from(implied.is_some());
+            for k in skip..self.clauses[conflict].lits.len() {
+                let lit = self.clauses[conflict].lits[k];
+                let var = lit.var();
+                if self.seen[var] || self.levels[var] == 0 {
+                    continue;
+                }
+                self.seen[var] = true;
+                self.bump_var(var);
+                if self.levels[var] >= self.level() {
+                    pending += 1;
+                } else {
+                    learnt.push(lit);
+                }
+            }
+            loop {
+                index -= 1;
+                if self.seen[self.trail[index].var()] {
+                    break;
+                }
+            }
+            let lit = self.trail[index];
+            self.seen[lit.var()] = false;
+            pending -= 1;
+            implied = Some(lit);
+            if pending == 0 {
+                break;
+            }
+            conflict = self.reasons[lit.var()].unwrap();
+        }
+        learnt[0] = !implied.unwrap();
+
+        // Drop literals implied by the others.
+        let mut minimized = vec![learnt[0]];
+        for &lit in &learnt[1..] {
+            let redundant = self.reasons[lit.var()].is_some_and(|reason| {
+                self.clauses[reason].lits[1..]
+                    .iter()
+                    .all(|other| self.seen[other.var()] || self.levels[other.var()] == 0)
+            });
+            if !redundant {
+                minimized.push(lit);
+            }
+        }
+        for lit in &learnt[1..] {
+            self.seen[lit.var()] = false;
+        }
+        let mut learnt = minimized;
+
+        let mut level = 0;
+        if learnt.len() > 1 {
+            let highest = (1..learnt.len())
+                .max_by_key(|&k| self.levels[learnt[k].var()])
+                .unwrap();
+            learnt.swap(1, highest);
+            level = self.levels[learnt[1].var()];
+        }
+        (learnt, level)
+    }
+
+    // The assumptions that force `lit`, an assumption found false.
+    fn analyze_final(&mut self, lit: Lit) {
+        self.core = vec![lit];
+        if self.levels[lit.var()] == 0 {
+            return;
+        }
+        self.seen[lit.var()] = true;
+        for n in (self.trail_limits[0]..self.trail.len()).rev() {
+            let var = self.trail[n].var();
+            if !self.seen[var] {
+                continue;
+            }
+            match self.reasons[var] {
+                None => self.core.push(self.trail[n]),
+                Some(reason) => {
+                    for k in 1..self.clauses[reason].lits.len() {
+                        let other = self.clauses[reason].lits[k].var();
+                        if self.levels[other] > 0 {
+                            self.seen[other] = true;
+                        }
+                    }
+                }
+            }
+            self.seen[var] = false;
+        }
+        self.seen[lit.var()] = false;
+    }
+
+    // Deletes the less active half of the learnt clauses that are not reasons.
+    fn reduce(&mut self) {
+        let mut candidates = (0..self.clauses.len())
+            .filter(|&index| {
+                let clause = &self.clauses[index];
+                if !clause.learnt || clause.deleted || clause.lits.len() <= 2 {
+                    return false;
+                }
+                let locked = self.reasons[clause.lits[0].var()] == Some(index)
+                    && self.value(clause.lits[0]) == Value::True;
+                !locked
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|&a, &b| {
+            self.clauses[a]
+                .activity
+                .total_cmp(&self.clauses[b].activity)
+        });
+        for &index in &candidates[..candidates.len() / 2] {
+            let clause = &mut self.clauses[index];
+            clause.deleted = true;
+            clause.lits = Vec::new();
+            self.learnts -= 1;
+        }
+        for watches in &mut self.watches {
+            watches.retain(|watch| !self.clauses[watch.clause].deleted);
+        }
+    }
+
+    fn pick_branch(&mut self) -> Option<Lit> {
+        while let Some(var) = self.order.pop(&self.activity) {
+            if self.values[var] == Value::Unassigned {
+                return Some(Lit::new(var, self.phases[var]));
+            }
+        }
+        None
+    }
+
+    pub fn solve(&mut self, assumptions: &[Lit]) -> CheckResult {
+        self.core.clear();
+        if !self.ok {
+            return CheckResult::Unsat;
+        }
+        let mut restart = 0;
+        let mut max_learnts = (self.clause_count() / 3).max(2000) as f64;
+        loop {
+            let budget = 100 * luby(restart);
+            match self.search(assumptions, budget, max_learnts as usize) {
+                Some(result) => {
+                    self.backtrack(0);
+                    return result;
+                }
+                None => {
+                    restart += 1;
+                    max_learnts *= 1.05;
+                }
+            }
+        }
+    }
+
+    // Searches until `budget` conflicts have passed, returning None to restart.
+    fn search(
+        &mut self,
+        assumptions: &[Lit],
+        budget: u64,
+        max_learnts: usize,
+    ) -> Option<CheckResult> {
+        let mut conflicts = 0;
+        loop {
+            if let Some(conflict) = self.propagate() {
+                self.conflicts += 1;
+                conflicts += 1;
+                if self.level() == 0 {
+                    self.ok = false;
+                    return Some(CheckResult::Unsat);
+                }
+                let (learnt, level) = self.analyze(conflict);
+                self.backtrack(level);
+                if learnt.len() == 1 {
+                    self.assign(learnt[0], None);
+                } else {
+                    let asserting = learnt[0];
+                    let index = self.attach(learnt, true);
+                    self.bump_clause(index);
+                    self.assign(asserting, Some(index));
+                }
+                self.activity_increment /= 0.95;
+                self.clause_increment /= 0.999;
+                if self.conflicts.is_multiple_of(256)
+                    && self
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    return Some(CheckResult::Unknown);
+                }
+                continue;
+            }
+            if conflicts >= budget {
+                self.backtrack(0);
+                return None;
+            }
+            if self.learnts >= max_learnts + self.trail.len() {
+                self.reduce();
+            }
+            let mut next = None;
+            while self.level() < assumptions.len() {
+                let assumption = assumptions[self.level()];
+                match self.value(assumption) {
+                    Value::True => self.trail_limits.push(self.trail.len()),
+                    Value::False => {
+                        self.analyze_final(assumption);
+                        return Some(CheckResult::Unsat);
+                    }
+                    Value::Unassigned => {
+                        next = Some(assumption);
+                        break;
+                    }
+                }
+            }
+            let lit = match next.or_else(|| self.pick_branch()) {
+                Some(lit) => lit,
+                None => {
+                    self.model = self
+                        .values
+                        .iter()
+                        .map(|&value| value == Value::True)
+                        .collect();
+                    return Some(CheckResult::Sat);
+                }
+            };
+            self.trail_limits.push(self.trail.len());
+            self.assign(lit, None);
+        }
+    }
+}
+
+// The Luby sequence 1, 1, 2, 1, 1, 2, 4, ... used to space out restarts.
+fn luby(mut n: u64) -> u64 {
+    let mut size = 1;
+    let mut power = 1;
+    while size < n + 1 {
+        size = 2 * size + 1;
+        power *= 2;
+    }
+    while size - 1 != n {
+        size = (size - 1) / 2;
+        power /= 2;
+        n %= size;
+    }
+    power
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_clauses(vars: usize, clauses: &[&[i32]]) -> SatSolver {
+        let mut solver = SatSolver::new();
+        for _ in 0..vars {
+            solver.new_var();
+        }
+        for clause in clauses {
+            solver.add_clause(&lits(clause));
+        }
+        solver
+    }
+
+    // DIMACS-style literals: 1 is the first variable, -1 its negation.
+    fn lits(clause: &[i32]) -> Vec<Lit> {
+        clause
+            .iter()
+            .map(|&lit| Lit::new(lit.unsigned_abs() as usize - 1, lit > 0))
+            .collect()
+    }
+
+    fn satisfies(solver: &SatSolver, clauses: &[Vec<i32>]) -> bool {
+        clauses.iter().all(|clause| {
+            clause
+                .iter()
+                .any(|&lit| solver.model_value(lit.unsigned_abs() as usize - 1) == (lit > 0))
+        })
+    }
+
+    // `pigeons` pigeons in `holes` holes, each hole holding at most one.
+    fn pigeonhole(pigeons: usize, holes: usize) -> (usize, Vec<Vec<i32>>) {
+        let var = |pigeon: usize, hole: usize| (pigeon * holes + hole + 1) as i32;
+        let mut clauses = Vec::new();
+        for pigeon in 0..pigeons {
+            clauses.push((0..holes).map(|hole| var(pigeon, hole)).collect());
+        }
+        for hole in 0..holes {
+            for a in 0..pigeons {
+                for b in a + 1..pigeons {
+                    clauses.push(vec![-var(a, hole), -var(b, hole)]);
+                }
+            }
+        }
+        (pigeons * holes, clauses)
+    }
+
+    fn add_all(solver: &mut SatSolver, clauses: &[Vec<i32>]) {
+        for clause in clauses {
+            solver.add_clause(&lits(clause));
+        }
+    }
+
+    #[test]
+    fn unit_clauses_propagate() {
+        let mut solver = with_clauses(3, &[&[1], &[-1, 2], &[-2, 3]]);
+        assert_eq!(solver.solve(&[]), CheckResult::Sat);
+        assert!((0..3).all(|var| solver.model_value(var)));
+        assert_eq!(solver.conflicts(), 0);
+
+        solver.add_clause(&lits(&[-3]));
+        assert_eq!(solver.solve(&[]), CheckResult::Unsat);
+        // Unsat without assumptions stays unsat.
+        assert_eq!(solver.solve(&[]), CheckResult::Unsat);
+        assert!(solver.core().is_empty());
+    }
+
+    #[test]
+    fn learns_from_conflicts() {
+        let (vars, clauses) = pigeonhole(5, 4);
+        let mut solver = with_clauses(vars, &[]);
+        add_all(&mut solver, &clauses);
+        assert_eq!(solver.solve(&[]), CheckResult::Unsat);
+        assert!(solver.conflicts() > 0);
+
+        let (vars, clauses) = pigeonhole(4, 4);
+        let mut solver = with_clauses(vars, &[]);
+        add_all(&mut solver, &clauses);
+        assert_eq!(solver.solve(&[]), CheckResult::Sat);
+        assert!(satisfies(&solver, &clauses));
+    }
+
+    // Random 3-SAT around the threshold, compared with trying every assignment.
+    #[test]
+    fn agrees_with_brute_force() {
+        let mut state = 7u64;
+        let mut next = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        let vars = 10;
+        let (mut sat, mut unsat) = (0, 0);
+        for _ in 0..200 {
+            let clauses = (0..43)
+                .map(|_| {
+                    (0..3)
+                        .map(|_| {
+                            let var = next(vars) as i32 + 1;
+                            if next(2) == 0 {
+                                var
+                            } else {
+                                -var
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let expected = (0..1u32 << vars).any(|assignment| {
+                clauses.iter().all(|clause| {
+                    clause.iter().any(|&lit| {
+                        let value = assignment >> (lit.unsigned_abs() - 1) & 1 == 1;
+                        value == (lit > 0)
+                    })
+                })
+            });
+            let mut solver = with_clauses(vars as usize, &[]);
+            add_all(&mut solver, &clauses);
+            match solver.solve(&[]) {
+                CheckResult::Sat => {
+                    assert!(expected);
+                    assert!(satisfies(&solver, &clauses));
+                    sat += 1;
+                }
+                CheckResult::Unsat => {
+                    assert!(!expected);
+                    unsat += 1;
+                }
+                CheckResult::Unknown => panic!("no deadline was set"),
+            }
+        }
+        assert!(sat > 0 && unsat > 0);
+    }
+
+    #[test]
+    fn assumptions_hold_for_one_check() {
+        let mut solver = with_clauses(4, &[&[-1, 2], &[-2, -3]]);
+        assert_eq!(solver.solve(&lits(&[1])), CheckResult::Sat);
+        assert!(solver.model_value(0) && solver.model_value(1) && !solver.model_value(2));
+        assert_eq!(solver.solve(&lits(&[3])), CheckResult::Sat);
+        assert!(!solver.model_value(0) && !solver.model_value(1));
+        assert_eq!(solver.solve(&lits(&[4, 1, 3])), CheckResult::Unsat);
+        assert_eq!(solver.solve(&[]), CheckResult::Sat);
+
+        // Clauses can still be added after a check.
+        solver.add_clause(&lits(&[1]));
+        assert_eq!(solver.solve(&lits(&[3])), CheckResult::Unsat);
+        assert_eq!(solver.core(), lits(&[3]));
+    }
+
+    #[test]
+    fn core_holds_only_the_assumptions_that_conflict() {
+        let mut solver = with_clauses(5, &[&[-1, 2], &[-2, -3], &[4, 5]]);
+        let assumptions = lits(&[4, 1, -5, 3]);
+        assert_eq!(solver.solve(&assumptions), CheckResult::Unsat);
+        let mut core = solver.core().to_vec();
+        core.sort();
+        assert_eq!(core, lits(&[1, 3]));
+        assert_eq!(solver.solve(&core), CheckResult::Unsat);
+
+        // A core from search rather than propagation alone.
+        let (vars, clauses) = pigeonhole(4, 3);
+        let mut solver = with_clauses(vars + 2, &[]);
+        let (guard, unrelated) = (vars as i32 + 1, vars as i32 + 2);
+        for clause in &clauses {
+            let mut clause = clause.clone();
+            clause.push(-guard);
+            solver.add_clause(&lits(&clause));
+        }
+        assert_eq!(solver.solve(&lits(&[unrelated, guard])), CheckResult::Unsat);
+        assert_eq!(solver.core(), lits(&[guard]));
+        assert_eq!(solver.solve(&lits(&[unrelated])), CheckResult::Sat);
+    }
+
+    #[test]
+    fn deadline_gives_up() {
+        let (vars, clauses) = pigeonhole(9, 8);
+        let mut solver = with_clauses(vars, &[]);
+        add_all(&mut solver, &clauses);
+        solver.set_deadline(Some(Instant::now()));
+        assert_eq!(solver.solve(&[]), CheckResult::Unknown);
+    }
+
+    #[test]
+    fn luby_sequence() {
+        let sequence = (0..15).map(luby).collect::<Vec<_>>();
+        assert_eq!(sequence, [1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8]);
+    }
+}
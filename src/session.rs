@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use crate::backend::{with_session, CheckResult, Literal, Session};
+use crate::grid::{Color, Coord, Direction, Grid, PreparedGrid, PreparedRule, RuleRef};
+use crate::hints::{self, HintResult};
+use crate::ir::simplified_rule_constraints;
+use crate::solver::{colored_grid, Deduction, GridSolveResult, SolveOptions};
+
+// A puzzle kept loaded in a solver while its colors and clues change, for interactive use. The
+// grid is encoded once on a worker thread, as for `Solutions`, with each prepared rule under its
+// own literal. Every check prepares the current grid and assumes the literals of its rules, first
+// encoding the rules of clues the session has not seen yet, so the solver and what it has learned
+// carry over from one check to the next.
+
+type Job = Box<dyn FnOnce(&mut dyn Session) + Send>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Forced {
+    // What each uncolored square can be.
+    Deductions(Vec<(Coord, Deduction)>),
+    Unsolvable,
+    Unknown,
+}
+
+pub struct SolveSession {
+    grid: Grid,
+    // The grids to go back to, saved by `push`.
+    saved: Vec<Grid>,
+    // The literal of every prepared rule encoded so far.
+    encoded: HashMap<PreparedRule, Literal>,
+    jobs: Sender<Job>,
+}
+
+impl SolveSession {
+    // Squares can change color and clues afterwards, but the shape of the grid and its rules stay.
+    pub fn new(grid: &Grid, options: SolveOptions) -> SolveSession {
+        let prepared = grid.prepare();
+        let encoded = prepared
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(rule, prepared_rule)| (prepared_rule.clone(), Literal::Rule(rule)))
+            .collect();
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        thread::spawn(move || {
+            let backend = options.backend.backend();
+            with_session(backend, &prepared, options.timeout, true, |session| {
+                for job in receiver {
+                    job(session);
+                }
+            });
+        });
+        SolveSession {
+            grid: grid.clone(),
+            saved: Vec::new(),
+            encoded,
+            jobs,
+        }
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    // The square of a dart keeps a color until the dart is removed.
+    pub fn set_color(&mut self, coord: Coord, color: Option<Color>) {
+        let (row, col) = (coord.i as usize, coord.j as usize);
+        match color {
+            Some(color) => self.grid.set_color(row, col, color),
+            None if self
+                .grid
+                .square(coord)
+                .is_some_and(|square| square.dart_number.is_some()) => {}
+            None => self.grid.clear_color(row, col),
+        }
+    }
+
+    pub fn set_area_number(&mut self, coord: Coord, number: Option<usize>) {
+        let (row, col) = (coord.i as usize, coord.j as usize);
+        match number {
+            Some(number) => self.grid.set_area_number(row, col, number),
+            None => self.grid.clear_area_number(row, col),
+        }
+    }
+
+    pub fn set_visible_count(&mut self, coord: Coord, count: Option<usize>) {
+        let (row, col) = (coord.i as usize, coord.j as usize);
+        match count {
+            Some(count) => self.grid.visible_count(row, col, count),
+            None => self.grid.clear_visible_count(row, col),
+        }
+    }
+
+    // A dart comes with the color of its square, and removing it leaves the square uncolored.
+    pub fn set_dart_number(&mut self, coord: Coord, dart: Option<(Direction, usize, Color)>) {
+        let (row, col) = (coord.i as usize, coord.j as usize);
+        match dart {
+            Some((direction, number, color)) => {
+                self.grid.dart_number(row, col, direction, number, color)
+            }
+            None => self.grid.clear_dart_number(row, col),
+        }
+    }
+
+    // Saves the colors and clues, to return to them with `pop`.
+    pub fn push(&mut self) {
+        self.saved.push(self.grid.clone());
+    }
+
+    // Undoes the changes since the last `push`, if any.
+    pub fn pop(&mut self) -> bool {
+        match self.saved.pop() {
+            Some(grid) => {
+                self.grid = grid;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn check(&mut self) -> GridSolveResult {
+        if self.grid.validate().is_err() {
+            return GridSolveResult::Unsolvable;
+        }
+        let (prepared, rules) = self.rules();
+        let grid = self.grid.clone();
+        self.call(move |session| match session.check(&literals(&rules)) {
+            CheckResult::Unsat => GridSolveResult::Unsolvable,
+            CheckResult::Unknown => GridSolveResult::Unknown,
+            CheckResult::Sat => {
+                GridSolveResult::Solved(colored_grid(&grid, &prepared, &session.model()))
+            }
+        })
+    }
+
+    // Tests both colors of every uncolored square.
+    pub fn forced(&mut self) -> Forced {
+        if self.grid.validate().is_err() {
+            return Forced::Unsolvable;
+        }
+        let (prepared, rules) = self.rules();
+        let uncolored = self
+            .grid
+            .squares()
+            .filter(|(_, square)| square.color.is_none())
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        self.call(move |session| {
+            let literals = literals(&rules);
+            let first = match session.check(&literals) {
+                CheckResult::Unsat => return Forced::Unsolvable,
+                CheckResult::Unknown => return Forced::Unknown,
+                CheckResult::Sat => session.model(),
+            };
+            // Every model shows a color each square can take, which saves checking it again.
+            let mut possible = first.into_iter().enumerate().collect::<HashSet<_>>();
+            let deductions = uncolored
+                .into_iter()
+                .map(|coord| {
+                    let index = prepared.square_indexes[&coord];
+                    let mut solvable = 0;
+                    let mut unsolvable = None;
+                    for color in [Color::Light, Color::Dark] {
+                        if possible.contains(&(index.0, color)) {
+                            solvable += 1;
+                            continue;
+                        }
+                        let mut assumptions = literals.clone();
+                        assumptions.push(Literal::Color(index, color));
+                        match session.check(&assumptions) {
+                            CheckResult::Sat => {
+                                possible.extend(session.model().into_iter().enumerate());
+                                solvable += 1;
+                            }
+                            CheckResult::Unsat => unsolvable = Some(color),
+                            CheckResult::Unknown => {}
+                        }
+                    }
+                    let deduction = match unsolvable {
+                        _ if solvable == 2 => Deduction::Free,
+                        Some(color) => Deduction::Forced(color.opposite()),
+                        None => Deduction::Undetermined,
+                    };
+                    (coord, deduction)
+                })
+                .collect();
+            Forced::Deductions(deductions)
+        })
+    }
+
+    pub fn hint(&mut self) -> HintResult {
+        if self.grid.validate().is_err() {
+            return HintResult::Unsolvable;
+        }
+        let (prepared, rules) = self.rules();
+        let grid = self.grid.clone();
+        self.call(move |session| hints::hint_in_session(session, &grid, &prepared, &rules))
+    }
+
+    // The literals that enforce the current grid, each with where its rule comes from. Given
+    // colors are assumed as they are, and rules the session has not seen are encoded first.
+    fn rules(&mut self) -> (PreparedGrid, Vec<(Literal, RuleRef)>) {
+        let prepared = self.grid.prepare();
+        let mut rules = Vec::new();
+        let mut unseen = Vec::new();
+        for (rule, prepared_rule) in prepared.rules.iter().enumerate() {
+            let literal = match prepared_rule {
+                PreparedRule::SquareIsColor(index, color) => Literal::Color(*index, *color),
+                _ => match self.encoded.get(prepared_rule) {
+                    Some(&literal) => literal,
+                    None => {
+                        unseen.push(rule);
+                        continue;
+                    }
+                },
+            };
+            rules.push((literal, prepared.sources[rule]));
+        }
+        if !unseen.is_empty() {
+            let constraints = unseen
+                .iter()
+                .map(|&rule| simplified_rule_constraints(&prepared.rules[rule], &prepared))
+                .collect::<Vec<_>>();
+            let literals = self.call(move |session| {
+                constraints
+                    .iter()
+                    .map(|constraints| session.add(constraints))
+                    .collect::<Vec<_>>()
+            });
+            for (rule, literal) in unseen.into_iter().zip(literals) {
+                self.encoded.insert(prepared.rules[rule].clone(), literal);
+                rules.push((literal, prepared.sources[rule]));
+            }
+        }
+        (prepared, rules)
+    }
+
+    // Runs `f` on the worker thread's session and waits for its result.
+    fn call<R: Send + 'static>(&self, f: impl FnOnce(&mut dyn Session) -> R + Send + 'static) -> R {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::new(move |session: &mut dyn Session| {
+                let _ = sender.send(f(session));
+            }))
+            .expect("solver thread stopped");
+        receiver.recv().expect("solver thread stopped")
+    }
+}
+
+fn literals(rules: &[(Literal, RuleRef)]) -> Vec<Literal> {
+    rules.iter().map(|&(literal, _)| literal).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::Rule;
+    use crate::solver::{try_solve_grid, SolveStatus};
+
+    fn at(i: isize, j: isize) -> Coord {
+        Coord { i, j }
+    }
+
+    // A 2x3 grid of areas with several solutions.
+    fn areas() -> Grid {
+        let mut grid = Grid::new(2, 3);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.set_area_number(0, 0, 3);
+        grid.set_area_number(1, 2, 2);
+        grid
+    }
+
+    // What `forced` should find, from a fresh solve for each color of each uncolored square.
+    fn forced_from_scratch(grid: &Grid, options: SolveOptions) -> Forced {
+        match try_solve_grid(grid, options).status() {
+            SolveStatus::Unsolvable => return Forced::Unsolvable,
+            SolveStatus::Unknown => return Forced::Unknown,
+            SolveStatus::Solved => {}
+        }
+        let deductions = grid
+            .squares()
+            .filter(|(_, square)| square.color.is_none())
+            .map(|(coord, _)| {
+                let solvable = [Color::Light, Color::Dark].map(|color| {
+                    let mut grid = grid.clone();
+                    grid.set_color(coord.i as usize, coord.j as usize, color);
+                    try_solve_grid(&grid, options).status() == SolveStatus::Solved
+                });
+                let deduction = match solvable {
+                    [true, true] => Deduction::Free,
+                    [true, false] => Deduction::Forced(Color::Light),
+                    [false, true] => Deduction::Forced(Color::Dark),
+                    [false, false] => unreachable!("the grid has a solution"),
+                };
+                (coord, deduction)
+            })
+            .collect();
+        Forced::Deductions(deductions)
+    }
+
+    fn same_check(session: &mut SolveSession, options: SolveOptions) {
+        let expected = try_solve_grid(session.grid(), options).status();
+        match session.check() {
+            GridSolveResult::Solved(solution) => {
+                assert_eq!(expected, SolveStatus::Solved);
+                // The solution keeps the colors of the current grid.
+                for (coord, square) in session.grid().squares() {
+                    if let Some(color) = square.color {
+                        assert_eq!(solution.square(coord).unwrap().color, Some(color));
+                    }
+                }
+            }
+            result => assert_eq!(result.status(), expected),
+        }
+    }
+
+    #[test]
+    fn push_and_pop_agree_with_solving_from_scratch() {
+        let grid = areas();
+        for options in test_options() {
+            let mut session = SolveSession::new(&grid, options);
+            same_check(&mut session, options);
+            assert_eq!(session.forced(), forced_from_scratch(&grid, options));
+
+            session.push();
+            session.set_color(at(0, 1), Some(Color::Dark));
+            let forced = session.forced();
+            assert!(matches!(&forced, Forced::Deductions(deductions) if !deductions.is_empty()));
+            assert_eq!(forced, forced_from_scratch(session.grid(), options));
+            same_check(&mut session, options);
+
+            // The 3 can no longer reach a third square.
+            session.push();
+            session.set_color(at(0, 0), Some(Color::Light));
+            session.set_color(at(1, 0), Some(Color::Dark));
+            assert_eq!(session.forced(), Forced::Unsolvable);
+            assert_eq!(session.check().status(), SolveStatus::Unsolvable);
+
+            assert!(session.pop());
+            same_check(&mut session, options);
+            assert!(session.pop());
+            assert!(!session.pop());
+            assert_eq!(session.grid().square(at(0, 1)).unwrap().color, None);
+            same_check(&mut session, options);
+            assert_eq!(session.forced(), forced_from_scratch(&grid, options));
+        }
+    }
+
+    #[test]
+    fn clues_are_encoded_once() {
+        let grid = areas();
+        for options in test_options() {
+            let mut session = SolveSession::new(&grid, options);
+            let rules = session.encoded.len();
+
+            session.push();
+            session.set_area_number(at(1, 0), Some(1));
+            same_check(&mut session, options);
+            assert_eq!(session.encoded.len(), rules + 1);
+
+            // Colors are assumed, so they never need encoding.
+            session.set_color(at(0, 1), Some(Color::Light));
+            same_check(&mut session, options);
+            assert_eq!(session.encoded.len(), rules + 1);
+
+            assert!(session.pop());
+            same_check(&mut session, options);
+            session.set_area_number(at(1, 0), Some(1));
+            same_check(&mut session, options);
+            assert_eq!(
+                session.forced(),
+                forced_from_scratch(session.grid(), options)
+            );
+            assert_eq!(session.encoded.len(), rules + 1);
+
+            // Once the clue is gone again, its rule is left out of the checks.
+            session.set_area_number(at(1, 0), None);
+            same_check(&mut session, options);
+            assert_eq!(session.forced(), forced_from_scratch(&grid, options));
+        }
+    }
+}
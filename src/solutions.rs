@@ -0,0 +1,167 @@
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::backend::{with_session, CheckResult};
+use crate::grid::Grid;
+use crate::solver::{colored_grid, SolveOptions};
+
+enum Message {
+    Solution(Grid),
+    // Every solution has been sent.
+    Exhausted,
+}
+
+// Streams the solutions of a grid, one model at a time. The solver lives on a worker thread
+// that finds the next solution while the previous one is being consumed, and stops when this
+// iterator is dropped.
+pub struct Solutions {
+    receiver: Receiver<Message>,
+    exhausted: bool,
+}
+
+impl Solutions {
+    // Each solution is distinct in color; `limit` caps how many are looked for.
+    pub fn new(grid: &Grid, options: SolveOptions, limit: Option<usize>) -> Solutions {
+        let (sender, receiver) = mpsc::sync_channel(0);
+        let grid = grid.clone();
+        thread::spawn(move || {
+            if grid.validate().is_err() {
+                let _ = sender.send(Message::Exhausted);
+                return;
+            }
+            let prepared = grid.prepare();
+            let backend = options.backend.backend();
+            with_session(backend, &prepared, options.timeout, false, |session| {
+                let mut found = 0;
+                while limit.is_none_or(|limit| found < limit) {
+                    match session.check(&[]) {
+                        CheckResult::Unsat => {
+                            let _ = sender.send(Message::Exhausted);
+                            return;
+                        }
+                        CheckResult::Unknown => return,
+                        CheckResult::Sat => {
+                            // Block on colors only, since the region variables can take many
+                            // values for the same coloring.
+                            let colors = session.model();
+                            session.block(&colors);
+                            let solution = colored_grid(&grid, &prepared, &colors);
+                            if sender.send(Message::Solution(solution)).is_err() {
+                                return;
+                            }
+                            found += 1;
+                        }
+                    }
+                }
+            });
+        });
+        Solutions {
+            receiver,
+            exhausted: false,
+        }
+    }
+
+    // Whether every solution has been produced, as opposed to stopping at the limit or timing out.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
+
+impl Iterator for Solutions {
+    type Item = Grid;
+
+    fn next(&mut self) -> Option<Grid> {
+        match self.receiver.recv() {
+            Ok(Message::Solution(grid)) => Some(grid),
+            Ok(Message::Exhausted) => {
+                self.exhausted = true;
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::{Color, Rule};
+
+    fn colorings(solutions: &mut Solutions) -> HashSet<Vec<Option<Color>>> {
+        solutions
+            .map(|grid| grid.squares().map(|(_, square)| square.color).collect())
+            .collect()
+    }
+
+    #[test]
+    fn every_solution_is_found_once() {
+        let mut connected = Grid::new(2, 2);
+        connected.add_rule(Rule::ConnectAll(Color::Dark));
+        connected.color_dark(0, 0);
+        for options in test_options() {
+            let mut solutions = Solutions::new(&Grid::new(2, 2), options, None);
+            assert_eq!(colorings(&mut solutions).len(), 16);
+            assert!(solutions.is_exhausted());
+
+            let mut solutions = Solutions::new(&connected, options, None);
+            let found = colorings(&mut solutions);
+            // Dark (0, 0) alone, with one or both neighbours, with one neighbour and the far
+            // corner, or with every square.
+            assert_eq!(found.len(), 7);
+            assert!(found.iter().all(|colors| colors[0] == Some(Color::Dark)));
+            assert!(solutions.is_exhausted());
+        }
+    }
+
+    #[test]
+    fn limit_stops_early() {
+        let grid = Grid::new(2, 2);
+        for options in test_options() {
+            let mut solutions = Solutions::new(&grid, options, Some(5));
+            assert_eq!(colorings(&mut solutions).len(), 5);
+            assert!(!solutions.is_exhausted());
+
+            // Reaching the limit says nothing about whether more solutions exist.
+            let mut solutions = Solutions::new(&grid, options, Some(16));
+            assert_eq!(colorings(&mut solutions).len(), 16);
+            assert!(!solutions.is_exhausted());
+
+            let mut solutions = Solutions::new(&grid, options, Some(17));
+            assert_eq!(colorings(&mut solutions).len(), 16);
+            assert!(solutions.is_exhausted());
+        }
+    }
+
+    #[test]
+    fn unsolvable_grids_are_exhausted_at_once() {
+        let mut grid = Grid::new(1, 2);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_light(0, 0);
+        grid.color_light(0, 1);
+        grid.set_area_number(0, 0, 1);
+        for options in test_options() {
+            let mut solutions = Solutions::new(&grid, options, None);
+            assert!(solutions.next().is_none());
+            assert!(solutions.is_exhausted());
+        }
+    }
+
+    #[test]
+    fn dropping_stops_the_worker() {
+        for options in test_options() {
+            // Far more solutions than are read, so the worker is still waiting to send one.
+            let mut solutions = Solutions::new(&Grid::new(6, 6), options, None);
+            assert!(solutions.next().is_some());
+            assert!(solutions.next().is_some());
+            drop(solutions);
+
+            // The worker gives up once nobody receives, and solving goes on as before.
+            let mut solutions = Solutions::new(&Grid::new(1, 1), options, None);
+            assert_eq!(colorings(&mut solutions).len(), 2);
+            assert!(solutions.is_exhausted());
+        }
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use rayon::iter::ParallelIterator;
+use rayon::slice::ParallelSlice;
+
+use crate::backend::{with_session, BackendKind, CheckResult, Literal};
+use crate::difficulty::{self, DifficultyTrace};
+use crate::generator::{self, GeneratedPuzzle, GeneratorOptions};
+use crate::grid::{Color, Coord, Grid, Impossible, PreparedGrid, RuleRef};
+use crate::hints::{self, HintResult};
+use crate::minimizer::{self, MinimizeResult};
+use crate::pdata::Solution;
+use crate::propagate::{self, propagate_grid, FixedSquare, PropagateOutcome};
+use crate::session::SolveSession;
+use crate::solutions::Solutions;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SolveOptions {
+    // Timeout in seconds for each solver check.
+    pub timeout: u32,
+    pub backend: BackendKind,
+}
+
+impl Default for SolveOptions {
+    fn default() -> Self {
+        SolveOptions {
+            timeout: 60,
+            backend: BackendKind::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolveStatus {
+    Solved,
+    Unsolvable,
+    Unknown,
+}
+
+#[derive(Clone, Debug)]
+pub struct SolveOutcome {
+    pub status: SolveStatus,
+    // The grid with every square colored, if solved.
+    pub grid: Option<Grid>,
+    // Why the puzzle is unsolvable, if that is evident from the givens.
+    pub impossible: Option<Impossible>,
+    pub elapsed: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeduceOutcome {
+    // The input grid with every forced square colored.
+    pub grid: Grid,
+    // Squares that can be either color.
+    pub unfillable: Vec<Coord>,
+    // A rule that cannot hold with the squares forced so far, if deducing found the puzzle to have
+    // no solution. The squares left uncolored are then undetermined.
+    pub contradiction: Option<RuleRef>,
+    pub elapsed: Duration,
+}
+
+// What `solve_underconstrained` has found so far, reported as it goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeduceProgress {
+    // A round of solver checks begins, with this timeout in seconds for each.
+    Round { timeout: u32 },
+    // A square colored by local reasoning, without a solver check.
+    Propagated(FixedSquare),
+    Forced(Coord, Color),
+    Unfillable(Coord),
+}
+
+// What deducing found for a square, in the terms of an underconstrained (kind 2) solution.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deduction {
+    Forced(Color),
+    // Solutions exist with either color.
+    Free,
+    // Neither color was proven possible or impossible within the timeout.
+    Undetermined,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeductionMismatch {
+    pub coord: Coord,
+    pub expected: Deduction,
+    pub actual: Deduction,
+}
+
+impl DeduceOutcome {
+    pub fn deduction(&self, coord: Coord) -> Deduction {
+        match self.grid.square(coord).and_then(|square| square.color) {
+            Some(color) => Deduction::Forced(color),
+            None if self.unfillable.contains(&coord) => Deduction::Free,
+            None => Deduction::Undetermined,
+        }
+    }
+
+    pub fn deductions(&self) -> Vec<(Coord, Deduction)> {
+        self.grid
+            .squares()
+            .map(|(coord, _)| (coord, self.deduction(coord)))
+            .collect()
+    }
+
+    // Compares against an expected solution, where blank squares are expected to be free.
+    pub fn mismatches(&self, expected: &Solution) -> Vec<DeductionMismatch> {
+        self.deductions()
+            .into_iter()
+            .filter_map(|(coord, actual)| {
+                let expected = match expected.color(coord.i as usize, coord.j as usize) {
+                    Some(color) => Deduction::Forced(color),
+                    None => Deduction::Free,
+                };
+                (expected != actual).then_some(DeductionMismatch {
+                    coord,
+                    expected,
+                    actual,
+                })
+            })
+            .collect()
+    }
+}
+
+pub struct Solver {
+    options: SolveOptions,
+}
+
+impl Solver {
+    pub fn new(options: SolveOptions) -> Solver {
+        Solver { options }
+    }
+
+    pub fn options(&self) -> &SolveOptions {
+        &self.options
+    }
+
+    pub fn solve(&self, grid: &Grid) -> SolveOutcome {
+        let start = Instant::now();
+        if let Err(impossible) = grid.validate() {
+            return SolveOutcome {
+                status: SolveStatus::Unsolvable,
+                grid: None,
+                impossible: Some(impossible),
+                elapsed: start.elapsed(),
+            };
+        }
+        let result = try_solve_grid(grid, self.options);
+        let status = result.status();
+        let grid = match result {
+            GridSolveResult::Solved(grid) => Some(grid),
+            _ => None,
+        };
+        SolveOutcome {
+            status,
+            grid,
+            impossible: None,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    pub fn check_uniqueness(&self, grid: &Grid) -> Uniqueness {
+        check_uniqueness(grid, self.options)
+    }
+
+    // Streams distinct solutions, stopping after `limit` if given.
+    pub fn solutions(&self, grid: &Grid, limit: Option<usize>) -> Solutions {
+        Solutions::new(grid, self.options, limit)
+    }
+
+    // Keeps the grid loaded in a solver while its colors and clues change.
+    pub fn session(&self, grid: &Grid) -> SolveSession {
+        SolveSession::new(grid, self.options)
+    }
+
+    pub fn difficulty(&self, grid: &Grid) -> DifficultyTrace {
+        difficulty::trace_difficulty(grid, self.options)
+    }
+
+    pub fn hint(&self, grid: &Grid) -> HintResult {
+        hints::next_hint(grid, self.options)
+    }
+
+    pub fn minimize(&self, grid: &Grid) -> MinimizeResult {
+        minimizer::minimize_clues(grid, self.options)
+    }
+
+    pub fn generate(&self, options: &GeneratorOptions) -> Result<GeneratedPuzzle, String> {
+        generator::generate(options, self.options)
+    }
+
+    // Colors the squares that local reasoning forces, without a solver.
+    pub fn propagate(&self, grid: &Grid) -> PropagateOutcome {
+        propagate::propagate_grid(grid)
+    }
+
+    // Finds every forced square, with per-check timeouts growing up to `options.timeout`.
+    pub fn deduce(&self, grid: &Grid) -> DeduceOutcome {
+        self.deduce_with_progress(grid, |_| {})
+    }
+
+    pub fn deduce_with_progress(
+        &self,
+        grid: &Grid,
+        mut progress: impl FnMut(DeduceProgress),
+    ) -> DeduceOutcome {
+        let start = Instant::now();
+        let mut grid = grid.clone();
+        let (mut unfillable, contradiction) =
+            match solve_underconstrained(&mut grid, self.options, &mut progress) {
+                Ok(unfillable) => (unfillable.into_iter().collect::<Vec<_>>(), None),
+                Err(contradiction) => (Vec::new(), Some(contradiction)),
+            };
+        unfillable.sort();
+        DeduceOutcome {
+            grid,
+            unfillable,
+            contradiction,
+            elapsed: start.elapsed(),
+        }
+    }
+}
+
+// Fails with the broken rule if propagation finds that the puzzle has no solution.
+pub fn solve_underconstrained(
+    grid: &mut Grid,
+    options: SolveOptions,
+    progress: &mut dyn FnMut(DeduceProgress),
+) -> Result<HashSet<Coord>, RuleRef> {
+    let mut unfillable = HashSet::new();
+
+    let max_timeout = options.timeout;
+    let mut timeout = 1;
+    loop {
+        // Local reasoning settles many squares without a solver check, including ones that only
+        // follow from squares the last round found.
+        let propagated = propagate_grid(grid);
+        if let Some(contradiction) = propagated.contradiction {
+            return Err(contradiction);
+        }
+        for &fixed in &propagated.fixed {
+            progress(DeduceProgress::Propagated(fixed));
+        }
+        *grid = propagated.grid;
+        progress(DeduceProgress::Round { timeout });
+        let solved = par_solve_grid(grid, &unfillable, SolveOptions { timeout, ..options });
+        if solved.is_empty() {
+            break;
+        }
+        let any_filled = solved
+            .iter()
+            .any(|(_, result)| matches!(result, SolveResult::Definitely(_)));
+        for (coord, result) in solved {
+            match result {
+                SolveResult::Definitely(color) => {
+                    progress(DeduceProgress::Forced(coord, color));
+                    grid.set_color(coord.i as usize, coord.j as usize, color);
+                }
+                SolveResult::Unfillable => {
+                    progress(DeduceProgress::Unfillable(coord));
+                    unfillable.insert(coord);
+                }
+                SolveResult::Unknown => {}
+            }
+        }
+        if !any_filled {
+            if timeout >= max_timeout {
+                break;
+            }
+            timeout = (timeout * 2).min(max_timeout);
+        }
+    }
+    Ok(unfillable)
+}
+
+pub enum GridSolveResult {
+    Solved(Grid),
+    Unsolvable,
+    Unknown,
+}
+
+impl GridSolveResult {
+    pub fn status(&self) -> SolveStatus {
+        match self {
+            GridSolveResult::Solved(_) => SolveStatus::Solved,
+            GridSolveResult::Unsolvable => SolveStatus::Unsolvable,
+            GridSolveResult::Unknown => SolveStatus::Unknown,
+        }
+    }
+}
+
+pub fn try_solve_grid(grid: &Grid, options: SolveOptions) -> GridSolveResult {
+    if grid.validate().is_err() {
+        return GridSolveResult::Unsolvable;
+    }
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    with_session(
+        backend,
+        &prepared,
+        options.timeout,
+        false,
+        |session| match session.check(&[]) {
+            CheckResult::Unsat => GridSolveResult::Unsolvable,
+            CheckResult::Unknown => GridSolveResult::Unknown,
+            CheckResult::Sat => {
+                GridSolveResult::Solved(colored_grid(grid, &prepared, &session.model()))
+            }
+        },
+    )
+}
+
+pub enum Uniqueness {
+    Unique(Grid),
+    // Two different solutions.
+    Multiple(Grid, Grid),
+    Unsolvable,
+    Unknown,
+}
+
+// Finds a solution, then rules out its coloring and asks again.
+pub fn check_uniqueness(grid: &Grid, options: SolveOptions) -> Uniqueness {
+    if grid.validate().is_err() {
+        return Uniqueness::Unsolvable;
+    }
+    let prepared = grid.prepare();
+    let backend = options.backend.backend();
+    with_session(backend, &prepared, options.timeout, false, |session| {
+        let first = match session.check(&[]) {
+            CheckResult::Unsat => return Uniqueness::Unsolvable,
+            CheckResult::Unknown => return Uniqueness::Unknown,
+            CheckResult::Sat => session.model(),
+        };
+        session.block(&first);
+        let first = colored_grid(grid, &prepared, &first);
+        match session.check(&[]) {
+            CheckResult::Unsat => Uniqueness::Unique(first),
+            CheckResult::Unknown => Uniqueness::Unknown,
+            CheckResult::Sat => {
+                Uniqueness::Multiple(first, colored_grid(grid, &prepared, &session.model()))
+            }
+        }
+    })
+}
+
+// Copies the grid with each square colored according to `colors`, by square index.
+pub(crate) fn colored_grid(grid: &Grid, prepared: &PreparedGrid, colors: &[Color]) -> Grid {
+    let mut grid = grid.clone();
+    for (coord, index) in &prepared.square_indexes {
+        grid.set_color(coord.i as usize, coord.j as usize, colors[index.0]);
+    }
+    grid
+}
+
+pub enum SolveResult {
+    Definitely(Color),
+    Unfillable,
+    Unknown,
+}
+
+// Tests both colors of every unfilled square. The squares are split between the workers, and each
+// worker encodes the grid once and checks its squares under assumptions, so that learned clauses
+// carry over from one square to the next.
+pub fn par_solve_grid(
+    grid: &Grid,
+    unfillable: &HashSet<Coord>,
+    options: SolveOptions,
+) -> Vec<(Coord, SolveResult)> {
+    let unfilled_squares = grid
+        .squares()
+        .filter(|(coord, square)| !unfillable.contains(coord) && square.color.is_none())
+        .map(|(coord, _)| coord)
+        .collect::<Vec<_>>();
+    if unfilled_squares.is_empty() {
+        return Vec::new();
+    }
+    let prepared = grid.prepare();
+    let chunk_size = unfilled_squares
+        .len()
+        .div_ceil(rayon::current_num_threads());
+    unfilled_squares
+        .par_chunks(chunk_size)
+        .flat_map_iter(|coords| solve_squares(&prepared, coords, options))
+        .collect()
+}
+
+fn solve_squares(
+    prepared: &PreparedGrid,
+    coords: &[Coord],
+    options: SolveOptions,
+) -> Vec<(Coord, SolveResult)> {
+    let backend = options.backend.backend();
+    with_session(backend, prepared, options.timeout, false, |session| {
+        // Every model shows a color each square can take, which saves checking it again.
+        let mut possible = HashSet::new();
+        coords
+            .iter()
+            .map(|&coord| {
+                let index = prepared.square_indexes[&coord];
+                let mut solvable = 0;
+                let mut unsolvable = None;
+                for color in [Color::Light, Color::Dark] {
+                    if possible.contains(&(index.0, color)) {
+                        solvable += 1;
+                        continue;
+                    }
+                    match session.check(&[Literal::Color(index, color)]) {
+                        CheckResult::Sat => {
+                            possible.extend(session.model().into_iter().enumerate());
+                            solvable += 1;
+                        }
+                        CheckResult::Unsat => unsolvable = Some(color),
+                        CheckResult::Unknown => {}
+                    }
+                }
+                let result = match unsolvable {
+                    _ if solvable == 2 => SolveResult::Unfillable,
+                    Some(color) => SolveResult::Definitely(color.opposite()),
+                    None => SolveResult::Unknown,
+                };
+                (coord, result)
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::test_options;
+    use crate::grid::Rule;
+    use crate::propagate::Propagator;
+
+    #[test]
+    fn deduce_reports_propagated_squares() {
+        let mut grid = Grid::new(2, 2);
+        grid.color_dark(0, 0);
+        grid.join_bottom(0, 0);
+        for solver in test_options().map(Solver::new) {
+            let mut propagated = Vec::new();
+            let outcome = solver.deduce_with_progress(&grid, |progress| {
+                if let DeduceProgress::Propagated(fixed) = progress {
+                    propagated.push((fixed.coord, fixed.color, fixed.propagator));
+                }
+            });
+            assert_eq!(
+                propagated,
+                [(Coord { i: 1, j: 0 }, Color::Dark, Propagator::Merge)]
+            );
+            assert_eq!(outcome.contradiction, None);
+            assert_eq!(
+                outcome.deduction(Coord { i: 1, j: 0 }),
+                Deduction::Forced(Color::Dark)
+            );
+            assert_eq!(outcome.deduction(Coord { i: 1, j: 1 }), Deduction::Free);
+        }
+    }
+
+    #[test]
+    fn deduce_reports_contradictions() {
+        // The dark square needs all four squares, but one of them is light.
+        let mut grid = Grid::new(2, 2);
+        grid.add_rule(Rule::RegionAreaEqualsNumber);
+        grid.color_dark(0, 0);
+        grid.set_area_number(0, 0, 4);
+        grid.color_light(1, 1);
+        for solver in test_options().map(Solver::new) {
+            let outcome = solver.deduce(&grid);
+            assert_eq!(
+                outcome.contradiction,
+                Some(RuleRef::Rule {
+                    index: 0,
+                    clue: Some(Coord { i: 0, j: 0 })
+                })
+            );
+            assert!(outcome.unfillable.is_empty());
+            assert_eq!(
+                outcome.deduction(Coord { i: 0, j: 1 }),
+                Deduction::Undetermined
+            );
+        }
+    }
+}
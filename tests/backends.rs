@@ -0,0 +1,159 @@
+#[cfg(feature = "z3")]
+use std::env;
+
+#[cfg(feature = "z3")]
+use ioi::checker::check_grid;
+use ioi::corpus::{self, CorpusEntry};
+use ioi::grid::{Color, Coord, Grid};
+use ioi::pdata::Solution;
+#[cfg(feature = "z3")]
+use ioi::solver::{try_solve_grid, GridSolveResult};
+use ioi::{BackendKind, SolveOptions, Solver, Uniqueness};
+#[cfg(feature = "z3")]
+use ioi::{Deduction, SolveStatus};
+
+// Every backend must give the same answers. The puzzles come from tests/corpus.json, which covers
+// each kind of rule on a small grid, and from the decoded.json corpus at IOI_CORPUS if it is set.
+
+const BUNDLED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus.json");
+
+fn load(path: &str) -> Vec<CorpusEntry> {
+    let corpus = corpus::load_corpus(path).unwrap();
+    assert!(corpus.errors.is_empty(), "{}: {:?}", path, corpus.errors);
+    corpus
+        .entries
+        .into_iter()
+        .filter(|entry| entry.untranslatable.is_empty())
+        .collect()
+}
+
+#[cfg(feature = "z3")]
+fn entries() -> Vec<CorpusEntry> {
+    let mut entries = load(BUNDLED);
+    if let Ok(path) = env::var("IOI_CORPUS") {
+        entries.extend(load(&path));
+    }
+    entries
+}
+
+fn options(backend: BackendKind) -> SolveOptions {
+    SolveOptions {
+        timeout: 10,
+        backend,
+    }
+}
+
+type Coloring = Vec<(Coord, Option<Color>)>;
+
+fn colors(grid: &Grid) -> Coloring {
+    grid.squares()
+        .map(|(coord, square)| (coord, square.color))
+        .collect()
+}
+
+// What a uniqueness check found, with the solution if there is only one.
+fn summary(uniqueness: &Uniqueness) -> Option<(&'static str, Coloring)> {
+    match uniqueness {
+        Uniqueness::Unique(solution) => Some(("unique", colors(solution))),
+        Uniqueness::Multiple(..) => Some(("multiple", Vec::new())),
+        Uniqueness::Unsolvable => Some(("unsolvable", Vec::new())),
+        Uniqueness::Unknown => None,
+    }
+}
+
+#[cfg(feature = "z3")]
+#[test]
+fn backends_agree_on_the_corpus() {
+    let entries = entries();
+    assert!(!entries.is_empty());
+    for entry in &entries {
+        let mut statuses = Vec::new();
+        let mut summaries = Vec::new();
+        let mut deductions = Vec::new();
+        for &backend in BackendKind::ALL {
+            match try_solve_grid(&entry.grid, options(backend)) {
+                GridSolveResult::Solved(solution) => {
+                    assert!(
+                        check_grid(&solution).is_valid(),
+                        "{}: {:?} found an invalid solution",
+                        entry.pid,
+                        backend
+                    );
+                    statuses.push(SolveStatus::Solved);
+                }
+                result => statuses.push(result.status()),
+            }
+            summaries.extend(summary(
+                &Solver::new(options(backend)).check_uniqueness(&entry.grid),
+            ));
+            deductions.push(
+                Solver::new(options(backend))
+                    .deduce(&entry.grid)
+                    .deductions(),
+            );
+        }
+
+        let known = statuses
+            .iter()
+            .filter(|&&status| status != SolveStatus::Unknown)
+            .collect::<Vec<_>>();
+        assert!(
+            known.windows(2).all(|pair| pair[0] == pair[1]),
+            "{}: {:?}",
+            entry.pid,
+            statuses
+        );
+        assert!(
+            summaries.windows(2).all(|pair| pair[0] == pair[1]),
+            "{}: {:?}",
+            entry.pid,
+            summaries
+        );
+        for (i, &(coord, deduction)) in deductions[0].iter().enumerate() {
+            let found = deductions
+                .iter()
+                .map(|deductions| deductions[i])
+                .filter(|&(_, deduction)| deduction != Deduction::Undetermined)
+                .collect::<Vec<_>>();
+            assert!(
+                found.windows(2).all(|pair| pair[0] == pair[1]),
+                "{}: {:?} is {:?} with one backend but {:?} with another",
+                entry.pid,
+                coord,
+                deduction,
+                found
+            );
+        }
+    }
+}
+
+// The solutions in tests/corpus.json, where null means the puzzle has none. Blank squares of an
+// underconstrained solution can take either color.
+#[test]
+fn sat_matches_known_solutions() {
+    let solver = Solver::new(options(BackendKind::Sat));
+    for entry in load(BUNDLED) {
+        let uniqueness = solver.check_uniqueness(&entry.grid);
+        match &entry.solution {
+            Some(solution) => {
+                let expected = match solution {
+                    Solution::Full(_) => "unique",
+                    Solution::Underconstrained(_) => "multiple",
+                };
+                assert_eq!(
+                    summary(&uniqueness).map(|(found, _)| found),
+                    Some(expected),
+                    "{}",
+                    entry.pid
+                );
+                let mismatches = solver.deduce(&entry.grid).mismatches(solution);
+                assert!(mismatches.is_empty(), "{}: {:?}", entry.pid, mismatches);
+            }
+            None => assert!(
+                matches!(uniqueness, Uniqueness::Unsolvable),
+                "{} has a solution",
+                entry.pid
+            ),
+        }
+    }
+}